@@ -0,0 +1,234 @@
+// Insert-heavy, lookup-heavy and mixed workloads over the table's split
+// modes, the other backends and std's HashMap, for random, sequential and
+// adversarial keys. A plain timing harness rather than criterion, so it runs
+// with no dependencies; take the best of a few runs before trusting a
+// difference.
+//
+//     cargo bench --bench workloads [-- <filter>]
+//
+// The filter is matched against "workload/keys/backend".
+
+extern crate stupid_hash;
+
+use std::collections::HashMap;
+use std::env;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use stupid_hash::cuckoo::CuckooHash;
+use stupid_hash::hash::{Hash, SplitPolicy};
+use stupid_hash::hopscotch::HopscotchHash;
+use stupid_hash::inline::InlineHash;
+use stupid_hash::paged::PagedHash;
+
+const RUNS: usize = 5;
+
+// The operations every backend supports
+trait Table {
+    fn upsert(&mut self, name: &str, value: u64);
+    fn lookup(&self, name: &str) -> Option<u64>;
+    fn remove(&mut self, name: &str) -> Option<u64>;
+}
+
+impl Table for Hash<u64> {
+    fn upsert(&mut self, name: &str, value: u64) {
+        Hash::upsert(self, name, value);
+    }
+    fn lookup(&self, name: &str) -> Option<u64> {
+        Hash::lookup(self, name).cloned()
+    }
+    fn remove(&mut self, name: &str) -> Option<u64> {
+        Hash::remove(self, name)
+    }
+}
+
+impl Table for PagedHash<u64> {
+    fn upsert(&mut self, name: &str, value: u64) {
+        PagedHash::upsert(self, name, value);
+    }
+    fn lookup(&self, name: &str) -> Option<u64> {
+        PagedHash::lookup(self, name).cloned()
+    }
+    fn remove(&mut self, name: &str) -> Option<u64> {
+        PagedHash::remove(self, name)
+    }
+}
+
+impl Table for CuckooHash<u64> {
+    fn upsert(&mut self, name: &str, value: u64) {
+        CuckooHash::upsert(self, name, value);
+    }
+    fn lookup(&self, name: &str) -> Option<u64> {
+        CuckooHash::lookup(self, name).cloned()
+    }
+    fn remove(&mut self, name: &str) -> Option<u64> {
+        CuckooHash::remove(self, name)
+    }
+}
+
+impl Table for HopscotchHash<u64> {
+    fn upsert(&mut self, name: &str, value: u64) {
+        HopscotchHash::upsert(self, name, value);
+    }
+    fn lookup(&self, name: &str) -> Option<u64> {
+        HopscotchHash::lookup(self, name).cloned()
+    }
+    fn remove(&mut self, name: &str) -> Option<u64> {
+        HopscotchHash::remove(self, name)
+    }
+}
+
+impl Table for InlineHash<u64> {
+    fn upsert(&mut self, name: &str, value: u64) {
+        InlineHash::upsert(self, name, value);
+    }
+    fn lookup(&self, name: &str) -> Option<u64> {
+        InlineHash::lookup(self, name).cloned()
+    }
+    fn remove(&mut self, name: &str) -> Option<u64> {
+        InlineHash::remove(self, name)
+    }
+}
+
+impl Table for HashMap<String, u64> {
+    fn upsert(&mut self, name: &str, value: u64) {
+        self.insert(name.to_string(), value);
+    }
+    fn lookup(&self, name: &str) -> Option<u64> {
+        self.get(name).cloned()
+    }
+    fn remove(&mut self, name: &str) -> Option<u64> {
+        HashMap::remove(self, name)
+    }
+}
+
+type Backend = (&'static str, fn() -> Box<dyn Table>);
+
+fn backends() -> Vec<Backend> {
+    vec![
+        ("hash", || Box::new(Hash::new())),
+        ("hash-overflow", || {
+            Box::new(Hash::with_split_policy(SplitPolicy::BucketOverflow))
+        }),
+        ("hash-two-choice", || Box::new(Hash::with_two_choices())),
+        ("hash-incremental", || {
+            Box::new(Hash::with_incremental_splits())
+        }),
+        ("paged", || Box::new(PagedHash::new())),
+        ("cuckoo", || Box::new(CuckooHash::new())),
+        ("hopscotch", || Box::new(HopscotchHash::new())),
+        ("inline", || Box::new(InlineHash::new())),
+        ("std", || Box::new(HashMap::new())),
+    ]
+}
+
+// xorshift64*, so runs are repeatable
+fn random(n: usize) -> Vec<String> {
+    let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+    (0..n)
+        .map(|_| {
+            state ^= state >> 12;
+            state ^= state << 25;
+            state ^= state >> 27;
+            format!("{:x}", state.wrapping_mul(0x2545_f491_4f6c_dd1d))
+        })
+        .collect()
+}
+
+fn sequential(n: usize) -> Vec<String> {
+    (0..n).map(|i| format!("key{}", i)).collect()
+}
+
+// "Aa" and "BB" hash the same under h * 31 + byte, so every string made of
+// k such blocks has the same full hash: 2^k keys in one bucket
+fn adversarial(n: usize) -> Vec<String> {
+    let blocks = (usize::BITS - n.leading_zeros()) as usize;
+    (0..n)
+        .map(|i| {
+            (0..blocks)
+                .map(|b| if i >> b & 1 == 0 { "Aa" } else { "BB" })
+                .collect()
+        })
+        .collect()
+}
+
+fn insert_heavy(table: &mut dyn Table, keys: &[String]) {
+    for (i, key) in keys.iter().enumerate() {
+        table.upsert(key, i as u64);
+    }
+}
+
+fn lookup_heavy(table: &mut dyn Table, keys: &[String]) {
+    insert_heavy(table, keys);
+    for _ in 0..4 {
+        for key in keys {
+            black_box(table.lookup(key));
+        }
+    }
+}
+
+// Roughly 50% lookups, 30% upserts and 20% removals
+fn mixed(table: &mut dyn Table, keys: &[String]) {
+    for (i, key) in keys.iter().enumerate() {
+        match i % 10 {
+            0..=2 => table.upsert(key, i as u64),
+            3 | 4 => {
+                black_box(table.remove(&keys[i / 2]));
+            }
+            _ => {
+                black_box(table.lookup(&keys[i / 3]));
+            }
+        }
+    }
+}
+
+type Workload = (&'static str, fn(&mut dyn Table, &[String]));
+
+fn time(
+    make: fn() -> Box<dyn Table>,
+    workload: fn(&mut dyn Table, &[String]),
+    keys: &[String],
+) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let mut table = make();
+            let start = Instant::now();
+            workload(&mut *table, keys);
+            let elapsed = start.elapsed();
+            black_box(table);
+            elapsed
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    // `cargo bench` passes --bench; anything else is a filter
+    let filter = env::args().skip(1).find(|arg| !arg.starts_with("--"));
+
+    let workloads: [Workload; 3] = [
+        ("insert", insert_heavy),
+        ("lookup", lookup_heavy),
+        ("mixed", mixed),
+    ];
+    // Adversarial keys put everything in one bucket, so keep them few
+    let key_sets = [
+        ("random", random(100_000)),
+        ("sequential", sequential(100_000)),
+        ("adversarial", adversarial(2_000)),
+    ];
+
+    for &(workload_name, workload) in &workloads {
+        for (keys_name, keys) in &key_sets {
+            for (backend_name, make) in backends() {
+                let name = format!("{}/{}/{}", workload_name, keys_name, backend_name);
+                if filter.as_ref().is_some_and(|f| !name.contains(&f[..])) {
+                    continue;
+                }
+                let elapsed = time(make, workload, keys);
+                let per_key = elapsed.as_nanos() / keys.len() as u128;
+                println!("{:<40} {:>10.2?} {:>6} ns/key", name, elapsed, per_key);
+            }
+        }
+    }
+}
@@ -0,0 +1,15 @@
+// Runs arbitrary bytes as a script of upserts, lookups and removals, see
+// `testutil::run_script` for the format. Panics on any divergence from
+// std's HashMap as well as on panics inside the table.
+//
+//     cargo +nightly fuzz run ops
+
+#![no_main]
+
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate stupid_hash;
+
+fuzz_target!(|data: &[u8]| {
+    stupid_hash::testutil::run_script(data);
+});
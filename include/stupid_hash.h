@@ -0,0 +1,44 @@
+/* C interface to the stupid_hash crate. Build the library with
+ *
+ *     cargo rustc --lib --release --features ffi --crate-type cdylib
+ *
+ * or `--crate-type staticlib` to link it in statically. */
+
+#ifndef STUPID_HASH_H
+#define STUPID_HASH_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define STUPID_HASH_EXISTED 0
+#define STUPID_HASH_INSERTED 1
+#define STUPID_HASH_NOT_FOUND 0
+#define STUPID_HASH_FOUND 1
+#define STUPID_HASH_EINVAL (-1)
+
+/* Opaque table handle. Keys are byte strings of any content, UTF-8 or not,
+ * and may contain NULs; values are owned by the caller and never
+ * dereferenced by the table. STUPID_HASH_EINVAL means a null handle, or a
+ * null key with a nonzero length. */
+typedef struct stupid_hash stupid_hash;
+
+stupid_hash *stupid_hash_new(void);
+void stupid_hash_free(stupid_hash *hash);
+
+int stupid_hash_insert(stupid_hash *hash, const uint8_t *key, size_t key_len,
+                       void *value);
+int stupid_hash_lookup(const stupid_hash *hash, const uint8_t *key,
+                       size_t key_len, void **out);
+int stupid_hash_remove(stupid_hash *hash, const uint8_t *key, size_t key_len,
+                       void **out);
+size_t stupid_hash_len(const stupid_hash *hash);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* STUPID_HASH_H */
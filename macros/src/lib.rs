@@ -0,0 +1,261 @@
+// The compile-time half of stupid_hash::static_hash!. It gets the pairs
+// from that macro with the path to stupid_hash in front, runs the same
+// "hash and displace" seed search as perfect::PerfectHash::build, and
+// expands to a perfect::StaticHash literal with the seeds and the slots
+// filled in, so nothing is hashed or searched at run time.
+//
+// The hash and the layout below must stay exactly as perfect.rs computes
+// them at lookup time. They use u64 throughout so that a table built on a
+// 64-bit host still works on a 32-bit target.
+
+extern crate proc_macro;
+
+use proc_macro::{Delimiter, Group, Spacing, TokenStream, TokenTree};
+
+const GROUP_SEED: u64 = 0x9e37_79b9;
+const GROUP_SIZE: usize = 4;
+const MAX_SEED: u64 = 1 << 16;
+
+fn hash(name: &str, seed: u64) -> u64 {
+    let mut h = seed;
+    for p in name.bytes() {
+        h = h.wrapping_mul(31).wrapping_add(p as u64 ^ seed);
+    }
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    h ^= h >> 33;
+    h
+}
+
+fn group(name: &str, groups: usize) -> usize {
+    (hash(name, GROUP_SEED) % groups as u64) as usize
+}
+
+fn slot(name: &str, seed: u64, slots: usize) -> usize {
+    (hash(name, seed) % slots as u64) as usize
+}
+
+// Finds a seed per group so that all keys get distinct slots, or None
+fn search(names: &[&str], groups: usize, nslots: usize) -> Option<Vec<u64>> {
+    let mut members: Vec<Vec<&str>> = vec![Vec::new(); groups];
+    for name in names {
+        members[group(name, groups)].push(name);
+    }
+
+    // Place the biggest groups while the table is still mostly empty
+    let mut order: Vec<usize> = (0..groups).collect();
+    order.sort_by(|&a, &b| members[b].len().cmp(&members[a].len()));
+
+    let mut taken = vec![false; nslots];
+    let mut seeds = vec![0; groups];
+    let mut candidate = Vec::new();
+
+    for g in order {
+        if members[g].is_empty() {
+            break;
+        }
+
+        let found = (1..MAX_SEED).find(|&seed| {
+            candidate.clear();
+            for name in &members[g] {
+                let s = slot(name, seed, nslots);
+                if taken[s] || candidate.contains(&s) {
+                    return false;
+                }
+                candidate.push(s);
+            }
+            true
+        })?;
+
+        for &s in &candidate {
+            taken[s] = true;
+        }
+        seeds[g] = found;
+    }
+
+    Some(seeds)
+}
+
+// The value of a string literal's source text, or None if it isn't one
+fn unquote(literal: &str) -> Option<String> {
+    if let Some(raw) = literal.strip_prefix('r') {
+        let hashes = &raw[..raw.len() - raw.trim_start_matches('#').len()];
+        let body = raw[hashes.len()..].strip_prefix('"')?;
+        return body.strip_suffix(hashes)?.strip_suffix('"').map(str::to_string);
+    }
+
+    let body = literal.strip_prefix('"')?.strip_suffix('"')?;
+    let mut value = String::new();
+    let mut chars = body.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            value.push(c);
+            continue;
+        }
+        let escaped = match chars.next()? {
+            'n' => '\n',
+            'r' => '\r',
+            't' => '\t',
+            '0' => '\0',
+            c @ ('\\' | '\'' | '"') => c,
+            'x' => {
+                let hex: String = chars.by_ref().take(2).collect();
+                u8::from_str_radix(&hex, 16).ok().filter(u8::is_ascii)? as char
+            }
+            'u' => {
+                if chars.next()? != '{' {
+                    return None;
+                }
+                let hex: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                std::char::from_u32(u32::from_str_radix(&hex.replace('_', ""), 16).ok()?)?
+            }
+            // A line continuation skips the newline and the indentation
+            '\n' => {
+                while chars.peek().is_some_and(|c| c.is_whitespace()) {
+                    chars.next();
+                }
+                continue;
+            }
+            _ => return None,
+        };
+        value.push(escaped);
+    }
+    Some(value)
+}
+
+fn tokens(source: &str) -> TokenStream {
+    source.parse().unwrap()
+}
+
+fn delimited(delimiter: Delimiter, stream: TokenStream) -> Option<TokenTree> {
+    Some(TokenTree::Group(Group::new(delimiter, stream)))
+}
+
+fn is_punct(token: &TokenTree, c: char) -> bool {
+    match *token {
+        TokenTree::Punct(ref p) => p.as_char() == c,
+        _ => false,
+    }
+}
+
+struct Entry {
+    // The literal as written, so the expansion keeps its span
+    literal: TokenTree,
+    key: String,
+    value: TokenStream,
+}
+
+// Splits `"key" => value, ...` into entries. A value runs to the next comma
+// outside brackets, so one with a comma of its own (a generic type, say)
+// needs parentheses around it.
+fn parse_entries(input: &[TokenTree]) -> Result<Vec<Entry>, String> {
+    let mut entries = Vec::new();
+    for part in input.split(|t| is_punct(t, ',')) {
+        if part.is_empty() {
+            continue;
+        }
+        let key = match part[0] {
+            TokenTree::Literal(ref lit) => unquote(&lit.to_string()),
+            _ => None,
+        };
+        let key = match key {
+            Some(key) => key,
+            None => return Err(format!("expected a string literal key, found `{}`", part[0])),
+        };
+        let arrow = match part.get(1) {
+            Some(TokenTree::Punct(p)) => p.as_char() == '=' && p.spacing() == Spacing::Joint,
+            _ => false,
+        };
+        if !arrow || !part.get(2).is_some_and(|t| is_punct(t, '>')) {
+            return Err(format!("expected `=>` after {}", part[0]));
+        }
+        if part.len() == 3 {
+            return Err(format!("missing value for {}", part[0]));
+        }
+        entries.push(Entry {
+            literal: part[0].clone(),
+            key,
+            value: part[3..].iter().cloned().collect(),
+        });
+    }
+    Ok(entries)
+}
+
+fn expand(input: TokenStream) -> Result<TokenStream, String> {
+    let input: Vec<TokenTree> = input.into_iter().collect();
+    // static_hash! passes its $crate first, then a `;`
+    let semi = match input.iter().position(|t| is_punct(t, ';')) {
+        Some(i) => i,
+        None => return Err("use stupid_hash::static_hash! rather than this macro".to_string()),
+    };
+    let krate = &input[..semi];
+    let mut entries = parse_entries(&input[semi + 1..])?;
+
+    entries.sort_by(|a, b| a.key.cmp(&b.key));
+    for pair in entries.windows(2) {
+        if pair[0].key == pair[1].key {
+            return Err(format!("duplicate key {}", pair[1].literal));
+        }
+    }
+
+    let count = entries.len();
+    let groups = (count / GROUP_SIZE).max(1);
+    let names: Vec<&str> = entries.iter().map(|e| &e.key[..]).collect();
+
+    // Start minimal (one slot per key) and loosen up if the search fails
+    let mut nslots = count.max(1);
+    let seeds = loop {
+        if let Some(seeds) = search(&names, groups, nslots) {
+            break seeds;
+        }
+        if nslots > 2 * count.max(1) {
+            return Err("no collision-free layout found for these keys".to_string());
+        }
+        nslots += nslots / 10 + 1;
+    };
+
+    let mut slots: Vec<Option<Entry>> = (0..nslots).map(|_| None).collect();
+    for entry in entries {
+        let s = slot(&entry.key, seeds[group(&entry.key, groups)], nslots);
+        slots[s] = Some(entry);
+    }
+
+    let seed_list: Vec<String> = seeds.iter().map(u64::to_string).collect();
+    let mut slot_list = TokenStream::new();
+    for entry in slots {
+        match entry {
+            None => slot_list.extend(tokens("::std::option::Option::None,")),
+            Some(entry) => {
+                let mut pair: TokenStream = Some(entry.literal).into_iter().collect();
+                pair.extend(tokens(","));
+                pair.extend(delimited(Delimiter::Parenthesis, entry.value));
+                let pair = delimited(Delimiter::Parenthesis, pair).into_iter().collect();
+
+                slot_list.extend(tokens("::std::option::Option::Some"));
+                slot_list.extend(delimited(Delimiter::Parenthesis, pair));
+                slot_list.extend(tokens(","));
+            }
+        }
+    }
+
+    let mut args = tokens("&");
+    args.extend(delimited(Delimiter::Bracket, tokens(&seed_list.join(","))));
+    args.extend(tokens(", &"));
+    args.extend(delimited(Delimiter::Bracket, slot_list));
+    args.extend(tokens(&format!(", {}", count)));
+
+    let mut out: TokenStream = krate.iter().cloned().collect();
+    out.extend(tokens("::perfect::StaticHash::from_parts"));
+    out.extend(delimited(Delimiter::Parenthesis, args));
+    Ok(out)
+}
+
+#[proc_macro]
+pub fn static_hash_impl(input: TokenStream) -> TokenStream {
+    match expand(input) {
+        Ok(tokens) => tokens,
+        Err(message) => tokens(&format!("compile_error!({:?})", message)),
+    }
+}
@@ -0,0 +1,360 @@
+// A variant of `Hash` whose bucket vectors and key bytes all live in a
+// caller-provided allocator (an arena, a shared-memory region, ...). Needs a
+// nightly compiler, since `Allocator` and `Vec::new_in` are still unstable.
+// `Arena` is a bump allocator for short-lived tables.
+//
+// This is a separate table rather than an allocator parameter on `Hash`
+// because `Hash` has to build on stable, where there is no `Allocator` to be
+// generic over, and because it hands keys out as `String`s in the global
+// heap. It only does lookup, upsert and remove, splitting the way
+// SplitPolicy::BucketOverflow does. It has no key length limit, never
+// merges buckets, and has none of `Hash`'s other methods. Anything beyond
+// the basics belongs in `Hash`.
+
+use std::alloc::{AllocError, Allocator, Global, Layout};
+use std::cell::{Cell, RefCell};
+use std::mem;
+use std::ptr::NonNull;
+use std::str;
+
+use hash::{bucket_index, hash_str, next_split};
+
+const NHASH: usize = 32;
+
+struct AllocNameVal<T, A: Allocator> {
+    name: Vec<u8, A>,
+    value: T,
+}
+
+pub struct AllocHash<T, A: Allocator + Clone = Global> {
+    table: Vec<Vec<AllocNameVal<T, A>, A>, A>,
+    bits: usize,
+    split_bucket: usize,
+    count: usize,
+    alloc: A,
+}
+
+impl<T> AllocHash<T> {
+    pub fn new() -> Self {
+        AllocHash::new_in(Global)
+    }
+}
+
+impl<T> Default for AllocHash<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, A> AllocHash<T, A>
+where
+    A: Allocator + Clone,
+{
+    pub fn new_in(alloc: A) -> Self {
+        let mut hash_vec = Vec::with_capacity_in(NHASH, alloc.clone());
+
+        for _i in 0..NHASH {
+            hash_vec.push(Vec::new_in(alloc.clone()));
+        }
+
+        AllocHash {
+            table: hash_vec,
+            bits: 5, // log_2(32)
+            split_bucket: 0,
+            count: 0,
+            alloc,
+        }
+    }
+
+    pub fn allocator(&self) -> &A {
+        &self.alloc
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn bucket_count(&self) -> usize {
+        self.table.len()
+    }
+
+    fn hash(&self, name: &str) -> usize {
+        bucket_index(hash_str(name), self.bits, self.table.len())
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<&T> {
+        let h = self.hash(name);
+
+        self.table[h]
+            .iter()
+            .find(|entry| &entry.name[..] == name.as_bytes())
+            .map(|entry| &entry.value)
+    }
+
+    // Returns true if the insert was a new key,
+    // False if we overwrote a key
+    pub fn upsert(&mut self, name: &str, value: T) -> bool {
+        let h = self.hash(name);
+
+        for entry in self.table[h].iter_mut() {
+            if &entry.name[..] == name.as_bytes() {
+                entry.value = value;
+                return false;
+            }
+        }
+
+        let mut key = Vec::with_capacity_in(name.len(), self.alloc.clone());
+        key.extend_from_slice(name.as_bytes());
+        self.insert_entry(h, AllocNameVal { name: key, value });
+
+        true
+    }
+
+    fn insert_entry(&mut self, h: usize, entry: AllocNameVal<T, A>) {
+        let entry_count = self.table[h].len();
+
+        self.table[h].push(entry);
+        self.count += 1;
+
+        if entry_count + 1 > (1 << self.bits) {
+            self.split();
+        }
+    }
+
+    fn split(&mut self) {
+        let empty = Vec::new_in(self.alloc.clone());
+        let orig_bucket = mem::replace(&mut self.table[self.split_bucket], empty);
+        self.table.push(Vec::new_in(self.alloc.clone()));
+        self.count -= orig_bucket.len();
+
+        let (bits, split_bucket) = next_split(self.bits, self.split_bucket, self.table.len());
+        self.bits = bits;
+        self.split_bucket = split_bucket;
+
+        // Keys were valid UTF-8 on the way in, so there is nothing to clone
+        // here; the entries just move to their new bucket.
+        for entry in orig_bucket {
+            let h = self.hash(str::from_utf8(&entry.name).unwrap());
+            self.insert_entry(h, entry);
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<T> {
+        let h = self.hash(name);
+        let entries = &mut self.table[h];
+        let i = entries
+            .iter()
+            .position(|entry| &entry.name[..] == name.as_bytes())?;
+
+        self.count -= 1;
+        Some(entries.swap_remove(i).value)
+    }
+
+    // Drops the values but not the table's own memory, for allocators that
+    // free everything at once, like Arena::reset(). Skips walking the table
+    // entirely when the values don't need dropping. With an allocator that
+    // frees piecemeal this leaks the table.
+    pub fn discard(mut self) {
+        if mem::needs_drop::<T>() {
+            for bucket in self.table.iter_mut() {
+                for entry in bucket.drain(..) {
+                    mem::forget(entry.name);
+                    drop(entry.value);
+                }
+            }
+        }
+        mem::forget(self);
+    }
+}
+
+const ARENA_CHUNK: usize = 64 * 1024;
+
+// A bump allocator: allocations are carved off the end of the current
+// chunk and never freed one by one. reset() gives everything back at once,
+// so a request-scoped table costs one reset instead of a free per bucket
+// and key. Allocate from it through a shared reference:
+// `AllocHash::new_in(&arena)`.
+pub struct Arena {
+    // Every chunk with the layout it was allocated with; the last one is
+    // the one being bumped through
+    chunks: RefCell<Vec<(NonNull<u8>, Layout)>>,
+    next: Cell<usize>,
+    end: Cell<usize>,
+    allocated: Cell<usize>,
+}
+
+impl Arena {
+    pub fn new() -> Self {
+        Arena {
+            chunks: RefCell::new(Vec::new()),
+            next: Cell::new(0),
+            end: Cell::new(0),
+            allocated: Cell::new(0),
+        }
+    }
+
+    // Bytes handed out since the last reset
+    pub fn allocated(&self) -> usize {
+        self.allocated.get()
+    }
+
+    // Bytes of memory the arena holds on to
+    pub fn capacity(&self) -> usize {
+        self.chunks.borrow().iter().map(|&(_, layout)| layout.size()).sum()
+    }
+
+    // Frees every allocation at once. Taking `&mut self` means nothing
+    // allocated from the arena can still be around. The largest chunk is
+    // kept for reuse.
+    pub fn reset(&mut self) {
+        let chunks = self.chunks.get_mut();
+        if let Some(largest) = (0..chunks.len()).max_by_key(|&i| chunks[i].1.size()) {
+            // The kept chunk goes last, since that is where bumping happens
+            let keep = chunks.swap_remove(largest);
+            for (ptr, layout) in chunks.drain(..) {
+                // Safety: allocated by Global with this layout in allocate()
+                unsafe { Global.deallocate(ptr, layout) };
+            }
+            chunks.push(keep);
+            self.next.set(keep.0.as_ptr() as usize);
+            self.end.set(keep.0.as_ptr() as usize + keep.1.size());
+        }
+        self.allocated.set(0);
+    }
+
+    fn bump(&self, layout: Layout) -> Option<NonNull<[u8]>> {
+        let start = self.next.get().checked_next_multiple_of(layout.align())?;
+        let end = start.checked_add(layout.size())?;
+        if end > self.end.get() {
+            return None;
+        }
+        self.next.set(end);
+        self.allocated.set(self.allocated.get() + layout.size());
+
+        // Derived from the chunk's pointer rather than cast from the address
+        let (base, _) = *self.chunks.borrow().last()?;
+        let ptr = NonNull::new(base.as_ptr().wrapping_add(start - base.as_ptr() as usize))?;
+        Some(NonNull::slice_from_raw_parts(ptr, layout.size()))
+    }
+}
+
+impl Default for Arena {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+unsafe impl Allocator for &Arena {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        if let Some(ptr) = self.bump(layout) {
+            return Ok(ptr);
+        }
+
+        let size = ARENA_CHUNK.max(layout.size() + layout.align());
+        let chunk = Layout::from_size_align(size, layout.align().max(16));
+        let chunk = chunk.map_err(|_| AllocError)?;
+        let ptr = Global.allocate(chunk)?.cast::<u8>();
+        self.chunks.borrow_mut().push((ptr, chunk));
+        self.next.set(ptr.as_ptr() as usize);
+        self.end.set(ptr.as_ptr() as usize + size);
+        self.bump(layout).ok_or(AllocError)
+    }
+
+    // Memory only comes back on reset()
+    unsafe fn deallocate(&self, _ptr: NonNull<u8>, _layout: Layout) {}
+}
+
+impl Drop for Arena {
+    fn drop(&mut self) {
+        for (ptr, layout) in self.chunks.get_mut().drain(..) {
+            // Safety: allocated by Global with this layout in allocate()
+            unsafe { Global.deallocate(ptr, layout) };
+        }
+    }
+}
+
+#[cfg(test)]
+#[derive(Clone)]
+struct Counting<'a> {
+    live: &'a Cell<isize>,
+}
+
+#[cfg(test)]
+unsafe impl<'a> Allocator for Counting<'a> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.live.set(self.live.get() + 1);
+        Global.allocate(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.live.set(self.live.get() - 1);
+        unsafe { Global.deallocate(ptr, layout) }
+    }
+}
+
+#[test]
+fn test_alloc_basics() {
+    let mut hashtab = AllocHash::new();
+    assert_eq!(hashtab.lookup("abc"), None);
+    assert!(hashtab.upsert("abc", 64));
+    assert!(!hashtab.upsert("abc", 256));
+    assert_eq!(hashtab.lookup("abc"), Some(&256));
+    assert_eq!(hashtab.remove("abc"), Some(256));
+    assert_eq!(hashtab.lookup("abc"), None);
+    assert!(hashtab.is_empty());
+}
+
+#[test]
+fn test_alloc_uses_allocator() {
+    let live = Cell::new(0);
+
+    {
+        let mut hashtab = AllocHash::new_in(Counting { live: &live });
+        for i in 0..4096 {
+            hashtab.upsert(&i.to_string(), i);
+        }
+        assert!(hashtab.bucket_count() > NHASH);
+        // One allocation for the table, one per bucket, one per key
+        assert!(live.get() >= (hashtab.bucket_count() + 4096) as isize);
+        assert_eq!(hashtab.len(), 4096);
+    }
+
+    assert_eq!(live.get(), 0);
+}
+
+#[test]
+fn test_arena() {
+    use std::rc::Rc;
+
+    let mut arena = Arena::new();
+    let value = Rc::new(());
+    {
+        let mut hashtab = AllocHash::new_in(&arena);
+        for i in 0..4096 {
+            hashtab.upsert(&i.to_string(), value.clone());
+        }
+        for i in 0..4096 {
+            assert!(hashtab.lookup(&i.to_string()).is_some());
+        }
+        assert_eq!(Rc::strong_count(&value), 4097);
+        hashtab.discard();
+    }
+    // The values were dropped, their memory wasn't
+    assert_eq!(Rc::strong_count(&value), 1);
+    assert!(arena.allocated() > 4096);
+    let capacity = arena.capacity();
+
+    arena.reset();
+    assert_eq!(arena.allocated(), 0);
+    assert!(arena.capacity() <= capacity);
+
+    // The kept chunk is reused
+    let mut again = AllocHash::new_in(&arena);
+    again.upsert("a", 1);
+    assert_eq!(again.lookup("a"), Some(&1));
+    assert!(arena.allocated() > 0);
+}
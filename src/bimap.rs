@@ -0,0 +1,113 @@
+// A bidirectional map: keys are unique, and so are values. Keys go through
+// the crate's `Hash`; the reverse direction uses a std `HashMap`, since
+// values are arbitrary `Hash + Eq` types rather than strings.
+
+use std::collections::HashMap;
+use std::hash::Hash as StdHash;
+
+use hash::Hash;
+
+pub struct BiHash<T: Clone + StdHash + Eq> {
+    by_key: Hash<T>,
+    by_value: HashMap<T, String>,
+}
+
+impl<T> BiHash<T>
+where
+    T: Clone + StdHash + Eq,
+{
+    pub fn new() -> Self {
+        BiHash {
+            by_key: Hash::new(),
+            by_value: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_key.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_key.is_empty()
+    }
+
+    // Pairs `name` with `value`. To keep both sides unique, any existing
+    // pair using either one is removed first; returns the value that was
+    // paired with `name` and the key that was paired with `value`, if any.
+    pub fn insert(&mut self, name: &str, value: T) -> (Option<T>, Option<String>) {
+        // Re-inserting the same pair changes nothing
+        if self.by_key.lookup(name) == Some(&value) {
+            return (Some(value), Some(name.to_string()));
+        }
+
+        let old_value = self.remove_by_key(name);
+        let old_key = self.remove_by_value(&value);
+
+        self.by_value.insert(value.clone(), name.to_string());
+        self.by_key.upsert(name, value);
+
+        (old_value, old_key)
+    }
+
+    pub fn get_by_key(&self, name: &str) -> Option<&T> {
+        self.by_key.lookup(name)
+    }
+
+    pub fn get_by_value(&self, value: &T) -> Option<&str> {
+        self.by_value.get(value).map(|name| &name[..])
+    }
+
+    pub fn remove_by_key(&mut self, name: &str) -> Option<T> {
+        let value = self.by_key.remove(name)?;
+        self.by_value.remove(&value);
+        Some(value)
+    }
+
+    pub fn remove_by_value(&mut self, value: &T) -> Option<String> {
+        let name = self.by_value.remove(value)?;
+        self.by_key.remove(&name);
+        Some(name)
+    }
+}
+
+impl<T> Default for BiHash<T>
+where
+    T: Clone + StdHash + Eq,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[test]
+fn test_bimap_basics() {
+    let mut bimap = BiHash::new();
+    assert_eq!(bimap.insert("one", 1), (None, None));
+    assert_eq!(bimap.insert("two", 2), (None, None));
+
+    assert_eq!(bimap.get_by_key("one"), Some(&1));
+    assert_eq!(bimap.get_by_value(&2), Some("two"));
+
+    assert_eq!(bimap.remove_by_value(&1), Some("one".to_string()));
+    assert_eq!(bimap.get_by_key("one"), None);
+    assert_eq!(bimap.remove_by_key("two"), Some(2));
+    assert_eq!(bimap.get_by_value(&2), None);
+    assert!(bimap.is_empty());
+}
+
+#[test]
+fn test_bimap_overwrite() {
+    let mut bimap = BiHash::new();
+    bimap.insert("a", 1);
+    bimap.insert("b", 2);
+
+    // "a" takes 2 away from "b" and drops its own old value
+    assert_eq!(bimap.insert("a", 2), (Some(1), Some("b".to_string())));
+    assert_eq!(bimap.len(), 1);
+    assert_eq!(bimap.get_by_key("b"), None);
+    assert_eq!(bimap.get_by_value(&1), None);
+    assert_eq!(bimap.get_by_value(&2), Some("a"));
+
+    assert_eq!(bimap.insert("a", 2), (Some(2), Some("a".to_string())));
+    assert_eq!(bimap.len(), 1);
+}
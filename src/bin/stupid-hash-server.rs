@@ -0,0 +1,62 @@
+// A minimal cache daemon serving one shared table over the network.
+// Run with `cargo run --features server --bin stupid-hash-server -- [options]`.
+
+extern crate stupid_hash;
+
+use std::env;
+use std::process;
+use std::sync::Arc;
+use std::thread;
+
+use stupid_hash::server::{self, http, memcached, resp, Handler, Store};
+
+const USAGE: &str = "\
+usage: stupid-hash-server [--memcached ADDR] [--resp ADDR] [--http ADDR]
+
+Serves one table over each requested protocol. With no options, speaks the
+memcached protocol on 127.0.0.1:11211.";
+
+fn main() {
+    let mut listeners: Vec<(String, Handler)> = Vec::new();
+    let mut args = env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        let handler: Handler = match &arg[..] {
+            "--memcached" => memcached::serve,
+            "--resp" => resp::serve,
+            "--http" => http::serve,
+            _ => {
+                eprintln!("{}", USAGE);
+                process::exit(2);
+            }
+        };
+        match args.next() {
+            Some(addr) => listeners.push((addr, handler)),
+            None => {
+                eprintln!("{}", USAGE);
+                process::exit(2);
+            }
+        }
+    }
+    if listeners.is_empty() {
+        listeners.push(("127.0.0.1:11211".to_string(), memcached::serve));
+    }
+
+    let store = Arc::new(Store::new());
+    let threads: Vec<_> = listeners
+        .into_iter()
+        .map(|(addr, handler)| {
+            let store = store.clone();
+            thread::spawn(move || {
+                if let Err(e) = server::listen(&addr[..], store, handler) {
+                    eprintln!("error: {}: {}", addr, e);
+                    process::exit(1);
+                }
+            })
+        })
+        .collect();
+
+    for thread in threads {
+        let _ = thread.join();
+    }
+}
@@ -0,0 +1,292 @@
+// Interactive shell over a single `Hash<String>`, handy for poking at how the
+// table splits. Run with `cargo run --features cli -- [snapshot-file]`.
+
+extern crate stupid_hash;
+
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::str;
+
+use stupid_hash::hash::Hash;
+
+const HELP: &str = "\
+commands:
+  set <key> <value>   insert or overwrite a key
+  get <key>           print the value for a key
+  del <key>           remove a key
+  stats               show the table layout
+  dump                print every entry, bucket by bucket
+  save [file]         write a snapshot
+  load [file]         replace the table with a snapshot
+  help                show this message
+  quit                leave";
+
+struct Repl {
+    hash: Hash<String>,
+    snapshot: Option<String>,
+}
+
+// Snapshots are one `key<TAB>value` line per entry, with backslashes and
+// control characters escaped so any string survives the round trip. The
+// shell prints keys and values escaped the same way, so what it shows can't
+// move the cursor or garble the terminal.
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\t' => out.push_str("\\t"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            c if c.is_control() => out.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+// The char of a `\u{...}` escape, with the `\u` already read
+fn unescape_unicode(chars: &mut str::Chars) -> Option<char> {
+    if chars.next() != Some('{') {
+        return None;
+    }
+    let hex: String = chars.by_ref().take_while(|&c| c != '}').collect();
+    char::from_u32(u32::from_str_radix(&hex, 16).ok()?)
+}
+
+fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => out.push('\t'),
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('u') => out.extend(unescape_unicode(&mut chars)),
+            Some(c) => out.push(c),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+fn save(hash: &Hash<String>, path: &str) -> io::Result<()> {
+    let mut w = BufWriter::new(File::create(path)?);
+    for (name, value) in hash.iter() {
+        writeln!(w, "{}\t{}", escape(name), escape(value))?;
+    }
+    w.flush()
+}
+
+fn load(path: &str) -> io::Result<Hash<String>> {
+    let mut hash = Hash::new();
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        let mut parts = line.splitn(2, '\t');
+        let name = parts.next().unwrap_or("");
+        let value = parts.next().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "snapshot line without a value")
+        })?;
+        hash.upsert(&unescape(name), unescape(value));
+    }
+    Ok(hash)
+}
+
+impl Repl {
+    // Runs one command line. Returns false once the user asks to quit.
+    fn execute<W: Write>(&mut self, line: &str, out: &mut W) -> io::Result<bool> {
+        let line = line.trim();
+        let (cmd, rest) = match line.find(char::is_whitespace) {
+            Some(i) => (&line[..i], line[i..].trim_start()),
+            None => (line, ""),
+        };
+
+        match cmd {
+            "" => {}
+            "set" => {
+                let (name, value) = match rest.find(char::is_whitespace) {
+                    Some(i) => (&rest[..i], rest[i..].trim_start()),
+                    None => {
+                        writeln!(out, "usage: set <key> <value>")?;
+                        return Ok(true);
+                    }
+                };
+                let new = self.hash.upsert(name, value.to_string());
+                writeln!(out, "{}", if new { "inserted" } else { "updated" })?;
+            }
+            "get" => match self.hash.lookup(rest) {
+                Some(value) => writeln!(out, "{}", escape(value))?,
+                None => writeln!(out, "(not found)")?,
+            },
+            "del" => match self.hash.remove(rest) {
+                Some(_) => writeln!(out, "deleted")?,
+                None => writeln!(out, "(not found)")?,
+            },
+            "stats" => {
+                let stats = self.hash.stats();
+                writeln!(out, "entries:        {}", stats.entries)?;
+                writeln!(out, "buckets:        {}", stats.buckets)?;
+                writeln!(out, "bits:           {}", stats.bits)?;
+                writeln!(out, "split bucket:   {}", stats.split_bucket)?;
+                writeln!(out, "empty buckets:  {}", stats.empty_buckets)?;
+                writeln!(out, "longest bucket: {}", stats.longest_bucket)?;
+            }
+            "dump" => {
+                let stats = self.hash.stats();
+                writeln!(
+                    out,
+                    "{} entries in {} buckets, {} bits, next split at bucket {}",
+                    stats.entries, stats.buckets, stats.bits, stats.split_bucket
+                )?;
+                for (index, bucket) in self.hash.buckets() {
+                    match bucket.len() {
+                        0 => writeln!(out, "bucket {}: empty", index)?,
+                        1 => writeln!(out, "bucket {}: 1 entry", index)?,
+                        n => writeln!(out, "bucket {}: {} entries", index, n)?,
+                    }
+                    for entry in bucket {
+                        writeln!(out, "  {} = {}", escape(entry.name()), escape(entry.value()))?;
+                    }
+                }
+            }
+            "save" | "load" => {
+                let path = if rest.is_empty() {
+                    match self.snapshot {
+                        Some(ref path) => path.clone(),
+                        None => {
+                            writeln!(out, "no snapshot file given")?;
+                            return Ok(true);
+                        }
+                    }
+                } else {
+                    rest.to_string()
+                };
+
+                let result = if cmd == "save" {
+                    save(&self.hash, &path)
+                } else {
+                    load(&path).map(|hash| self.hash = hash)
+                };
+                match result {
+                    Ok(()) => writeln!(out, "{}d {}", cmd, path)?,
+                    Err(e) => writeln!(out, "error: {}: {}", path, e)?,
+                }
+            }
+            "help" => writeln!(out, "{}", HELP)?,
+            "quit" | "exit" => return Ok(false),
+            _ => writeln!(out, "unknown command `{}`, try `help`", cmd)?,
+        }
+
+        Ok(true)
+    }
+}
+
+fn main() {
+    let snapshot = env::args().nth(1);
+    let hash = match snapshot {
+        Some(ref path) => match load(path) {
+            Ok(hash) => hash,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Hash::new(),
+            Err(e) => {
+                eprintln!("error: {}: {}", path, e);
+                std::process::exit(1);
+            }
+        },
+        None => Hash::new(),
+    };
+    let mut repl = Repl { hash, snapshot };
+
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let mut lines = stdin.lock().lines();
+
+    loop {
+        let _ = write!(out, "> ");
+        let _ = out.flush();
+
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            _ => break,
+        };
+        match repl.execute(&line, &mut out) {
+            Ok(true) => {}
+            _ => break,
+        }
+    }
+}
+
+#[cfg(test)]
+fn run(repl: &mut Repl, line: &str) -> String {
+    let mut out = Vec::new();
+    repl.execute(line, &mut out).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn test_commands() {
+    let mut repl = Repl {
+        hash: Hash::new(),
+        snapshot: None,
+    };
+
+    assert_eq!(run(&mut repl, "set greeting hello world"), "inserted\n");
+    assert_eq!(run(&mut repl, "set greeting hi"), "updated\n");
+    assert_eq!(run(&mut repl, "get greeting"), "hi\n");
+    assert!(run(&mut repl, "stats").starts_with("entries:        1\n"));
+    let dump = run(&mut repl, "dump");
+    let bucket = repl.hash.bucket_index_for("greeting");
+    assert!(dump.starts_with("1 entries in 32 buckets, 5 bits, next split at bucket 0\n"));
+    assert!(dump.contains(&format!("bucket {}: 1 entry\n  greeting = hi\n", bucket)));
+    assert!(dump.contains("bucket 31: empty\n") || bucket == 31);
+    assert_eq!(dump.lines().count(), 34);
+
+    // Nothing printed can reach the terminal as a control character
+    assert_eq!(run(&mut repl, "set cr a\rb\x1b[2Jc"), "inserted\n");
+    assert_eq!(run(&mut repl, "get cr"), "a\\rb\\u{1b}[2Jc\n");
+    assert!(run(&mut repl, "dump").contains("  cr = a\\rb\\u{1b}[2Jc\n"));
+    run(&mut repl, "del cr");
+    assert_eq!(run(&mut repl, "del greeting"), "deleted\n");
+    assert_eq!(run(&mut repl, "get greeting"), "(not found)\n");
+    assert_eq!(run(&mut repl, "save"), "no snapshot file given\n");
+    assert!(!repl.execute("quit", &mut Vec::new()).unwrap());
+}
+
+#[test]
+fn test_snapshot_roundtrip() {
+    // Removes the file however the test ends
+    struct TempFile(std::path::PathBuf);
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    // Named per process, so concurrent test runs don't share it
+    let name = format!("stupid-hash-cli-test-{}.snapshot", std::process::id());
+    let file = TempFile(env::temp_dir().join(name));
+    let path = file.0.to_str().unwrap();
+
+    let mut hash = Hash::new();
+    hash.upsert("plain", "value".to_string());
+    hash.upsert("tab\tkey", "multi\nline \\ value".to_string());
+    hash.upsert("crlf\r\n", "bell\x07 del\x7f \\u{41}".to_string());
+    save(&hash, path).unwrap();
+
+    let loaded = load(path).unwrap();
+    assert_eq!(loaded.len(), 3);
+    assert_eq!(
+        loaded.lookup("crlf\r\n").map(|s| &s[..]),
+        Some("bell\x07 del\x7f \\u{41}")
+    );
+    assert_eq!(loaded.lookup("plain").map(|s| &s[..]), Some("value"));
+    assert_eq!(
+        loaded.lookup("tab\tkey").map(|s| &s[..]),
+        Some("multi\nline \\ value")
+    );
+}
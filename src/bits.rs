@@ -0,0 +1,137 @@
+// Formatting and parsing helpers for looking at hashes and masks, mostly
+// for debugging how keys map onto buckets. Formatted numbers are zero-padded
+// to the full width of a usize unless a width is given.
+
+use std::fmt;
+use std::num::ParseIntError;
+
+const WIDTH: usize = usize::BITS as usize;
+
+// `value` zero-padded to `digits` digits in the given radix
+fn padded(value: usize, radix: u32, digits: usize) -> String {
+    let s = match radix {
+        2 => format!("{:b}", value),
+        8 => format!("{:o}", value),
+        _ => format!("{:x}", value),
+    };
+    format!("{:0>width$}", s, width = digits)
+}
+
+pub fn binary(value: usize) -> String {
+    padded(value, 2, WIDTH)
+}
+
+pub fn octal(value: usize) -> String {
+    padded(value, 8, WIDTH.div_ceil(3))
+}
+
+pub fn hex(value: usize) -> String {
+    padded(value, 16, WIDTH / 4)
+}
+
+// The low `width` bits of `value` in binary, e.g. a hash masked down to the
+// bits the table currently addresses buckets with
+pub fn low_bits(value: usize, width: usize) -> String {
+    let width = width.min(WIDTH);
+    let mask = if width == WIDTH { !0 } else { (1 << width) - 1 };
+    padded(value & mask, 2, width)
+}
+
+// Splits `digits` into groups of `size` counted from the right, so
+// "11010110" grouped by 4 is "1101_0110" with '_'
+pub fn group(digits: &str, size: usize, separator: char) -> String {
+    assert!(size > 0, "group size must be positive");
+
+    let chars: Vec<char> = digits.chars().collect();
+    let mut grouped = String::with_capacity(chars.len() + chars.len() / size);
+    for (i, c) in chars.iter().enumerate() {
+        if i > 0 && (chars.len() - i).is_multiple_of(size) {
+            grouped.push(separator);
+        }
+        grouped.push(*c);
+    }
+    grouped
+}
+
+// Parses the output of the functions above back. A 0b/0o/0x prefix and
+// '_' or ' ' separators are allowed.
+fn parse(s: &str, radix: u32, prefix: &str) -> Result<usize, ParseIntError> {
+    let s = s.trim();
+    let s = s.strip_prefix(prefix).unwrap_or(s);
+    let digits: String = s.chars().filter(|&c| c != '_' && c != ' ').collect();
+    usize::from_str_radix(&digits, radix)
+}
+
+pub fn parse_binary(s: &str) -> Result<usize, ParseIntError> {
+    parse(s, 2, "0b")
+}
+
+pub fn parse_octal(s: &str) -> Result<usize, ParseIntError> {
+    parse(s, 8, "0o")
+}
+
+pub fn parse_hex(s: &str) -> Result<usize, ParseIntError> {
+    parse(s, 16, "0x")
+}
+
+// Displays a value in binary, grouped into nibbles. `{:#}` adds a 0b
+// prefix.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Bits {
+    value: usize,
+    width: usize,
+}
+
+impl Bits {
+    pub fn new(value: usize) -> Self {
+        Bits { value, width: WIDTH }
+    }
+
+    // Only the low `width` bits
+    pub fn low(value: usize, width: usize) -> Self {
+        Bits {
+            value,
+            width: width.min(WIDTH),
+        }
+    }
+}
+
+impl fmt::Display for Bits {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if f.alternate() {
+            write!(f, "0b")?;
+        }
+        write!(f, "{}", group(&low_bits(self.value, self.width), 4, '_'))
+    }
+}
+
+#[test]
+fn test_formatting() {
+    assert_eq!(binary(5).len(), WIDTH);
+    assert!(binary(5).ends_with("0101"));
+    assert_eq!(hex(0xbeef).len(), WIDTH / 4);
+    assert!(hex(0xbeef).ends_with("000beef"));
+    assert!(octal(8).ends_with("010"));
+    assert_eq!(low_bits(0b1011_0110, 5), "10110");
+    assert_eq!(low_bits(!0, 100), binary(!0));
+
+    assert_eq!(group("11010110", 4, '_'), "1101_0110");
+    assert_eq!(group("110101", 4, ' '), "11 0101");
+    assert_eq!(group("", 4, '_'), "");
+
+    assert_eq!(Bits::low(0xa5, 8).to_string(), "1010_0101");
+    assert_eq!(format!("{:#}", Bits::low(3, 6)), "0b00_0011");
+    assert_eq!(Bits::new(1).to_string().len(), WIDTH + WIDTH / 4 - 1);
+}
+
+#[test]
+fn test_parsing() {
+    assert_eq!(parse_binary(&binary(12345)), Ok(12345));
+    assert_eq!(parse_binary("0b1010_0101"), Ok(0xa5));
+    assert_eq!(parse_binary(&Bits::low(77, 8).to_string()), Ok(77));
+    assert_eq!(parse_hex(&hex(0xdead_beef)), Ok(0xdead_beef));
+    assert_eq!(parse_hex("0xff ff"), Ok(0xffff));
+    assert_eq!(parse_octal(&octal(511)), Ok(511));
+    assert!(parse_binary("012").is_err());
+    assert!(parse_hex("").is_err());
+}
@@ -0,0 +1,138 @@
+// A bloom filter over string keys, built on the same multiplier hash as the
+// table. Put one in front of a `Hash` to answer most lookups for absent keys
+// without touching the table.
+
+use hash::hash_str_seeded;
+
+// Two fixed seeds; the k probe positions are derived from them by double
+// hashing (h1 + i * h2), so only two passes over the key are needed.
+const SEED_A: usize = 0x9e37_79b9;
+const SEED_B: usize = 0x7f4a_7c15;
+
+#[derive(Clone, Debug)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    nbits: usize,
+    k: usize,
+}
+
+impl BloomFilter {
+    // Sizes the filter for `expected` keys at roughly the given false
+    // positive rate.
+    pub fn new(expected: usize, fp_rate: f64) -> Self {
+        assert!(fp_rate > 0.0 && fp_rate < 1.0, "fp_rate must be in (0, 1)");
+
+        let n = expected.max(1) as f64;
+        let ln2 = ::std::f64::consts::LN_2;
+        let nbits = (-n * fp_rate.ln() / (ln2 * ln2)).ceil() as usize;
+        let k = (nbits as f64 / n * ln2).round() as usize;
+
+        BloomFilter::with_size(nbits, k)
+    }
+
+    // A filter with exactly `nbits` bits (rounded up to a whole word) and
+    // `k` probes per key.
+    pub fn with_size(nbits: usize, k: usize) -> Self {
+        let words = nbits.max(1).div_ceil(64);
+
+        BloomFilter {
+            bits: vec![0; words],
+            nbits: words * 64,
+            k: k.max(1),
+        }
+    }
+
+    pub fn bit_count(&self) -> usize {
+        self.nbits
+    }
+
+    pub fn hash_count(&self) -> usize {
+        self.k
+    }
+
+    fn probes(&self, name: &str) -> Probes {
+        Probes {
+            h: hash_str_seeded(name, SEED_A),
+            step: hash_str_seeded(name, SEED_B) | 1,
+            left: self.k,
+            nbits: self.nbits,
+        }
+    }
+
+    pub fn insert(&mut self, name: &str) {
+        for bit in self.probes(name) {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+    }
+
+    // False means the key was definitely never inserted; true means it
+    // probably was.
+    pub fn contains(&self, name: &str) -> bool {
+        self.probes(name)
+            .all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    pub fn clear(&mut self) {
+        for word in self.bits.iter_mut() {
+            *word = 0;
+        }
+    }
+}
+
+impl<'a> Extend<&'a str> for BloomFilter {
+    fn extend<I: IntoIterator<Item = &'a str>>(&mut self, iter: I) {
+        for name in iter {
+            self.insert(name);
+        }
+    }
+}
+
+struct Probes {
+    h: usize,
+    step: usize,
+    left: usize,
+    nbits: usize,
+}
+
+impl Iterator for Probes {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        if self.left == 0 {
+            return None;
+        }
+        self.left -= 1;
+
+        let bit = self.h % self.nbits;
+        self.h = self.h.wrapping_add(self.step);
+        Some(bit)
+    }
+}
+
+#[test]
+fn test_bloom_no_false_negatives() {
+    let mut bloom = BloomFilter::new(1000, 0.01);
+
+    for i in 0..1000 {
+        bloom.insert(&format!("key{}", i));
+    }
+    for i in 0..1000 {
+        assert!(bloom.contains(&format!("key{}", i)));
+    }
+
+    bloom.clear();
+    assert!(!bloom.contains("key0"));
+}
+
+#[test]
+fn test_bloom_false_positive_rate() {
+    let mut bloom = BloomFilter::new(1000, 0.01);
+    let keys: Vec<String> = (0..1000).map(|i| i.to_string()).collect();
+    bloom.extend(keys.iter().map(|k| &k[..]));
+
+    let false_positives = (1000..11000)
+        .filter(|i| bloom.contains(&i.to_string()))
+        .count();
+    // Allow some slack over the 1% target
+    assert!(false_positives < 200, "{} false positives", false_positives);
+}
@@ -0,0 +1,360 @@
+// A thread-safe table holding at most a fixed number of entries. Inserting
+// a new key into a full table evicts the oldest one, which is queued for an
+// eviction callback (writing it back to storage, say) run by whichever
+// thread calls process_evictions(). If the callback is slower than the
+// inserts, evicted entries pile up; once `max_backlog` of them are waiting,
+// inserting a new key fails with Backpressure, or with insert_timeout()
+// waits for the backlog to drain, rather than letting memory grow.
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use config::{Eviction, HashConfig};
+use error;
+use hash::Hash;
+use ordered::OrderedHash;
+
+// The insert was refused because eviction is behind; the value is handed
+// back
+#[derive(Debug, PartialEq, Eq)]
+pub struct Backpressure<T>(pub T);
+
+impl<T> fmt::Display for Backpressure<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "eviction is falling behind")
+    }
+}
+
+impl<T: fmt::Debug> Error for Backpressure<T> {}
+
+struct State<T: Clone> {
+    hash: OrderedHash<T>,
+    // Evicted, waiting for the callback
+    evicted: VecDeque<(String, T)>,
+    // Taken by process_evictions() but not yet through the callback
+    in_flight: usize,
+    evictions: usize,
+}
+
+impl<T> State<T>
+where
+    T: Clone,
+{
+    fn backlog(&self) -> usize {
+        self.evicted.len() + self.in_flight
+    }
+}
+
+// The part of a process_evictions() batch still counted in `in_flight`.
+// Dropping it stops counting whatever the callback didn't get to, so a
+// panicking callback can't leave the backlog stuck full.
+struct InFlight<'a, T: Clone> {
+    table: &'a BoundedHash<T>,
+    remaining: usize,
+}
+
+impl<'a, T> InFlight<'a, T>
+where
+    T: Clone,
+{
+    fn finish(&mut self, n: usize) {
+        self.table.lock().in_flight -= n;
+        self.remaining -= n;
+        self.table.drained.notify_all();
+    }
+}
+
+impl<'a, T> Drop for InFlight<'a, T>
+where
+    T: Clone,
+{
+    fn drop(&mut self) {
+        if self.remaining > 0 {
+            let remaining = self.remaining;
+            self.finish(remaining);
+        }
+    }
+}
+
+pub struct BoundedHash<T: Clone> {
+    state: Mutex<State<T>>,
+    // Signalled whenever the backlog shrinks
+    drained: Condvar,
+    capacity: usize,
+    max_backlog: usize,
+}
+
+impl<T> BoundedHash<T>
+where
+    T: Clone,
+{
+    pub fn new(capacity: usize, max_backlog: usize) -> Self {
+        assert!(capacity > 0, "a BoundedHash needs room for one entry");
+        BoundedHash::with_table(OrderedHash::new(), capacity, max_backlog)
+    }
+
+    // A table set up as `config` says, with its capacity and backlog taken
+    // from the eviction policy, which must be Eviction::Oldest
+    pub fn with_config(config: &HashConfig) -> Result<Self, error::Error> {
+        config.validate()?;
+        let (capacity, max_backlog) = match config.eviction {
+            Eviction::Oldest { capacity, max_backlog } => (capacity, max_backlog),
+            Eviction::None => {
+                return Err(error::Error::InvalidConfig(
+                    "a BoundedHash needs eviction \"oldest\"".to_string(),
+                ))
+            }
+        };
+
+        let table = Hash::with_config(&HashConfig {
+            eviction: Eviction::None,
+            ..config.clone()
+        })?;
+        Ok(BoundedHash::with_table(OrderedHash::from_table(table), capacity, max_backlog))
+    }
+
+    fn with_table(hash: OrderedHash<T>, capacity: usize, max_backlog: usize) -> Self {
+        BoundedHash {
+            state: Mutex::new(State {
+                hash,
+                evicted: VecDeque::new(),
+                in_flight: 0,
+                evictions: 0,
+            }),
+            drained: Condvar::new(),
+            capacity,
+            max_backlog,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        // Only the eviction callback runs outside the lock, so a panic can't
+        // leave the state half-updated
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().hash.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().hash.is_empty()
+    }
+
+    // Evicted entries the callback hasn't finished with
+    pub fn backlog(&self) -> usize {
+        self.lock().backlog()
+    }
+
+    // Entries evicted so far
+    pub fn evictions(&self) -> usize {
+        self.lock().evictions
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<T> {
+        self.lock().hash.lookup(name).cloned()
+    }
+
+    pub fn remove(&self, name: &str) -> Option<T> {
+        self.lock().hash.remove(name)
+    }
+
+    // Inserts unless that needs an eviction and the backlog is full; hands
+    // the state back in that case
+    fn try_insert<'a>(
+        &self,
+        mut state: MutexGuard<'a, State<T>>,
+        name: &str,
+        value: T,
+    ) -> Result<bool, (MutexGuard<'a, State<T>>, T)> {
+        if let Some(current) = state.hash.lookup_mut(name) {
+            *current = value;
+            return Ok(false);
+        }
+        // A key over the table's max_key_len panics, as in Hash::upsert(),
+        // but before anything is evicted for it
+        if let Err(e) = state.hash.check_key(name) {
+            panic!("{}", e);
+        }
+        if state.hash.len() >= self.capacity {
+            if state.backlog() >= self.max_backlog {
+                return Err((state, value));
+            }
+            if let Some(oldest) = state.hash.pop_first() {
+                state.evicted.push_back(oldest);
+                state.evictions += 1;
+            }
+        }
+        state.hash.upsert(name, value);
+        Ok(true)
+    }
+
+    // Returns true for a new key. Overwriting a key never evicts, so never
+    // fails.
+    pub fn insert(&self, name: &str, value: T) -> Result<bool, Backpressure<T>> {
+        self.try_insert(self.lock(), name, value)
+            .map_err(|(_, value)| Backpressure(value))
+    }
+
+    // Like insert(), but waits up to `timeout` for the backlog to drain
+    pub fn insert_timeout(
+        &self,
+        name: &str,
+        mut value: T,
+        timeout: Duration,
+    ) -> Result<bool, Backpressure<T>> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.lock();
+        loop {
+            match self.try_insert(state, name, value) {
+                Ok(new) => return Ok(new),
+                Err((guard, unplaced)) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(Backpressure(unplaced));
+                    }
+                    value = unplaced;
+                    state = self
+                        .drained
+                        .wait_timeout(guard, deadline - now)
+                        .unwrap_or_else(|e| e.into_inner())
+                        .0;
+                }
+            }
+        }
+    }
+
+    // Runs `callback` on every evicted entry waiting, without holding the
+    // lock, so inserts carry on meanwhile. Returns how many were handled.
+    // If the callback panics, the rest of its batch is dropped and stops
+    // counting towards the backlog.
+    pub fn process_evictions<F: FnMut(&str, T)>(&self, mut callback: F) -> usize {
+        let batch: Vec<(String, T)> = {
+            let mut state = self.lock();
+            let batch: Vec<_> = state.evicted.drain(..).collect();
+            state.in_flight += batch.len();
+            batch
+        };
+
+        let n = batch.len();
+        let mut in_flight = InFlight {
+            table: self,
+            remaining: n,
+        };
+        for (name, value) in batch {
+            callback(&name, value);
+            // Room frees up one entry at a time
+            in_flight.finish(1);
+        }
+        n
+    }
+}
+
+#[test]
+fn test_bounded_eviction() {
+    let table = BoundedHash::new(3, 2);
+    for name in &["a", "b", "c"] {
+        assert_eq!(table.insert(name, 1), Ok(true));
+    }
+    assert_eq!(table.insert("d", 1), Ok(true));
+    assert_eq!(table.insert("e", 1), Ok(true));
+    assert_eq!((table.len(), table.backlog()), (3, 2));
+
+    // Eviction is behind: new keys are refused, overwrites aren't
+    assert_eq!(table.insert("f", 7), Err(Backpressure(7)));
+    assert_eq!(table.insert("e", 2), Ok(false));
+    assert_eq!(table.lookup("a"), None);
+    assert_eq!(table.lookup("e"), Some(2));
+
+    let mut evicted = Vec::new();
+    assert_eq!(table.process_evictions(|name, _| evicted.push(name.to_string())), 2);
+    assert_eq!(evicted, vec!["a", "b"]);
+    assert_eq!(table.insert("f", 7), Ok(true));
+    assert_eq!(table.evictions(), 3);
+    assert_eq!(table.remove("f"), Some(7));
+}
+
+#[test]
+fn test_bounded_panicking_evictor() {
+    use std::panic::{self, AssertUnwindSafe};
+
+    let table = BoundedHash::new(1, 2);
+    for name in &["a", "b", "c"] {
+        table.insert(name, 1).unwrap();
+    }
+    assert_eq!((table.backlog(), table.insert("d", 1)), (2, Err(Backpressure(1))));
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        table.process_evictions(|_, _| panic!("storage is down"))
+    }));
+    assert!(result.is_err());
+    assert_eq!(table.backlog(), 0);
+    assert_eq!(table.insert("d", 1), Ok(true));
+    assert_eq!(table.insert_timeout("e", 1, Duration::from_secs(10)), Ok(true));
+}
+
+#[test]
+fn test_bounded_insert_timeout() {
+    use std::thread;
+
+    let table = BoundedHash::new(1, 1);
+    table.insert("a", 0).unwrap();
+    table.insert("b", 0).unwrap();
+
+    let short = Duration::from_millis(10);
+    assert_eq!(table.insert_timeout("c", 1, short), Err(Backpressure(1)));
+
+    // A slow evictor: the insert waits for it instead of failing
+    thread::scope(|s| {
+        s.spawn(|| {
+            thread::sleep(Duration::from_millis(50));
+            table.process_evictions(|_, _| thread::sleep(Duration::from_millis(20)));
+        });
+        let result = table.insert_timeout("c", 1, Duration::from_secs(10));
+        assert_eq!(result, Ok(true));
+    });
+    assert_eq!(table.lookup("c"), Some(1));
+    assert!(table.backlog() <= 1);
+}
+
+#[test]
+fn test_bounded_config() {
+    use std::panic::{self, AssertUnwindSafe};
+
+    let config: HashConfig = "eviction = \"oldest\"\ncapacity = 2\nmax_backlog = 1\nseed = 5"
+        .parse()
+        .unwrap();
+    let table = BoundedHash::with_config(&config).unwrap();
+    assert_eq!(table.capacity(), 2);
+    for name in &["a", "b", "c"] {
+        assert_eq!(table.insert(name, 1), Ok(true));
+    }
+    assert_eq!(table.insert("d", 1), Err(Backpressure(1)));
+    table.process_evictions(|_, _| {});
+
+    let long = "k".repeat(100);
+    let config = HashConfig {
+        max_key_len: Some(8),
+        ..config
+    };
+    let table = BoundedHash::with_config(&config).unwrap();
+    table.insert("a", 1).unwrap();
+    table.insert("b", 1).unwrap();
+    let result = panic::catch_unwind(AssertUnwindSafe(|| table.insert(&long, 1)));
+    assert!(result.is_err());
+    assert_eq!((table.len(), table.evictions()), (2, 0));
+    assert_eq!(table.lookup("a"), Some(1));
+
+    let unbounded = HashConfig::default();
+    assert!(matches!(
+        BoundedHash::<u8>::with_config(&unbounded),
+        Err(error::Error::InvalidConfig(_))
+    ));
+}
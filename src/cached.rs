@@ -0,0 +1,288 @@
+// Read-through caching: a table in front of a slower source of values. A
+// miss calls the loader and keeps what it returns, so the next lookup of
+// that key is a hit. Keys the loader has no value for aren't remembered and
+// are asked for again next time. SharedCachedHash is the same over a lock,
+// for many threads; with single_flight() concurrent misses on one key make
+// one call to the loader between them instead of one each.
+
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+use hash::Hash;
+
+pub struct CachedHash<T: Clone, F> {
+    hash: Hash<T>,
+    loader: F,
+    loads: usize,
+}
+
+impl<T, F> CachedHash<T, F>
+where
+    T: Clone,
+    F: Fn(&str) -> Option<T>,
+{
+    pub fn new(loader: F) -> Self {
+        CachedHash {
+            hash: Hash::new(),
+            loader,
+            loads: 0,
+        }
+    }
+
+    pub fn inner(&self) -> &Hash<T> {
+        &self.hash
+    }
+
+    pub fn len(&self) -> usize {
+        self.hash.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hash.is_empty()
+    }
+
+    // Number of times the loader has been called
+    pub fn loads(&self) -> usize {
+        self.loads
+    }
+
+    pub fn get(&mut self, name: &str) -> Option<&T> {
+        if self.hash.lookup(name).is_none() {
+            self.loads += 1;
+            let value = (self.loader)(name)?;
+            self.hash.upsert(name, value);
+        }
+        self.hash.lookup(name)
+    }
+
+    // Only what's cached; never calls the loader
+    pub fn peek(&self, name: &str) -> Option<&T> {
+        self.hash.lookup(name)
+    }
+
+    // Writes a value without going through the loader, e.g. after writing
+    // it to the backing store too
+    pub fn upsert(&mut self, name: &str, value: T) -> bool {
+        self.hash.upsert(name, value)
+    }
+
+    // Forgets `name`, so the next get() loads it afresh
+    pub fn invalidate(&mut self, name: &str) -> Option<T> {
+        self.hash.remove(name)
+    }
+}
+
+// A load in progress that other threads missing on the same key wait for.
+// Holds None until the load is over, then the loader's answer.
+struct Flight<T> {
+    result: Mutex<Option<Option<T>>>,
+    done: Condvar,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    // Every change under these locks is a single call into a table, so a
+    // panic elsewhere can't have left one half-updated
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+pub struct SharedCachedHash<T: Clone, F> {
+    hash: Mutex<Hash<T>>,
+    loader: F,
+    single_flight: bool,
+    flights: Mutex<Hash<Arc<Flight<T>>>>,
+    loads: Mutex<usize>,
+}
+
+// Ends a flight when its load is over, however it ends: if the loader
+// panicked, the waiters get a miss rather than waiting forever
+struct Landing<'a, T: Clone> {
+    flights: &'a Mutex<Hash<Arc<Flight<T>>>>,
+    flight: Arc<Flight<T>>,
+    name: &'a str,
+}
+
+impl<'a, T> Drop for Landing<'a, T>
+where
+    T: Clone,
+{
+    fn drop(&mut self) {
+        lock(self.flights).remove(self.name);
+        let mut result = lock(&self.flight.result);
+        if result.is_none() {
+            *result = Some(None);
+        }
+        self.flight.done.notify_all();
+    }
+}
+
+impl<T, F> SharedCachedHash<T, F>
+where
+    T: Clone,
+    F: Fn(&str) -> Option<T>,
+{
+    pub fn new(loader: F) -> Self {
+        SharedCachedHash {
+            hash: Mutex::new(Hash::new()),
+            loader,
+            single_flight: false,
+            flights: Mutex::new(Hash::new()),
+            loads: Mutex::new(0),
+        }
+    }
+
+    // Concurrent misses on the same key share one load
+    pub fn single_flight(mut self) -> Self {
+        self.single_flight = true;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        lock(&self.hash).len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        lock(&self.hash).is_empty()
+    }
+
+    pub fn loads(&self) -> usize {
+        *lock(&self.loads)
+    }
+
+    fn load(&self, name: &str) -> Option<T> {
+        *lock(&self.loads) += 1;
+        let value = (self.loader)(name)?;
+        lock(&self.hash).upsert(name, value.clone());
+        Some(value)
+    }
+
+    // The loader runs without any lock held, so a slow load only holds up
+    // the threads waiting for that key
+    pub fn get(&self, name: &str) -> Option<T> {
+        if let Some(value) = lock(&self.hash).lookup(name) {
+            return Some(value.clone());
+        }
+        if !self.single_flight {
+            return self.load(name);
+        }
+
+        let (flight, leader) = {
+            let mut flights = lock(&self.flights);
+            // Checked again under the flights lock: a flight that just
+            // landed has put its value in the table
+            if let Some(value) = lock(&self.hash).lookup(name) {
+                return Some(value.clone());
+            }
+            match flights.lookup(name) {
+                Some(flight) => (flight.clone(), false),
+                None => {
+                    let flight = Arc::new(Flight {
+                        result: Mutex::new(None),
+                        done: Condvar::new(),
+                    });
+                    flights.upsert(name, flight.clone());
+                    (flight, true)
+                }
+            }
+        };
+
+        if leader {
+            let landing = Landing {
+                flights: &self.flights,
+                flight,
+                name,
+            };
+            let value = self.load(name);
+            *lock(&landing.flight.result) = Some(value.clone());
+            return value;
+        }
+
+        let mut result = lock(&flight.result);
+        while result.is_none() {
+            result = flight.done.wait(result).unwrap_or_else(|e| e.into_inner());
+        }
+        result.clone().unwrap()
+    }
+
+    pub fn peek(&self, name: &str) -> Option<T> {
+        lock(&self.hash).lookup(name).cloned()
+    }
+
+    pub fn upsert(&self, name: &str, value: T) -> bool {
+        lock(&self.hash).upsert(name, value)
+    }
+
+    pub fn invalidate(&self, name: &str) -> Option<T> {
+        lock(&self.hash).remove(name)
+    }
+}
+
+#[test]
+fn test_read_through() {
+    let mut cache = CachedHash::new(|name: &str| name.parse::<u32>().ok().map(|n| n * 2));
+    assert_eq!(cache.get("21"), Some(&42));
+    assert_eq!(cache.get("21"), Some(&42));
+    assert_eq!(cache.loads(), 1);
+
+    // Misses in the backend aren't cached
+    assert_eq!(cache.get("nope"), None);
+    assert_eq!(cache.get("nope"), None);
+    assert_eq!(cache.loads(), 3);
+    assert_eq!(cache.len(), 1);
+
+    cache.upsert("21", 0);
+    assert_eq!(cache.peek("21"), Some(&0));
+    assert_eq!(cache.invalidate("21"), Some(0));
+    assert_eq!(cache.peek("21"), None);
+    assert_eq!(cache.get("21"), Some(&42));
+    assert_eq!(cache.loads(), 4);
+}
+
+#[test]
+fn test_single_flight() {
+    use std::sync::Barrier;
+    use std::thread;
+    use std::time::Duration;
+
+    let barrier = Barrier::new(8);
+    let slow = |name: &str| {
+        thread::sleep(Duration::from_millis(50));
+        Some(name.len())
+    };
+    let cache = SharedCachedHash::new(slow).single_flight();
+    thread::scope(|s| {
+        for _ in 0..8 {
+            s.spawn(|| {
+                barrier.wait();
+                assert_eq!(cache.get("herd"), Some(4));
+            });
+        }
+    });
+    assert_eq!(cache.loads(), 1);
+    assert_eq!(cache.peek("herd"), Some(4));
+
+    // Without single flight every concurrent miss loads
+    let cache = SharedCachedHash::new(slow);
+    thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| assert_eq!(cache.get("herd"), Some(4)));
+        }
+    });
+    assert!(cache.loads() > 1);
+}
+
+#[test]
+fn test_single_flight_panic() {
+    use std::panic::{self, AssertUnwindSafe};
+
+    let cache = SharedCachedHash::new(|name: &str| {
+        if name == "bad" {
+            panic!("backend down");
+        }
+        Some(1)
+    })
+    .single_flight();
+    let result = panic::catch_unwind(AssertUnwindSafe(|| cache.get("bad")));
+    assert!(result.is_err());
+    // The failed flight was cleaned up
+    assert!(lock(&cache.flights).is_empty());
+    assert_eq!(cache.get("good"), Some(1));
+}
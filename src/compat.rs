@@ -0,0 +1,715 @@
+// A stand-in for std::collections::HashMap with string keys, built on the
+// linear-hashing table, so existing code can be pointed at it by changing
+// one import and compared for behaviour and speed. Method names and
+// signatures follow std's. The differences: keys have to be string-like
+// (K: Borrow<str>, so String, Box<str>, &str and the like), lookups take
+// anything that reads as a str, keys and values must be Clone, as the
+// table's values are, and there is no hasher parameter. Each entry keeps
+// its key twice, once in the table and once next to the value, so that
+// iterators can hand out &K.
+
+use std::borrow::Borrow;
+use std::fmt;
+use std::iter::FromIterator;
+use std::mem;
+use std::ops::Index;
+
+use hash::{self, Hash};
+
+#[derive(Clone)]
+pub struct HashMap<K: Clone, V: Clone> {
+    hash: Hash<(K, V)>,
+}
+
+impl<K, V> HashMap<K, V>
+where
+    K: Borrow<str> + Clone,
+    V: Clone,
+{
+    pub fn new() -> Self {
+        HashMap { hash: Hash::new() }
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        let mut map = HashMap::new();
+        map.reserve(capacity);
+        map
+    }
+
+    // How many entries fit before the table next splits
+    pub fn capacity(&self) -> usize {
+        self.hash.len() + self.hash.capacity()
+    }
+
+    pub fn reserve(&mut self, additional: usize) {
+        self.hash.reserve(additional);
+    }
+
+    pub fn shrink_to_fit(&mut self) {
+        self.hash.shrink_to_fit();
+    }
+
+    pub fn len(&self) -> usize {
+        self.hash.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hash.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.hash = Hash::new();
+    }
+
+    pub fn get<Q>(&self, k: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: AsRef<str> + ?Sized,
+    {
+        self.hash.lookup(k.as_ref()).map(|entry| &entry.1)
+    }
+
+    pub fn get_key_value<Q>(&self, k: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: AsRef<str> + ?Sized,
+    {
+        self.hash.lookup(k.as_ref()).map(|entry| (&entry.0, &entry.1))
+    }
+
+    pub fn get_mut<Q>(&mut self, k: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: AsRef<str> + ?Sized,
+    {
+        self.hash.lookup_mut(k.as_ref()).map(|entry| &mut entry.1)
+    }
+
+    pub fn contains_key<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: AsRef<str> + ?Sized,
+    {
+        self.hash.lookup(k.as_ref()).is_some()
+    }
+
+    // Like std, an existing key keeps its original K and only the value is
+    // replaced
+    pub fn insert(&mut self, k: K, v: V) -> Option<V> {
+        if let Some(entry) = self.hash.lookup_mut(k.borrow()) {
+            return Some(mem::replace(&mut entry.1, v));
+        }
+        let name = k.borrow().to_string();
+        self.hash.upsert(&name, (k, v));
+        None
+    }
+
+    pub fn remove<Q>(&mut self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: AsRef<str> + ?Sized,
+    {
+        self.hash.remove(k.as_ref()).map(|entry| entry.1)
+    }
+
+    pub fn remove_entry<Q>(&mut self, k: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q>,
+        Q: AsRef<str> + ?Sized,
+    {
+        self.hash.remove(k.as_ref())
+    }
+
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V> {
+        if self.contains_key(key.borrow()) {
+            Entry::Occupied(OccupiedEntry { map: self, key })
+        } else {
+            Entry::Vacant(VacantEntry { map: self, key })
+        }
+    }
+
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        let mut removed = Vec::new();
+        for (name, entry) in self.hash.iter_mut() {
+            if !f(&entry.0, &mut entry.1) {
+                removed.push(name.to_string());
+            }
+        }
+        for name in removed {
+            self.hash.remove(&name);
+        }
+    }
+
+    pub fn drain(&mut self) -> Drain<K, V> {
+        Drain {
+            inner: mem::take(&mut self.hash).into_iter(),
+        }
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            inner: self.hash.iter(),
+            remaining: self.hash.len(),
+        }
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V> {
+        IterMut {
+            remaining: self.hash.len(),
+            inner: self.hash.iter_mut(),
+        }
+    }
+
+    pub fn keys(&self) -> Keys<'_, K, V> {
+        Keys { inner: self.iter() }
+    }
+
+    pub fn values(&self) -> Values<'_, K, V> {
+        Values { inner: self.iter() }
+    }
+
+    pub fn values_mut(&mut self) -> ValuesMut<'_, K, V> {
+        ValuesMut {
+            inner: self.iter_mut(),
+        }
+    }
+
+    pub fn into_keys(self) -> IntoKeys<K, V> {
+        IntoKeys {
+            inner: self.into_iter(),
+        }
+    }
+
+    pub fn into_values(self) -> IntoValues<K, V> {
+        IntoValues {
+            inner: self.into_iter(),
+        }
+    }
+}
+
+impl<K, V> Default for HashMap<K, V>
+where
+    K: Borrow<str> + Clone,
+    V: Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V> fmt::Debug for HashMap<K, V>
+where
+    K: Borrow<str> + Clone + fmt::Debug,
+    V: Clone + fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<K, V> PartialEq for HashMap<K, V>
+where
+    K: Borrow<str> + Clone,
+    V: Clone + PartialEq,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().all(|(k, v)| other.get(k.borrow()) == Some(v))
+    }
+}
+
+impl<K, V> Eq for HashMap<K, V>
+where
+    K: Borrow<str> + Clone,
+    V: Clone + Eq,
+{
+}
+
+impl<K, Q, V> Index<&Q> for HashMap<K, V>
+where
+    K: Borrow<str> + Borrow<Q> + Clone,
+    Q: AsRef<str> + ?Sized,
+    V: Clone,
+{
+    type Output = V;
+
+    fn index(&self, key: &Q) -> &V {
+        self.get(key).expect("no entry found for key")
+    }
+}
+
+impl<K, V> FromIterator<(K, V)> for HashMap<K, V>
+where
+    K: Borrow<str> + Clone,
+    V: Clone,
+{
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut map = HashMap::new();
+        map.extend(iter);
+        map
+    }
+}
+
+impl<K, V> Extend<(K, V)> for HashMap<K, V>
+where
+    K: Borrow<str> + Clone,
+    V: Clone,
+{
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (k, v) in iter {
+            self.insert(k, v);
+        }
+    }
+}
+
+impl<K, V, const N: usize> From<[(K, V); N]> for HashMap<K, V>
+where
+    K: Borrow<str> + Clone,
+    V: Clone,
+{
+    fn from(entries: [(K, V); N]) -> Self {
+        let mut map = HashMap::with_capacity(N);
+        map.extend(entries);
+        map
+    }
+}
+
+pub struct Iter<'a, K: 'a + Clone, V: 'a + Clone> {
+    inner: hash::Iter<'a, (K, V)>,
+    remaining: usize,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V>
+where
+    K: Clone,
+    V: Clone,
+{
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (_, entry) = self.inner.next()?;
+        self.remaining -= 1;
+        Some((&entry.0, &entry.1))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, K, V> ExactSizeIterator for Iter<'a, K, V>
+where
+    K: Clone,
+    V: Clone,
+{
+}
+
+pub struct IterMut<'a, K: 'a + Clone, V: 'a + Clone> {
+    inner: hash::IterMut<'a, (K, V)>,
+    remaining: usize,
+}
+
+impl<'a, K, V> Iterator for IterMut<'a, K, V>
+where
+    K: Clone,
+    V: Clone,
+{
+    type Item = (&'a K, &'a mut V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (_, entry) = self.inner.next()?;
+        self.remaining -= 1;
+        let (ref k, ref mut v) = *entry;
+        Some((k, v))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, K, V> ExactSizeIterator for IterMut<'a, K, V>
+where
+    K: Clone,
+    V: Clone,
+{
+}
+
+pub struct Keys<'a, K: 'a + Clone, V: 'a + Clone> {
+    inner: Iter<'a, K, V>,
+}
+
+impl<'a, K, V> Iterator for Keys<'a, K, V>
+where
+    K: Clone,
+    V: Clone,
+{
+    type Item = &'a K;
+
+    fn next(&mut self) -> Option<&'a K> {
+        self.inner.next().map(|(k, _)| k)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<'a, K, V> ExactSizeIterator for Keys<'a, K, V>
+where
+    K: Clone,
+    V: Clone,
+{
+}
+
+pub struct Values<'a, K: 'a + Clone, V: 'a + Clone> {
+    inner: Iter<'a, K, V>,
+}
+
+impl<'a, K, V> Iterator for Values<'a, K, V>
+where
+    K: Clone,
+    V: Clone,
+{
+    type Item = &'a V;
+
+    fn next(&mut self) -> Option<&'a V> {
+        self.inner.next().map(|(_, v)| v)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<'a, K, V> ExactSizeIterator for Values<'a, K, V>
+where
+    K: Clone,
+    V: Clone,
+{
+}
+
+pub struct ValuesMut<'a, K: 'a + Clone, V: 'a + Clone> {
+    inner: IterMut<'a, K, V>,
+}
+
+impl<'a, K, V> Iterator for ValuesMut<'a, K, V>
+where
+    K: Clone,
+    V: Clone,
+{
+    type Item = &'a mut V;
+
+    fn next(&mut self) -> Option<&'a mut V> {
+        self.inner.next().map(|(_, v)| v)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+pub struct IntoIter<K: Clone, V: Clone> {
+    inner: hash::IntoIter<(K, V)>,
+}
+
+impl<K, V> Iterator for IntoIter<K, V>
+where
+    K: Clone,
+    V: Clone,
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        self.inner.next().map(|(_, entry)| entry)
+    }
+}
+
+// Everything drain() took out; the map is already empty
+pub type Drain<K, V> = IntoIter<K, V>;
+
+pub struct IntoKeys<K: Clone, V: Clone> {
+    inner: IntoIter<K, V>,
+}
+
+impl<K, V> Iterator for IntoKeys<K, V>
+where
+    K: Clone,
+    V: Clone,
+{
+    type Item = K;
+
+    fn next(&mut self) -> Option<K> {
+        self.inner.next().map(|(k, _)| k)
+    }
+}
+
+pub struct IntoValues<K: Clone, V: Clone> {
+    inner: IntoIter<K, V>,
+}
+
+impl<K, V> Iterator for IntoValues<K, V>
+where
+    K: Clone,
+    V: Clone,
+{
+    type Item = V;
+
+    fn next(&mut self) -> Option<V> {
+        self.inner.next().map(|(_, v)| v)
+    }
+}
+
+impl<K, V> IntoIterator for HashMap<K, V>
+where
+    K: Borrow<str> + Clone,
+    V: Clone,
+{
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V>;
+
+    fn into_iter(self) -> IntoIter<K, V> {
+        IntoIter {
+            inner: self.hash.into_iter(),
+        }
+    }
+}
+
+impl<'a, K, V> IntoIterator for &'a HashMap<K, V>
+where
+    K: Borrow<str> + Clone,
+    V: Clone,
+{
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Iter<'a, K, V> {
+        self.iter()
+    }
+}
+
+impl<'a, K, V> IntoIterator for &'a mut HashMap<K, V>
+where
+    K: Borrow<str> + Clone,
+    V: Clone,
+{
+    type Item = (&'a K, &'a mut V);
+    type IntoIter = IterMut<'a, K, V>;
+
+    fn into_iter(self) -> IterMut<'a, K, V> {
+        self.iter_mut()
+    }
+}
+
+pub enum Entry<'a, K: 'a + Clone, V: 'a + Clone> {
+    Occupied(OccupiedEntry<'a, K, V>),
+    Vacant(VacantEntry<'a, K, V>),
+}
+
+// Both kinds of entry look their key up again when used, since the table's
+// own entries borrow the key for their whole life and this API takes it by
+// value
+pub struct OccupiedEntry<'a, K: 'a + Clone, V: 'a + Clone> {
+    map: &'a mut HashMap<K, V>,
+    key: K,
+}
+
+pub struct VacantEntry<'a, K: 'a + Clone, V: 'a + Clone> {
+    map: &'a mut HashMap<K, V>,
+    key: K,
+}
+
+impl<'a, K, V> Entry<'a, K, V>
+where
+    K: Borrow<str> + Clone,
+    V: Clone,
+{
+    pub fn key(&self) -> &K {
+        match *self {
+            Entry::Occupied(ref entry) => entry.key(),
+            Entry::Vacant(ref entry) => entry.key(),
+        }
+    }
+
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(default),
+        }
+    }
+
+    pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> &'a mut V {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(default()),
+        }
+    }
+
+    pub fn or_insert_with_key<F: FnOnce(&K) -> V>(self, default: F) -> &'a mut V {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let value = default(entry.key());
+                entry.insert(value)
+            }
+        }
+    }
+
+    pub fn or_default(self) -> &'a mut V
+    where
+        V: Default,
+    {
+        self.or_insert_with(V::default)
+    }
+
+    pub fn and_modify<F: FnOnce(&mut V)>(self, f: F) -> Self {
+        match self {
+            Entry::Occupied(mut entry) => {
+                f(entry.get_mut());
+                Entry::Occupied(entry)
+            }
+            Entry::Vacant(entry) => Entry::Vacant(entry),
+        }
+    }
+}
+
+impl<'a, K, V> OccupiedEntry<'a, K, V>
+where
+    K: Borrow<str> + Clone,
+    V: Clone,
+{
+    // The key passed to entry(), which equals the stored one
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    pub fn get(&self) -> &V {
+        self.map.get(self.key.borrow()).unwrap()
+    }
+
+    pub fn get_mut(&mut self) -> &mut V {
+        self.map.get_mut(self.key.borrow()).unwrap()
+    }
+
+    pub fn into_mut(self) -> &'a mut V {
+        self.map.get_mut(self.key.borrow()).unwrap()
+    }
+
+    pub fn insert(&mut self, value: V) -> V {
+        mem::replace(self.get_mut(), value)
+    }
+
+    pub fn remove(self) -> V {
+        self.remove_entry().1
+    }
+
+    pub fn remove_entry(self) -> (K, V) {
+        self.map.remove_entry(self.key.borrow()).unwrap()
+    }
+}
+
+impl<'a, K, V> VacantEntry<'a, K, V>
+where
+    K: Borrow<str> + Clone,
+    V: Clone,
+{
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    pub fn into_key(self) -> K {
+        self.key
+    }
+
+    pub fn insert(self, value: V) -> &'a mut V {
+        let name = self.key.borrow().to_string();
+        self.map.hash.upsert(&name, (self.key, value));
+        self.map.get_mut(&name[..]).unwrap()
+    }
+}
+
+#[test]
+fn test_compat_map() {
+    let mut map: HashMap<String, i32> = HashMap::new();
+    assert_eq!(map.insert("a".to_string(), 1), None);
+    assert_eq!(map.insert("b".to_string(), 2), None);
+    assert_eq!(map.insert("a".to_string(), 10), Some(1));
+    assert_eq!(map.get("a"), Some(&10));
+    assert_eq!(map.get(&"b".to_string()), Some(&2));
+    assert_eq!(map["b"], 2);
+    assert!(map.contains_key("a") && !map.contains_key("c"));
+    *map.get_mut("b").unwrap() += 1;
+    assert_eq!(map.get_key_value("b"), Some((&"b".to_string(), &3)));
+    assert_eq!(map.len(), 2);
+
+    for (_, v) in map.iter_mut() {
+        *v *= 2;
+    }
+    for v in &mut map.values_mut() {
+        *v += 1;
+    }
+    let mut entries: Vec<(&String, &i32)> = map.iter().collect();
+    entries.sort();
+    assert_eq!(entries, vec![(&"a".to_string(), &21), (&"b".to_string(), &7)]);
+    assert_eq!(map.keys().len(), 2);
+    assert_eq!(map.values().sum::<i32>(), 28);
+
+    assert_eq!(map.remove("a"), Some(21));
+    assert_eq!(map.remove_entry("b"), Some(("b".to_string(), 7)));
+    assert!(map.is_empty());
+}
+
+#[test]
+fn test_compat_entry() {
+    let mut words: HashMap<&str, usize> = HashMap::new();
+    for word in "the cat and the hat and the bat".split(' ') {
+        *words.entry(word).or_insert(0) += 1;
+    }
+    assert_eq!(words["the"], 3);
+    assert_eq!(words["and"], 2);
+
+    words.entry("cat").and_modify(|n| *n += 10).or_default();
+    *words.entry("dog").or_default() += 1;
+    assert_eq!(words.entry("cow").or_insert_with_key(|k| k.len()), &mut 3);
+    assert_eq!((words["cat"], words["dog"]), (11, 1));
+
+    match words.entry("hat") {
+        Entry::Occupied(mut e) => {
+            assert_eq!(e.insert(5), 1);
+            assert_eq!(e.remove(), 5);
+        }
+        Entry::Vacant(_) => panic!("hat should be there"),
+    }
+    match words.entry("hat") {
+        Entry::Vacant(e) => assert_eq!(e.into_key(), "hat"),
+        Entry::Occupied(_) => panic!("hat was removed"),
+    }
+}
+
+#[test]
+fn test_compat_traits() {
+    let map = HashMap::from([("x".to_string(), 1), ("y".to_string(), 2)]);
+    let copy: HashMap<String, i32> = map.clone().into_iter().collect();
+    assert_eq!(map, copy);
+    assert_eq!(format!("{:?}", HashMap::from([("k", 1)])), "{\"k\": 1}");
+
+    let mut other = copy.clone();
+    other.retain(|k, _| k == "x");
+    assert_ne!(map, other);
+    assert_eq!(other.len(), 1);
+    other.extend(vec![("z".to_string(), 3)]);
+    let mut keys: Vec<String> = other.clone().into_keys().collect();
+    keys.sort();
+    assert_eq!(keys, vec!["x", "z"]);
+    assert_eq!(other.drain().count(), 2);
+    assert!(other.is_empty());
+
+    let mut sized: HashMap<Box<str>, ()> = HashMap::with_capacity(1000);
+    assert!(sized.capacity() >= 1000);
+    sized.insert("boxed".into(), ());
+    assert!(sized.contains_key("boxed"));
+    sized.clear();
+    assert_eq!(sized.into_values().count(), 0);
+}
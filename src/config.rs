@@ -0,0 +1,297 @@
+// Every tunable a table has, in one value a service can keep in its config
+// file. validate() says what's wrong with a configuration before a table is
+// built from it, and Hash::with_config() (or BoundedHash::with_config(),
+// for an eviction policy) builds one. A configuration prints as, and parses
+// back from, lines of `key = value`:
+//
+//     # Tables for the session store
+//     initial_buckets = 1024
+//     split_policy = "load-factor"
+//     load_factor = 1.5
+//     split_mode = "incremental"
+//     multiplier = 31
+//     seed = 0
+//     max_key_len = 256
+//     eviction = "oldest"
+//     capacity = 100000
+//     max_backlog = 1000
+//
+// Any key can be left out to keep its default. That's the same syntax as
+// TOML, so the lines can also sit in a service's own TOML file.
+
+use std::fmt;
+use std::str::FromStr;
+
+use error::Error;
+use hash::SplitPolicy;
+
+// How a table goes about a split once its policy calls for one
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SplitMode {
+    // Split there and then, moving the bucket's entries in one go
+    Eager,
+    // Hash::with_two_choices()
+    TwoChoice,
+    // Hash::with_incremental_splits()
+    Incremental,
+    // Hash::with_deferred_splits()
+    Deferred,
+}
+
+// Which entries a table drops to stay within its size
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Eviction {
+    None,
+    // The oldest key goes once there are `capacity` of them; see BoundedHash
+    Oldest { capacity: usize, max_backlog: usize },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct HashConfig {
+    // A power of two no smaller than 32; the table never shrinks below it
+    pub initial_buckets: usize,
+    pub split_policy: SplitPolicy,
+    pub split_mode: SplitMode,
+    // Must be odd. Changing it or the seed changes where every key goes,
+    // so *_with_hash() methods ignore the hash they are given.
+    pub multiplier: u64,
+    pub seed: u64,
+    // In bytes; None for no limit
+    pub max_key_len: Option<usize>,
+    pub eviction: Eviction,
+}
+
+impl Default for HashConfig {
+    fn default() -> Self {
+        HashConfig {
+            initial_buckets: 32,
+            split_policy: SplitPolicy::default(),
+            split_mode: SplitMode::Eager,
+            multiplier: 31,
+            seed: 0,
+            max_key_len: None,
+            eviction: Eviction::None,
+        }
+    }
+}
+
+fn invalid(why: String) -> Error {
+    Error::InvalidConfig(why)
+}
+
+impl HashConfig {
+    pub fn validate(&self) -> Result<(), Error> {
+        if !self.initial_buckets.is_power_of_two() || self.initial_buckets < 32 {
+            return Err(invalid(format!(
+                "initial_buckets must be a power of two no smaller than 32, not {}",
+                self.initial_buckets
+            )));
+        }
+        if let SplitPolicy::LoadFactor(load) = self.split_policy {
+            if load.is_nan() || load <= 0.0 {
+                return Err(invalid("load factor must be positive".to_string()));
+            }
+        }
+        if self.multiplier & 1 == 0 {
+            return Err(invalid(format!("multiplier must be odd, not {}", self.multiplier)));
+        }
+        if self.max_key_len == Some(0) {
+            return Err(invalid("max_key_len must be at least 1".to_string()));
+        }
+        if let Eviction::Oldest { capacity: 0, .. } = self.eviction {
+            return Err(invalid("eviction capacity must be at least 1".to_string()));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for SplitMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            SplitMode::Eager => "eager",
+            SplitMode::TwoChoice => "two-choice",
+            SplitMode::Incremental => "incremental",
+            SplitMode::Deferred => "deferred",
+        };
+        f.write_str(name)
+    }
+}
+
+impl fmt::Display for HashConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "initial_buckets = {}", self.initial_buckets)?;
+        match self.split_policy {
+            SplitPolicy::LoadFactor(load) => {
+                writeln!(f, "split_policy = \"load-factor\"")?;
+                writeln!(f, "load_factor = {:?}", load)?;
+            }
+            SplitPolicy::BucketOverflow => writeln!(f, "split_policy = \"bucket-overflow\"")?,
+        }
+        writeln!(f, "split_mode = \"{}\"", self.split_mode)?;
+        writeln!(f, "multiplier = {}", self.multiplier)?;
+        writeln!(f, "seed = {}", self.seed)?;
+        if let Some(len) = self.max_key_len {
+            writeln!(f, "max_key_len = {}", len)?;
+        }
+        match self.eviction {
+            Eviction::None => writeln!(f, "eviction = \"none\""),
+            Eviction::Oldest { capacity, max_backlog } => {
+                writeln!(f, "eviction = \"oldest\"")?;
+                writeln!(f, "capacity = {}", capacity)?;
+                writeln!(f, "max_backlog = {}", max_backlog)
+            }
+        }
+    }
+}
+
+// The text between the quotes of a string value
+fn quoted(value: &str) -> Option<&str> {
+    value.strip_prefix('"')?.strip_suffix('"')
+}
+
+// Parses the `key = value` format above. Unknown and repeated keys are
+// errors, so a typo doesn't quietly leave a default in place. The result
+// is not validated.
+impl FromStr for HashConfig {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let mut config = HashConfig::default();
+        let mut seen: Vec<&str> = Vec::new();
+        let mut policy = None;
+        let mut load = None;
+        let mut eviction = None;
+        let (mut capacity, mut max_backlog) = (None, None);
+
+        for (n, line) in s.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let bad = |why: &str| invalid(format!("line {}: {}", n + 1, why));
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => return Err(bad("expected `key = value`")),
+            };
+            if seen.contains(&key) {
+                return Err(bad(&format!("`{}` is set twice", key)));
+            }
+            seen.push(key);
+
+            let number = || value.parse::<u64>().map_err(|_| bad("expected a number"));
+            let string = || quoted(value).ok_or_else(|| bad("expected a quoted string"));
+            match key {
+                "initial_buckets" => config.initial_buckets = number()? as usize,
+                "split_policy" => policy = Some(string()?),
+                "load_factor" => {
+                    load = Some(value.parse::<f64>().map_err(|_| bad("expected a number"))?)
+                }
+                "split_mode" => {
+                    config.split_mode = match string()? {
+                        "eager" => SplitMode::Eager,
+                        "two-choice" => SplitMode::TwoChoice,
+                        "incremental" => SplitMode::Incremental,
+                        "deferred" => SplitMode::Deferred,
+                        other => return Err(bad(&format!("unknown split mode `{}`", other))),
+                    }
+                }
+                "multiplier" => config.multiplier = number()?,
+                "seed" => config.seed = number()?,
+                "max_key_len" => config.max_key_len = Some(number()? as usize),
+                "eviction" => eviction = Some(string()?),
+                "capacity" => capacity = Some(number()? as usize),
+                "max_backlog" => max_backlog = Some(number()? as usize),
+                _ => return Err(bad(&format!("unknown key `{}`", key))),
+            }
+        }
+
+        config.split_policy = match (policy, load) {
+            (None, None) => SplitPolicy::default(),
+            (None, Some(load)) | (Some("load-factor"), Some(load)) => {
+                SplitPolicy::LoadFactor(load)
+            }
+            (Some("load-factor"), None) => SplitPolicy::default(),
+            (Some("bucket-overflow"), None) => SplitPolicy::BucketOverflow,
+            (Some("bucket-overflow"), Some(_)) => {
+                let why = "load_factor only goes with split_policy \"load-factor\"";
+                return Err(invalid(why.to_string()));
+            }
+            (Some(other), _) => return Err(invalid(format!("unknown split policy `{}`", other))),
+        };
+
+        config.eviction = match (eviction, capacity, max_backlog) {
+            (None, None, None) | (Some("none"), None, None) => Eviction::None,
+            (Some("oldest"), Some(capacity), Some(max_backlog)) => {
+                Eviction::Oldest { capacity, max_backlog }
+            }
+            (Some("oldest"), _, _) => {
+                return Err(invalid("eviction \"oldest\" needs capacity and max_backlog".into()))
+            }
+            (Some("none"), _, _) | (None, _, _) => {
+                return Err(invalid("capacity and max_backlog need eviction \"oldest\"".into()))
+            }
+            (Some(other), _, _) => return Err(invalid(format!("unknown eviction `{}`", other))),
+        };
+
+        Ok(config)
+    }
+}
+
+#[test]
+fn test_config_text() {
+    let config = HashConfig {
+        initial_buckets: 1024,
+        split_policy: SplitPolicy::LoadFactor(1.5),
+        split_mode: SplitMode::Incremental,
+        multiplier: 131,
+        seed: 7,
+        max_key_len: Some(256),
+        eviction: Eviction::Oldest {
+            capacity: 100,
+            max_backlog: 10,
+        },
+    };
+    assert_eq!(config.to_string().parse::<HashConfig>().unwrap(), config);
+    let default = HashConfig::default();
+    assert_eq!(default.to_string().parse::<HashConfig>().unwrap(), default);
+
+    let text = "# sessions\n\ninitial_buckets = 64  # grows anyway\n\
+                split_policy = \"bucket-overflow\"\n";
+    let parsed: HashConfig = text.parse().unwrap();
+    assert_eq!(parsed.initial_buckets, 64);
+    assert_eq!(parsed.split_policy, SplitPolicy::BucketOverflow);
+    assert_eq!(parsed.seed, 0);
+
+    let errors = [
+        ("initial_buckets = 64\ninitial_buckets = 1", "line 2: `initial_buckets` is set twice"),
+        ("initail_buckets = 64", "line 1: unknown key `initail_buckets`"),
+        ("split_mode = eager", "line 1: expected a quoted string"),
+        ("seed = -1", "line 1: expected a number"),
+        (
+            "eviction = \"oldest\"\ncapacity = 9",
+            "eviction \"oldest\" needs capacity and max_backlog",
+        ),
+    ];
+    for &(text, why) in &errors {
+        let e = text.parse::<HashConfig>().unwrap_err();
+        assert_eq!(e.to_string(), format!("invalid configuration: {}", why));
+    }
+
+    let buckets = "initial_buckets must be a power of two no smaller than 32, not";
+    let checks = [
+        ("initial_buckets = 48", format!("{} 48", buckets)),
+        ("initial_buckets = 16", format!("{} 16", buckets)),
+        ("load_factor = 0", "load factor must be positive".to_string()),
+        ("multiplier = 32", "multiplier must be odd, not 32".to_string()),
+        ("max_key_len = 0", "max_key_len must be at least 1".to_string()),
+        (
+            "eviction = \"oldest\"\ncapacity = 0\nmax_backlog = 1",
+            "eviction capacity must be at least 1".to_string(),
+        ),
+    ];
+    for &(text, ref why) in &checks {
+        let e = text.parse::<HashConfig>().unwrap().validate().unwrap_err();
+        assert_eq!(e.to_string(), format!("invalid configuration: {}", why));
+    }
+    assert!(config.validate().is_ok());
+}
@@ -0,0 +1,106 @@
+// Exact per-key counts on top of `Hash<u64>`. Counts and the total stick
+// at u64::MAX rather than overflow, as in `CountMin`.
+
+use hash::Hash;
+
+pub struct Counter {
+    counts: Hash<u64>,
+    total: u64,
+}
+
+impl Counter {
+    pub fn new() -> Self {
+        Counter {
+            counts: Hash::new(),
+            total: 0,
+        }
+    }
+
+    // Number of distinct keys counted
+    pub fn len(&self) -> usize {
+        self.counts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+
+    // Sum of all counts
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    pub fn add(&mut self, name: &str) {
+        self.add_n(name, 1);
+    }
+
+    pub fn add_n(&mut self, name: &str, n: u64) {
+        match self.counts.lookup_mut(name) {
+            Some(count) => *count = count.saturating_add(n),
+            None => {
+                self.counts.upsert(name, n);
+            }
+        }
+        self.total = self.total.saturating_add(n);
+    }
+
+    pub fn count(&self, name: &str) -> u64 {
+        self.counts.lookup(name).cloned().unwrap_or(0)
+    }
+
+    // The `n` highest counts, largest first. Ties are broken by key so the
+    // result doesn't depend on the table layout.
+    pub fn most_common(&self, n: usize) -> Vec<(&str, u64)> {
+        let mut all: Vec<(&str, u64)> = self.counts.iter().map(|(name, &count)| (name, count)).collect();
+
+        all.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        all.truncate(n);
+        all
+    }
+
+    // Adds every count from `other` into this counter
+    pub fn merge(&mut self, other: &Counter) {
+        for (name, &count) in other.counts.iter() {
+            self.add_n(name, count);
+        }
+    }
+}
+
+impl Default for Counter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> Extend<&'a str> for Counter {
+    fn extend<I: IntoIterator<Item = &'a str>>(&mut self, iter: I) {
+        for name in iter {
+            self.add(name);
+        }
+    }
+}
+
+#[test]
+fn test_counter() {
+    let mut counter = Counter::new();
+    counter.extend("the cat sat on the mat with the hat".split(' '));
+
+    assert_eq!(counter.count("the"), 3);
+    assert_eq!(counter.count("dog"), 0);
+    assert_eq!(counter.len(), 7);
+    assert_eq!(counter.total(), 9);
+    assert_eq!(counter.most_common(3), vec![("the", 3), ("cat", 1), ("hat", 1)]);
+
+    let mut other = Counter::new();
+    other.add_n("cat", 5);
+    other.add("dog");
+    counter.merge(&other);
+    assert_eq!(counter.most_common(2), vec![("cat", 6), ("the", 3)]);
+    assert_eq!(counter.count("dog"), 1);
+    assert_eq!(counter.total(), 15);
+
+    counter.add_n("cat", u64::MAX);
+    assert_eq!(counter.count("cat"), u64::MAX);
+    assert_eq!(counter.total(), u64::MAX);
+    assert_eq!(counter.count("the"), 3);
+}
@@ -0,0 +1,218 @@
+// A cuckoo-hashed alternative to `Hash` with the same lookup/upsert/remove
+// interface. Every key lives in one of exactly two slots, one per table, so a
+// lookup never probes more than two entries. Inserts displace whatever sits
+// in the way into its other slot. A key that still has no slot after
+// MAX_KICKS displacements goes into a small stash, as HopscotchHash does with
+// its overflow list, and gets another try whenever the tables double. Keys
+// that collide in both tables never separate however big the tables get, so
+// only the load factor decides when to grow.
+//
+// This is a standalone type rather than a mode of `Hash`: its layout has no
+// buckets or splits for `Hash`'s constructors to configure.
+
+use std::mem;
+
+use hash::{hash_str_seeded, mix64};
+
+const INITIAL_SLOTS: usize = 16;
+const MAX_KICKS: usize = 64;
+const SEEDS: [usize; 2] = [0x9e37_79b9, 0x85eb_ca6b];
+
+struct Slot<T> {
+    name: String,
+    value: T,
+}
+
+pub struct CuckooHash<T> {
+    tables: [Vec<Option<Slot<T>>>; 2],
+    // Keys that found no slot; usually empty
+    stash: Vec<Slot<T>>,
+    count: usize,
+}
+
+fn empty_table<T>(slots: usize) -> Vec<Option<Slot<T>>> {
+    (0..slots).map(|_| None).collect()
+}
+
+impl<T> CuckooHash<T> {
+    pub fn new() -> Self {
+        CuckooHash {
+            tables: [empty_table(INITIAL_SLOTS), empty_table(INITIAL_SLOTS)],
+            stash: Vec::new(),
+            count: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    // Total number of slots across both tables
+    pub fn capacity(&self) -> usize {
+        self.tables[0].len() * 2
+    }
+
+    fn index(&self, table: usize, name: &str) -> usize {
+        let slots = self.tables[table].len();
+        (mix64(hash_str_seeded(name, SEEDS[table])) as usize) & (slots - 1)
+    }
+
+    fn find(&self, name: &str) -> Option<(usize, usize)> {
+        (0..2)
+            .map(|t| (t, self.index(t, name)))
+            .find(|&(t, i)| match self.tables[t][i] {
+                Some(ref slot) => slot.name == name,
+                None => false,
+            })
+    }
+
+    fn find_stashed(&self, name: &str) -> Option<usize> {
+        self.stash.iter().position(|slot| slot.name == name)
+    }
+
+    // Number of keys that are in the stash rather than the tables
+    pub fn stashed(&self) -> usize {
+        self.stash.len()
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<&T> {
+        match self.find(name) {
+            Some((t, i)) => self.tables[t][i].as_ref().map(|slot| &slot.value),
+            None => self.find_stashed(name).map(|i| &self.stash[i].value),
+        }
+    }
+
+    // Returns true if the insert was a new key,
+    // False if we overwrote a key
+    pub fn upsert(&mut self, name: &str, value: T) -> bool {
+        if let Some((t, i)) = self.find(name) {
+            if let Some(ref mut slot) = self.tables[t][i] {
+                slot.value = value;
+            }
+            return false;
+        }
+        if let Some(i) = self.find_stashed(name) {
+            self.stash[i].value = value;
+            return false;
+        }
+
+        // Cuckoo tables get unreliable past half full
+        if (self.count + 1) * 2 > self.capacity() {
+            self.grow();
+        }
+
+        self.place(Slot {
+            name: name.to_string(),
+            value,
+        });
+        self.count += 1;
+
+        true
+    }
+
+    fn place(&mut self, mut slot: Slot<T>) {
+        let mut t = 0;
+        for _ in 0..MAX_KICKS {
+            let i = self.index(t, &slot.name);
+            match self.tables[t][i].replace(slot) {
+                None => return,
+                Some(evicted) => slot = evicted,
+            }
+            t ^= 1;
+        }
+
+        // Probably a cycle; the next grow() may break it
+        self.stash.push(slot);
+    }
+
+    fn grow(&mut self) {
+        let slots = self.tables[0].len() * 2;
+        let [first, second] = mem::replace(&mut self.tables, [empty_table(slots), empty_table(slots)]);
+        let stash = mem::take(&mut self.stash);
+
+        for slot in first.into_iter().chain(second).flatten().chain(stash) {
+            self.place(slot);
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<T> {
+        let value = match self.find(name) {
+            Some((t, i)) => self.tables[t][i].take().map(|slot| slot.value),
+            None => self.find_stashed(name).map(|i| self.stash.swap_remove(i).value),
+        };
+        if value.is_some() {
+            self.count -= 1;
+        }
+        value
+    }
+}
+
+impl<T> Default for CuckooHash<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[test]
+fn test_cuckoo_basics() {
+    let mut hashtab = CuckooHash::new();
+    assert_eq!(hashtab.lookup("abc"), None);
+    assert!(hashtab.upsert("abc", 64));
+    assert!(!hashtab.upsert("abc", 256));
+    assert_eq!(hashtab.lookup("abc"), Some(&256));
+    assert_eq!(hashtab.remove("abc"), Some(256));
+    assert_eq!(hashtab.remove("abc"), None);
+    assert!(hashtab.is_empty());
+}
+
+#[test]
+fn test_cuckoo_growth() {
+    let mut hashtab = CuckooHash::new();
+    let n_entries = 20000;
+
+    for i in 0..n_entries {
+        assert!(hashtab.upsert(&i.to_string(), i));
+    }
+    assert_eq!(hashtab.len(), n_entries);
+    assert!(hashtab.capacity() >= 2 * n_entries);
+
+    for i in 0..n_entries {
+        assert_eq!(hashtab.lookup(&i.to_string()), Some(&i));
+    }
+    for i in (0..n_entries).filter(|i| i % 2 == 0) {
+        assert_eq!(hashtab.remove(&i.to_string()), Some(i));
+    }
+    assert_eq!(hashtab.lookup("2"), None);
+    assert_eq!(hashtab.lookup("3"), Some(&3));
+}
+
+#[test]
+fn test_cuckoo_stash() {
+    // Slot indexes come from the low bits of each seeded hash, so keys whose
+    // hashes agree in the low bits of both collide in both tables
+    let mask = (INITIAL_SLOTS - 1) as u64;
+    let key_hash = |name: &str, t: usize| mix64(hash_str_seeded(name, SEEDS[t])) & mask;
+    let colliding: Vec<String> = (0..)
+        .map(|i: u32| i.to_string())
+        .filter(|name| key_hash(name, 0) == 0 && key_hash(name, 1) == 0)
+        .take(3)
+        .collect();
+
+    let mut hashtab = CuckooHash::new();
+    for (i, name) in colliding.iter().enumerate() {
+        assert!(hashtab.upsert(name, i));
+    }
+    assert_eq!(hashtab.stashed(), 1);
+    assert_eq!(hashtab.capacity(), 2 * INITIAL_SLOTS);
+    for (i, name) in colliding.iter().enumerate() {
+        assert_eq!(hashtab.lookup(name), Some(&i));
+    }
+    assert!(!hashtab.upsert(&colliding[2], 9));
+    assert_eq!(hashtab.remove(&colliding[2]), Some(9));
+    assert_eq!(hashtab.remove(&colliding[2]), None);
+    assert_eq!(hashtab.len(), 2);
+}
@@ -0,0 +1,189 @@
+// One error type for the whole crate, so code calling into several of its
+// fallible APIs can `?` them all into a single Result. Each module keeps its
+// own error type, which says exactly what can go wrong there, and converts
+// into this one. Where the module's error hands back a value (a rejected
+// CAS, a refused insert) the conversion drops it; match on the module's
+// error first to get it back.
+
+use std::collections::TryReserveError;
+use std::error;
+use std::fmt;
+use std::io;
+use std::num::ParseIntError;
+use std::result;
+
+use bounded::Backpressure;
+use hash::{CasError, StaleCursor};
+use oplog::Gap;
+use perfect::BuildError;
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    // try_reserve() or try_upsert() couldn't allocate
+    Alloc(TryReserveError),
+    // Reading or writing a snapshot or a log
+    Io(io::Error),
+    // cas() on a key that isn't in the table
+    NotFound,
+    // cas() found a different value from the expected one
+    Mismatch,
+    StaleCursor,
+    // A BoundedHash refused an insert
+    Backpressure,
+    // A Follower was given a record out of order
+    Gap(Gap),
+    // PerfectHash::build() failed
+    Build(BuildError),
+    // A table was asked for with settings it can't work with
+    InvalidConfig(String),
+    // bits::parse_binary() and friends were given something that isn't a
+    // number
+    Parse(ParseIntError),
+    // A key longer than the table's configured max_key_len
+    KeyTooLong { len: usize, max: usize },
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Alloc(_) => write!(f, "out of memory"),
+            Error::Io(_) => write!(f, "I/O error"),
+            Error::NotFound => write!(f, "key not found"),
+            Error::Mismatch => write!(f, "value changed"),
+            Error::StaleCursor => write!(f, "{}", StaleCursor),
+            Error::Backpressure => write!(f, "eviction is falling behind"),
+            Error::Gap(_) => write!(f, "replication log out of order"),
+            Error::Build(_) => write!(f, "cannot build perfect hash"),
+            Error::InvalidConfig(ref why) => write!(f, "invalid configuration: {}", why),
+            Error::Parse(_) => write!(f, "invalid number"),
+            Error::KeyTooLong { len, max } => {
+                write!(f, "key of {} bytes is over the limit of {}", len, max)
+            }
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Alloc(ref e) => Some(e),
+            Error::Io(ref e) => Some(e),
+            Error::Gap(ref e) => Some(e),
+            Error::Build(ref e) => Some(e),
+            Error::Parse(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<TryReserveError> for Error {
+    fn from(e: TryReserveError) -> Self {
+        Error::Alloc(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl<T> From<CasError<T>> for Error {
+    fn from(e: CasError<T>) -> Self {
+        match e {
+            CasError::NotFound(_) => Error::NotFound,
+            CasError::Mismatch { .. } => Error::Mismatch,
+        }
+    }
+}
+
+impl From<StaleCursor> for Error {
+    fn from(_: StaleCursor) -> Self {
+        Error::StaleCursor
+    }
+}
+
+impl<T> From<Backpressure<T>> for Error {
+    fn from(_: Backpressure<T>) -> Self {
+        Error::Backpressure
+    }
+}
+
+impl From<Gap> for Error {
+    fn from(e: Gap) -> Self {
+        Error::Gap(e)
+    }
+}
+
+impl From<BuildError> for Error {
+    fn from(e: BuildError) -> Self {
+        Error::Build(e)
+    }
+}
+
+impl From<ParseIntError> for Error {
+    fn from(e: ParseIntError) -> Self {
+        Error::Parse(e)
+    }
+}
+
+#[test]
+fn test_error_propagation() {
+    use std::error::Error as StdError;
+
+    use bits;
+    use hash::Hash;
+    use oplog::{Follower, Op, Record};
+    use perfect::PerfectHash;
+
+    fn restore_and_bump(snapshot: &[u8]) -> Result<Hash<String>> {
+        let mut hash = Hash::read_snapshot(snapshot)?;
+        hash.try_reserve(10)?;
+        hash.cas("counter", &"1".to_string(), "2".to_string())?;
+        Ok(hash)
+    }
+
+    let mut hash = Hash::new();
+    hash.upsert("counter", "1".to_string());
+    let mut snapshot = Vec::new();
+    hash.write_snapshot(&mut snapshot).unwrap();
+    let bumped = restore_and_bump(&snapshot).unwrap();
+    assert_eq!(bumped.lookup("counter").map(|s| &s[..]), Some("2"));
+
+    // The first failure comes out, with what caused it behind it
+    let e = restore_and_bump(&snapshot[..snapshot.len() - 1]).unwrap_err();
+    assert_eq!(e.to_string(), "I/O error");
+    let cause = e.source().unwrap().downcast_ref::<io::Error>().unwrap();
+    assert_eq!(cause.kind(), io::ErrorKind::UnexpectedEof);
+
+    let mut stale = Hash::new();
+    stale.upsert("counter", "9".to_string());
+    let mut snapshot = Vec::new();
+    stale.write_snapshot(&mut snapshot).unwrap();
+    let e = restore_and_bump(&snapshot).unwrap_err();
+    assert!(matches!(e, Error::Mismatch));
+    assert!(e.source().is_none());
+
+    let replay = || -> Result<usize> {
+        let mut follower: Follower<String> = Follower::new();
+        let record = Record { seq: 2, op: Op::Remove("a".to_string()) };
+        Ok(follower.apply_op_log(&[record])?)
+    };
+    let e = replay().unwrap_err();
+    assert_eq!(e.source().unwrap().to_string(), "expected record 1, got 2");
+
+    let build = || -> Result<PerfectHash<i32>> {
+        Ok(PerfectHash::build(vec![("a", 1), ("a", 2)])?)
+    };
+    let e = build().err().unwrap();
+    assert_eq!(e.source().unwrap().to_string(), "duplicate key `a`");
+
+    let mask = |s: &str| -> Result<usize> { Ok(bits::parse_hex(s)? & 0xff) };
+    assert_eq!(mask("0x1234").unwrap(), 0x34);
+    let e = mask("0xzz").unwrap_err();
+    assert_eq!(e.to_string(), "invalid number");
+    assert!(e.source().unwrap().is::<ParseIntError>());
+}
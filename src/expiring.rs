@@ -0,0 +1,197 @@
+// A table whose entries expire: every write gives its key a time to live,
+// after which lookups no longer see it. Expired entries are skipped lazily
+// and only removed by purge_expired(), so expiry costs nothing until the
+// caller chooses to pay for it. With jitter, each key's TTL is stretched or
+// shrunk by a random fraction, so keys written together with the same TTL
+// don't all expire in the same instant and cause a burst of reloads.
+//
+// A TTL too long to add to the current time, like Duration::MAX, means the
+// key never expires.
+//
+// The *_at methods take the current time as an argument, for callers with
+// a clock of their own (and for tests); the rest use Instant::now().
+
+use std::time::{Duration, Instant};
+
+use hash::Hash;
+
+pub struct ExpiringHash<T: Clone> {
+    // Each value with the moment it expires, None for never
+    hash: Hash<(Option<Instant>, T)>,
+    // Percentage a TTL may move either way
+    jitter: u32,
+    // xorshift64 state for picking jitter
+    random: u64,
+}
+
+impl<T> ExpiringHash<T>
+where
+    T: Clone,
+{
+    pub fn new() -> Self {
+        ExpiringHash {
+            hash: Hash::new(),
+            jitter: 0,
+            random: 0,
+        }
+    }
+
+    // Every TTL moves by up to `percent` either way, picked per key on
+    // insertion; TTLs average out at what was asked for. `seed` makes the
+    // choices repeatable.
+    pub fn with_jitter(mut self, percent: u32, seed: u64) -> Self {
+        assert!(percent <= 100, "jitter can't be more than 100%");
+        self.jitter = percent;
+        // xorshift gets stuck at zero
+        self.random = seed | 1;
+        self
+    }
+
+    // Entries stored, expired ones included until they're purged
+    pub fn len(&self) -> usize {
+        self.hash.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hash.is_empty()
+    }
+
+    fn next_random(&mut self) -> u64 {
+        self.random ^= self.random << 13;
+        self.random ^= self.random >> 7;
+        self.random ^= self.random << 17;
+        self.random
+    }
+
+    // `ttl` with this table's jitter applied
+    fn jittered(&mut self, ttl: Duration) -> Duration {
+        if self.jitter == 0 {
+            return ttl;
+        }
+        let nanos = ttl.as_nanos().min(u64::MAX as u128) as u64;
+        let span = nanos / 100 * self.jitter as u64;
+        let offset = self.next_random() % span.saturating_mul(2).saturating_add(1);
+        Duration::from_nanos((nanos - span).saturating_add(offset))
+    }
+
+    pub fn upsert_at(&mut self, name: &str, value: T, ttl: Duration, now: Instant) -> bool {
+        let ttl = self.jittered(ttl);
+        self.hash.upsert(name, (now.checked_add(ttl), value))
+    }
+
+    pub fn upsert(&mut self, name: &str, value: T, ttl: Duration) -> bool {
+        self.upsert_at(name, value, ttl, Instant::now())
+    }
+
+    pub fn lookup_at(&self, name: &str, now: Instant) -> Option<&T> {
+        match self.hash.lookup(name) {
+            Some(&(expires, ref value)) if expires.is_none_or(|at| at > now) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<&T> {
+        self.lookup_at(name, Instant::now())
+    }
+
+    // When `name` expires; None if it isn't there or never expires
+    pub fn expires_at(&self, name: &str) -> Option<Instant> {
+        self.hash.lookup(name).and_then(|entry| entry.0)
+    }
+
+    // Returns the value even if it had already expired
+    pub fn remove(&mut self, name: &str) -> Option<T> {
+        self.hash.remove(name).map(|entry| entry.1)
+    }
+
+    // Removes everything that has expired by `now`; returns how many
+    pub fn purge_expired_at(&mut self, now: Instant) -> usize {
+        let expired: Vec<String> = self
+            .hash
+            .iter()
+            .filter(|&(_, entry)| entry.0.is_some_and(|at| at <= now))
+            .map(|(name, _)| name.to_string())
+            .collect();
+        for name in &expired {
+            self.hash.remove(name);
+        }
+        expired.len()
+    }
+
+    pub fn purge_expired(&mut self) -> usize {
+        self.purge_expired_at(Instant::now())
+    }
+}
+
+impl<T> Default for ExpiringHash<T>
+where
+    T: Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[test]
+fn test_expiry() {
+    let start = Instant::now();
+    let secs = Duration::from_secs;
+    let mut cache = ExpiringHash::new();
+    cache.upsert_at("short", 1, secs(10), start);
+    cache.upsert_at("long", 2, secs(100), start);
+
+    assert_eq!(cache.lookup_at("short", start + secs(9)), Some(&1));
+    assert_eq!(cache.lookup_at("short", start + secs(10)), None);
+    assert_eq!(cache.lookup_at("long", start + secs(50)), Some(&2));
+    assert_eq!(cache.expires_at("short"), Some(start + secs(10)));
+
+    // Expired entries stay until purged
+    assert_eq!(cache.len(), 2);
+    assert_eq!(cache.purge_expired_at(start + secs(50)), 1);
+    assert_eq!(cache.len(), 1);
+
+    // Rewriting a key starts its TTL again
+    cache.upsert_at("long", 3, secs(100), start + secs(90));
+    assert_eq!(cache.lookup_at("long", start + secs(150)), Some(&3));
+    assert_eq!(cache.remove("long"), Some(3));
+
+    // Too long to add to an Instant: never expires
+    cache.upsert_at("forever", 4, Duration::MAX, start);
+    assert_eq!(cache.expires_at("forever"), None);
+    assert_eq!(cache.lookup_at("forever", start + secs(1 << 40)), Some(&4));
+    assert_eq!(cache.purge_expired_at(start + secs(1 << 40)), 0);
+    assert_eq!(cache.lookup("forever"), Some(&4));
+    let mut jittered = ExpiringHash::new().with_jitter(10, 1);
+    assert!(jittered.upsert("forever", 4, Duration::MAX));
+}
+
+#[test]
+fn test_ttl_jitter() {
+    let start = Instant::now();
+    let ttl = Duration::from_secs(100);
+    let mut cache = ExpiringHash::new().with_jitter(10, 42);
+    for i in 0..1000 {
+        cache.upsert_at(&i.to_string(), i, ttl, start);
+    }
+
+    let mut expiries: Vec<Duration> =
+        (0..1000).map(|i| cache.expires_at(&i.to_string()).unwrap() - start).collect();
+    expiries.sort();
+    // All within 10%, spread across the range rather than bunched together
+    assert!(expiries[0] >= Duration::from_secs(90));
+    assert!(expiries[999] <= Duration::from_secs(110));
+    assert!(expiries[999] - expiries[0] > Duration::from_secs(19));
+    expiries.dedup();
+    assert!(expiries.len() > 990);
+
+    let at_100 = cache.purge_expired_at(start + ttl);
+    assert!(at_100 > 400 && at_100 < 600, "{} expired at the nominal TTL", at_100);
+
+    // Without jitter they all go at once
+    let mut cache = ExpiringHash::new();
+    for i in 0..100 {
+        cache.upsert_at(&i.to_string(), i, ttl, start);
+    }
+    assert_eq!(cache.purge_expired_at(start + ttl - Duration::from_nanos(1)), 0);
+    assert_eq!(cache.purge_expired_at(start + ttl), 100);
+}
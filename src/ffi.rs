@@ -0,0 +1,211 @@
+// C interface over opaque `Hash` handles. Keys are passed as (pointer,
+// length) byte strings, which may hold any bytes; values are untyped
+// pointers owned by the caller. The matching declarations live in
+// include/stupid_hash.h. The library is built with
+// `cargo rustc --lib --release --features ffi --crate-type cdylib` (or
+// `staticlib`), so that ordinary builds of the crate don't link a shared
+// library nobody asked for.
+//
+// Safety: every function taking a handle expects either null or a pointer
+// returned by stupid_hash_new() that has not been freed yet, and a key
+// pointer valid for `key_len` bytes (or null when `key_len` is 0).
+
+#![allow(clippy::missing_safety_doc)]
+
+use std::borrow::Cow;
+use std::os::raw::{c_int, c_void};
+#[cfg(test)]
+use std::ptr;
+use std::slice;
+use std::str;
+
+use hash::Hash;
+
+pub type StupidHash = Hash<*mut c_void>;
+
+pub const STUPID_HASH_EXISTED: c_int = 0;
+pub const STUPID_HASH_INSERTED: c_int = 1;
+pub const STUPID_HASH_NOT_FOUND: c_int = 0;
+pub const STUPID_HASH_FOUND: c_int = 1;
+pub const STUPID_HASH_EINVAL: c_int = -1;
+
+// The table's keys are strs, but a C caller's can be any bytes. UTF-8 keys
+// are stored as they are. Any other key, and a UTF-8 one starting with NUL,
+// is stored as a NUL followed by each of its bytes as the char of the same
+// value. Plain keys never start with NUL, so no two byte strings end up as
+// the same key.
+unsafe fn key<'a>(key: *const u8, key_len: usize) -> Option<Cow<'a, str>> {
+    let bytes: &[u8] = if key.is_null() {
+        // An empty key may come in as a null pointer
+        if key_len != 0 {
+            return None;
+        }
+        &[]
+    } else {
+        slice::from_raw_parts(key, key_len)
+    };
+
+    match str::from_utf8(bytes) {
+        Ok(name) if !name.starts_with('\0') => Some(Cow::Borrowed(name)),
+        _ => {
+            let mut escaped = String::with_capacity(1 + 2 * bytes.len());
+            escaped.push('\0');
+            escaped.extend(bytes.iter().map(|&b| b as char));
+            Some(Cow::Owned(escaped))
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn stupid_hash_new() -> *mut StupidHash {
+    Box::into_raw(Box::new(Hash::new()))
+}
+
+// Frees the table itself; the values it points to are left alone.
+#[no_mangle]
+pub unsafe extern "C" fn stupid_hash_free(hash: *mut StupidHash) {
+    if !hash.is_null() {
+        drop(Box::from_raw(hash));
+    }
+}
+
+// Returns STUPID_HASH_INSERTED for a new key, STUPID_HASH_EXISTED if an
+// existing value was overwritten.
+#[no_mangle]
+pub unsafe extern "C" fn stupid_hash_insert(
+    hash: *mut StupidHash,
+    key_ptr: *const u8,
+    key_len: usize,
+    value: *mut c_void,
+) -> c_int {
+    let (hash, name) = match (hash.as_mut(), key(key_ptr, key_len)) {
+        (Some(hash), Some(name)) => (hash, name),
+        _ => return STUPID_HASH_EINVAL,
+    };
+
+    if hash.upsert(&name, value) {
+        STUPID_HASH_INSERTED
+    } else {
+        STUPID_HASH_EXISTED
+    }
+}
+
+// Stores the value for the key in `*out` (if `out` is non-null) and returns
+// STUPID_HASH_FOUND, or returns STUPID_HASH_NOT_FOUND.
+#[no_mangle]
+pub unsafe extern "C" fn stupid_hash_lookup(
+    hash: *const StupidHash,
+    key_ptr: *const u8,
+    key_len: usize,
+    out: *mut *mut c_void,
+) -> c_int {
+    let (hash, name) = match (hash.as_ref(), key(key_ptr, key_len)) {
+        (Some(hash), Some(name)) => (hash, name),
+        _ => return STUPID_HASH_EINVAL,
+    };
+
+    match hash.lookup(&name) {
+        Some(value) => {
+            if !out.is_null() {
+                *out = *value;
+            }
+            STUPID_HASH_FOUND
+        }
+        None => STUPID_HASH_NOT_FOUND,
+    }
+}
+
+// Like stupid_hash_lookup, but also removes the entry.
+#[no_mangle]
+pub unsafe extern "C" fn stupid_hash_remove(
+    hash: *mut StupidHash,
+    key_ptr: *const u8,
+    key_len: usize,
+    out: *mut *mut c_void,
+) -> c_int {
+    let (hash, name) = match (hash.as_mut(), key(key_ptr, key_len)) {
+        (Some(hash), Some(name)) => (hash, name),
+        _ => return STUPID_HASH_EINVAL,
+    };
+
+    match hash.remove(&name) {
+        Some(value) => {
+            if !out.is_null() {
+                *out = value;
+            }
+            STUPID_HASH_FOUND
+        }
+        None => STUPID_HASH_NOT_FOUND,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn stupid_hash_len(hash: *const StupidHash) -> usize {
+    hash.as_ref().map_or(0, |hash| hash.len())
+}
+
+#[test]
+fn test_ffi_roundtrip() {
+    let mut a = 1;
+    let mut b = 2;
+    let pa = &mut a as *mut i32 as *mut c_void;
+    let pb = &mut b as *mut i32 as *mut c_void;
+    let mut out = ptr::null_mut();
+
+    unsafe {
+        let h = stupid_hash_new();
+        assert_eq!(stupid_hash_insert(h, b"abc".as_ptr(), 3, pa), STUPID_HASH_INSERTED);
+        assert_eq!(stupid_hash_insert(h, b"abc".as_ptr(), 3, pb), STUPID_HASH_EXISTED);
+        assert_eq!(stupid_hash_len(h), 1);
+
+        assert_eq!(stupid_hash_lookup(h, b"abc".as_ptr(), 3, &mut out), STUPID_HASH_FOUND);
+        assert_eq!(out, pb);
+        assert_eq!(stupid_hash_lookup(h, b"abd".as_ptr(), 3, &mut out), STUPID_HASH_NOT_FOUND);
+
+        assert_eq!(stupid_hash_remove(h, b"abc".as_ptr(), 3, &mut out), STUPID_HASH_FOUND);
+        assert_eq!(out, pb);
+        assert_eq!(stupid_hash_len(h), 0);
+        stupid_hash_free(h);
+    }
+}
+
+#[test]
+fn test_ffi_byte_keys() {
+    let mut values = [0i32; 4];
+    let p: Vec<*mut c_void> = values.iter_mut().map(|v| v as *mut i32 as *mut c_void).collect();
+    // Not UTF-8; what its escaped form looks like, spelled in UTF-8; and a
+    // UTF-8 key starting with NUL
+    let keys: [&[u8]; 4] = [b"\xff\xfe", "\0\u{ff}\u{fe}".as_bytes(), b"\0ab", b"ab"];
+    let mut out = ptr::null_mut();
+
+    unsafe {
+        let h = stupid_hash_new();
+        for (key, &value) in keys.iter().zip(&p) {
+            assert_eq!(stupid_hash_insert(h, key.as_ptr(), key.len(), value), STUPID_HASH_INSERTED);
+        }
+        assert_eq!(stupid_hash_len(h), 4);
+        for (key, &value) in keys.iter().zip(&p) {
+            assert_eq!(stupid_hash_lookup(h, key.as_ptr(), key.len(), &mut out), STUPID_HASH_FOUND);
+            assert_eq!(out, value);
+        }
+
+        assert_eq!(stupid_hash_remove(h, keys[0].as_ptr(), 2, &mut out), STUPID_HASH_FOUND);
+        assert_eq!(out, p[0]);
+        assert_eq!(stupid_hash_lookup(h, keys[0].as_ptr(), 2, &mut out), STUPID_HASH_NOT_FOUND);
+        assert_eq!(stupid_hash_len(h), 3);
+        stupid_hash_free(h);
+    }
+}
+
+#[test]
+fn test_ffi_invalid_input() {
+    unsafe {
+        let h = stupid_hash_new();
+        assert_eq!(stupid_hash_insert(h, ptr::null(), 4, ptr::null_mut()), STUPID_HASH_EINVAL);
+        assert_eq!(stupid_hash_insert(h, ptr::null(), 0, ptr::null_mut()), STUPID_HASH_INSERTED);
+        assert_eq!(stupid_hash_insert(ptr::null_mut(), b"a".as_ptr(), 1, ptr::null_mut()), STUPID_HASH_EINVAL);
+        assert_eq!(stupid_hash_len(ptr::null()), 0);
+        stupid_hash_free(h);
+        stupid_hash_free(ptr::null_mut());
+    }
+}
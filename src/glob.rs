@@ -0,0 +1,159 @@
+// Shell-style wildcard patterns for key scans: `*` matches any run of
+// characters, `?` any single character, `[abc]`, `[a-z]` and `[!abc]` one
+// character from (or not from) a set, and `\` escapes the next character. An
+// unclosed `[` is taken literally.
+
+#[derive(Clone, Debug)]
+enum Token {
+    Literal(char),
+    AnyChar,
+    Star,
+    Class { negated: bool, ranges: Vec<(char, char)> },
+}
+
+impl Token {
+    fn matches(&self, c: char) -> bool {
+        match *self {
+            Token::Literal(l) => l == c,
+            Token::AnyChar => true,
+            Token::Star => false,
+            Token::Class {
+                negated,
+                ref ranges,
+            } => ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi) != negated,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Pattern {
+    tokens: Vec<Token>,
+}
+
+// Parses a class body starting just after `[`; returns the token and the
+// number of characters consumed, or None if the class is never closed.
+fn parse_class(chars: &[char]) -> Option<(Token, usize)> {
+    let mut i = 0;
+    let negated = chars.first() == Some(&'!') || chars.first() == Some(&'^');
+    if negated {
+        i += 1;
+    }
+
+    let mut ranges = Vec::new();
+    let start = i;
+    while i < chars.len() {
+        let c = chars[i];
+        // A `]` right at the start is part of the set
+        if c == ']' && i > start {
+            return Some((Token::Class { negated, ranges }, i + 1));
+        }
+        if i + 2 < chars.len() && chars[i + 1] == '-' && chars[i + 2] != ']' {
+            ranges.push((c, chars[i + 2]));
+            i += 3;
+        } else {
+            ranges.push((c, c));
+            i += 1;
+        }
+    }
+
+    None
+}
+
+impl Pattern {
+    pub fn new(pattern: &str) -> Self {
+        let chars: Vec<char> = pattern.chars().collect();
+        let mut tokens = Vec::new();
+        let mut i = 0;
+
+        while i < chars.len() {
+            let token = match chars[i] {
+                '*' => {
+                    // Runs of stars mean the same as one
+                    if let Some(&Token::Star) = tokens.last() {
+                        i += 1;
+                        continue;
+                    }
+                    Token::Star
+                }
+                '?' => Token::AnyChar,
+                '\\' if i + 1 < chars.len() => {
+                    i += 1;
+                    Token::Literal(chars[i])
+                }
+                '[' => match parse_class(&chars[i + 1..]) {
+                    Some((class, used)) => {
+                        i += used;
+                        class
+                    }
+                    None => Token::Literal('['),
+                },
+                c => Token::Literal(c),
+            };
+            tokens.push(token);
+            i += 1;
+        }
+
+        Pattern { tokens }
+    }
+
+    pub fn matches(&self, text: &str) -> bool {
+        let text: Vec<char> = text.chars().collect();
+        let (mut t, mut p) = (0, 0);
+        // Where to resume if the current attempt fails: the token after the
+        // last star, and the text position that star is now extended to
+        let mut backtrack: Option<(usize, usize)> = None;
+
+        while t < text.len() {
+            match self.tokens.get(p) {
+                Some(&Token::Star) => {
+                    p += 1;
+                    backtrack = Some((p, t));
+                    continue;
+                }
+                Some(token) if token.matches(text[t]) => {
+                    p += 1;
+                    t += 1;
+                    continue;
+                }
+                _ => {}
+            }
+
+            match backtrack {
+                Some((bp, bt)) => {
+                    p = bp;
+                    t = bt + 1;
+                    backtrack = Some((bp, bt + 1));
+                }
+                None => return false,
+            }
+        }
+
+        self.tokens[p..].iter().all(|token| matches!(*token, Token::Star))
+    }
+}
+
+#[test]
+fn test_glob_patterns() {
+    let cases = [
+        ("user:*:session", "user:42:session", true),
+        ("user:*:session", "user::session", true),
+        ("user:*:session", "user:42:sessions", false),
+        ("*", "", true),
+        ("a*b*c", "axxbyyc", true),
+        ("a*b*c", "axxbyy", false),
+        ("?at", "cat", true),
+        ("?at", "at", false),
+        ("[ch]at", "hat", true),
+        ("[!ch]at", "hat", false),
+        ("[a-c]x", "bx", true),
+        ("[]]", "]", true),
+        ("\\*", "*", true),
+        ("\\*", "a", false),
+        ("[abc", "[abc", true),
+        ("ü*", "über", true),
+    ];
+
+    for &(pattern, text, expected) in &cases {
+        assert_eq!(Pattern::new(pattern).matches(text), expected, "{} vs {}", pattern, text);
+    }
+}
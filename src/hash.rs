@@ -1,188 +1,3599 @@
 const NHASH: usize = 32;
 const MULTIPLIER: usize = 31;
+// Seed for the second hash function in two-choice mode
+const SECOND_CHOICE_SEED: usize = 0x9e37_79b9;
+// Average bucket length reserve() grows the table for under
+// SplitPolicy::BucketOverflow, which has no load target of its own
+const RESERVE_LOAD: usize = 2;
+// Entries per bucket the default policy lets the table reach before a split
+const DEFAULT_MAX_LOAD: f64 = 2.0;
+// Entries an incremental split moves per insert or removal
+const MIGRATE_STEP: usize = 8;
+// Under a load factor policy, removals merge buckets back together once the
+// load drops below this fraction of the maximum
+const MIN_LOAD_FRACTION: f64 = 0.25;
 
+use std::collections::TryReserveError;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::iter;
 use std::mem;
+use std::slice;
+use std::thread;
+use std::vec;
 
-#[derive(Clone, Debug)]
+use bits;
+use config::{Eviction, HashConfig, SplitMode};
+use error;
+use glob::Pattern;
+use key::{self, Key};
+use oplog::{self, LogValue};
+use trie::Trie;
+
+#[derive(Debug)]
 pub struct NameVal<T: Clone> {
-    name: String,
+    name: Key,
     value: T,
 }
 
+impl<T> Clone for NameVal<T>
+where
+    T: Clone,
+{
+    fn clone(&self) -> Self {
+        NameVal {
+            name: self.name.clone(),
+            value: self.value.clone(),
+        }
+    }
+
+    // Reuses the key's buffer (and whatever the value can reuse)
+    fn clone_from(&mut self, source: &Self) {
+        self.name.clone_from(&source.name);
+        self.value.clone_from(&source.value);
+    }
+}
+
+impl<T> NameVal<T>
+where
+    T: Clone,
+{
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn value(&self) -> &T {
+        &self.value
+    }
+
+    pub fn value_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+// When the table grows by another bucket
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SplitPolicy {
+    // Controlled splitting: split whenever the average bucket length goes
+    // over the given load. Chains stay short however the keys fall.
+    LoadFactor(f64),
+    // Split only when an insert makes one bucket longer than 1 << bits
+    // entries. This is how the table always used to grow; it lets chains
+    // get very long before anything happens.
+    BucketOverflow,
+}
+
+impl Default for SplitPolicy {
+    fn default() -> Self {
+        SplitPolicy::LoadFactor(DEFAULT_MAX_LOAD)
+    }
+}
+
 pub struct Hash<T: Clone> {
     table: Vec<Vec<NameVal<T>>>,
     bits: usize,
     split_bucket: usize,
+    count: usize,
+    // Bumped whenever entries are added, removed or moved between buckets
+    generation: u64,
+    split_policy: SplitPolicy,
+    two_choice: bool,
+    incremental: bool,
+    // Splits wait for maintain() instead of happening in upsert
+    deferred: bool,
+    // The split whose entries are still being moved, in incremental mode
+    migration: Option<Migration>,
+    prefix_index: Option<Trie>,
+    // The table never merges below this many buckets
+    min_buckets: usize,
+    hashing: Hashing,
+    // Longest key upsert() accepts, in bytes
+    max_key_len: usize,
+}
+
+// An incremental split in progress. The new bucket `to` already takes new
+// keys, but entries that belong in it may still sit in `from`, which is
+// scanned a few entries at a time starting at `next`.
+#[derive(Clone, Copy, Debug)]
+struct Migration {
+    from: usize,
+    to: usize,
+    next: usize,
 }
 
+#[deprecated(note = "use bits::binary")]
 pub fn bit_string(bits: usize) -> String {
-    let mut bstr: String = "".to_string();
-    for i in 0..(mem::size_of::<usize>()*8) {
-        let mask = 1 << i;
-        bstr.push(if mask & bits == 0 { '0' } else { '1' });
+    bits::binary(bits)
+}
+
+// Version of the hash hash_key() computes. It only changes if the function
+// does, so anything that stores or shares hashes can check it.
+pub const HASH_VERSION: u32 = 1;
+
+// The hash the table computes for a key, before it is masked down to a
+// bucket index: h = h * 31 + byte over the key's UTF-8 bytes, starting from
+// 0 and wrapping at 64 bits. This is stable for a given HASH_VERSION, so shard
+// routers and on-disk formats can compute the same values. How the hash is
+// turned into a bucket index is internal and may change at any time. On
+// 32-bit targets the table uses the low 32 bits.
+pub fn hash_key(name: &str) -> u64 {
+    name.bytes()
+        .fold(0u64, |h, p| h.wrapping_mul(MULTIPLIER as u64).wrapping_add(p as u64))
+}
+
+pub(crate) fn hash_str(name: &str) -> usize {
+    hash_str_seeded(name, 0)
+}
+
+// A family of variants of the table's hash for structures that need several
+// independent-ish hash functions. Each byte is xored with the seed before it
+// is mixed in, so keys that collide under one seed usually don't under
+// another. Seed 0 is the table's own hash.
+pub(crate) fn hash_str_seeded(name: &str, seed: usize) -> usize {
+    hash_str_with(name, MULTIPLIER, seed)
+}
+
+#[inline]
+fn hash_str_with(name: &str, multiplier: usize, seed: usize) -> usize {
+    let mut h: usize = seed;
+
+    for p in name.bytes() {
+        let p = p as usize ^ seed;
+        // Instead of silently wrapping (like most C implementations do,
+        // even if that is strictly undefined), rust panics if we overflow
+        // an integer value. So we need to use this magic instead.
+        h = h.wrapping_mul(multiplier).wrapping_add(p);
+    }
+
+    h
+}
+
+// The hash function a table uses. By default that's hash_str(), which is
+// hash_key(); a HashConfig can pick another multiplier and seed, so keys
+// that pile up in one bucket under one setting spread out under another.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Hashing {
+    multiplier: usize,
+    seed: usize,
+}
+
+impl Hashing {
+    const DEFAULT: Hashing = Hashing {
+        multiplier: MULTIPLIER,
+        seed: 0,
+    };
+
+    fn key(self, name: &str) -> usize {
+        // The constant multiplier is cheaper than a variable one
+        if self == Hashing::DEFAULT {
+            hash_str(name)
+        } else {
+            hash_str_with(name, self.multiplier, self.seed)
+        }
+    }
+
+    // The second function in two-choice mode
+    fn second(self, name: &str) -> usize {
+        hash_str_with(name, self.multiplier, self.seed ^ SECOND_CHOICE_SEED)
+    }
+}
+
+// The multiplier hash of a short key leaves the high bits mostly zero. That
+// is fine for masking off bucket indexes, but structures that look at every
+// bit (leading zeros, top bits) run it through this finalizer (MurmurHash3's
+// fmix64) first.
+pub(crate) fn mix64(h: usize) -> u64 {
+    let mut h = h as u64;
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    h ^= h >> 33;
+    h
+}
+
+fn worker_threads() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+// Levenshtein distance between `a` and `b`, or None once it's certain to
+// exceed `max`
+fn edit_distance(a: &[char], b: &str, max: usize) -> Option<usize> {
+    let b: Vec<char> = b.chars().collect();
+    let diff = if a.len() > b.len() { a.len() - b.len() } else { b.len() - a.len() };
+    if diff > max {
+        return None;
+    }
+
+    let mut prev: Vec<usize> = (0..b.len() + 1).collect();
+    let mut cur = vec![0; b.len() + 1];
+    for (i, ca) in a.iter().enumerate() {
+        cur[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitute = prev[j] + if ca == cb { 0 } else { 1 };
+            cur[j + 1] = substitute.min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        if cur.iter().all(|&d| d > max) {
+            return None;
+        }
+        mem::swap(&mut prev, &mut cur);
+    }
+
+    Some(prev[b.len()]).filter(|&d| d <= max)
+}
+
+// Masks a full hash down to a bucket index for a table of `len` buckets
+// addressed with `bits` bits.
+pub(crate) fn bucket_index(h: usize, bits: usize, len: usize) -> usize {
+    let m = h & ((1 << bits) - 1);
+    if m < len {
+        m
+    } else {
+        m ^ (1 << (bits - 1))
+    }
+}
+
+// Returns the (bits, split_bucket) pair to use once a split has grown the
+// table to `len` buckets. Buckets are split in order; when the table reaches
+// a power of two the round is over, the address space doubles and splitting
+// starts again from bucket 0.
+pub(crate) fn next_split(bits: usize, split_bucket: usize, len: usize) -> (usize, usize) {
+    let bits = if len - 1 == 1 << bits { bits + 1 } else { bits };
+    if len == 1 << bits {
+        (bits, 0)
+    } else {
+        (bits, split_bucket + 1)
+    }
+}
+
+// The reverse of next_split(): returns the (bits, split_bucket) pair once a
+// merge has shrunk the table to `len` buckets. The bucket that was removed
+// is folded into the new split_bucket, which is the one it was split from.
+pub(crate) fn prev_split(bits: usize, split_bucket: usize, len: usize) -> (usize, usize) {
+    if split_bucket == 0 {
+        (bits, len - (1 << (bits - 1)))
+    } else if len == 1 << (bits - 1) {
+        (bits - 1, 0)
+    } else {
+        (bits, split_bucket - 1)
+    }
+}
+
+// Asks the CPU to start loading a bucket's entries and its first key, so
+// they are on their way into cache before they are compared. Only does
+// anything with the `prefetch` feature on x86_64; elsewhere it compiles to
+// nothing.
+#[inline(always)]
+fn prefetch<T: Clone>(bucket: &[NameVal<T>]) {
+    #[cfg(all(feature = "prefetch", target_arch = "x86_64"))]
+    {
+        use std::arch::x86_64::{_mm_prefetch, _MM_HINT_T0};
+
+        // A prefetch is only a hint and never faults. Older compilers still
+        // want it in an unsafe block.
+        #[allow(unused_unsafe)]
+        unsafe {
+            _mm_prefetch(bucket.as_ptr() as *const i8, _MM_HINT_T0);
+            if let Some(entry) = bucket.first() {
+                _mm_prefetch(entry.name.as_ptr() as *const i8, _MM_HINT_T0);
+            }
+        }
+    }
+    #[cfg(not(all(feature = "prefetch", target_arch = "x86_64")))]
+    let _ = bucket;
+}
+
+// Gives back a bucket's memory once it is mostly unused: all of it when the
+// bucket is empty, half of it at a quarter full. Waiting that long keeps a
+// bucket hovering around one size from reallocating on every change.
+// Shrinking takes a new, smaller buffer; if there's no memory for one the
+// bucket keeps the buffer it has, where shrink_to() would abort.
+fn release<T>(bucket: &mut Vec<T>) {
+    if bucket.is_empty() {
+        *bucket = Vec::new();
+    } else if bucket.len() * 4 <= bucket.capacity() {
+        let mut smaller = Vec::new();
+        if smaller.try_reserve_exact(bucket.len() * 2).is_ok() {
+            smaller.append(bucket);
+            *bucket = smaller;
+        }
     }
+}
 
-    bstr.chars().rev().collect()
+fn check_split_policy(policy: SplitPolicy) -> Result<(), error::Error> {
+    match policy {
+        SplitPolicy::LoadFactor(load) if load.is_nan() || load <= 0.0 => {
+            Err(error::Error::InvalidConfig("load factor must be positive".to_string()))
+        }
+        _ => Ok(()),
+    }
 }
 
+// How many keys ahead lookup_many() prefetches
+const PREFETCH_AHEAD: usize = 4;
+
+// For code that must never panic or abort, such as plugins and anything
+// behind an FFI boundary, here is what to avoid.
+//
+// Panics: no method panics on valid input. with_split_policy() panics on a
+// bad policy (try_with_split_policy() returns an error instead). Inserting
+// a key over a configured max_key_len panics; try_insert() returns an
+// error instead, and transaction() fails before applying anything. The
+// par_* methods pass on panics from their closures.
+//
+// Allocation failure aborts the process, except in:
+// - try_new(), try_with_split_policy(), try_reserve(), try_upsert(),
+//   try_upsert_with(), try_insert() and try_clone(), which return an
+//   error;
+// - remove() and pop(), which may allocate to shrink the table and its
+//   buckets, but only fallibly, and put the shrinking off if that fails;
+// - lookups, cas(), cursors and the borrowing iterators, which never
+//   allocate at all.
+//
+// Exceptions:
+// - With a prefix index enabled, its trie still allocates infallibly.
+// - Anything that returns a new Vec or String allocates it.
+// - Values' own clone() is up to them.
+
 impl<T> Hash<T>
 where
     T: Clone,
 {
     pub fn new() -> Self {
-        let mut hash_vec = Vec::with_capacity(NHASH);
+        Hash::with_initial_buckets(NHASH, 5) // log_2(32)
+    }
+
+    // A table that starts out with `N` buckets instead of 32, so one whose
+    // working set is known up front skips the splits it would otherwise
+    // grow through, and never shrinks below `N` either. `N` must be a power
+    // of two no smaller than 32; that's checked, and the address bits
+    // worked out, at compile time.
+    pub fn with_buckets<const N: usize>() -> Self {
+        Hash::with_initial_buckets(N, InitialBuckets::<N>::BITS)
+    }
+
+    fn with_initial_buckets(buckets: usize, bits: usize) -> Self {
+        let mut hash_vec = Vec::with_capacity(buckets);
 
-        for _i in 0..NHASH {
+        for _i in 0..buckets {
             hash_vec.push(Vec::new());
         }
 
+        Hash::from_buckets(hash_vec, bits)
+    }
+
+    // Like new(), but reports a failure to allocate the bucket list instead
+    // of aborting
+    pub fn try_new() -> Result<Self, TryReserveError> {
+        let mut hash_vec = Vec::new();
+        hash_vec.try_reserve_exact(NHASH)?;
+        // Empty buckets don't allocate
+        hash_vec.resize_with(NHASH, Vec::new);
+
+        Ok(Hash::from_buckets(hash_vec, 5))
+    }
+
+    fn from_buckets(table: Vec<Vec<NameVal<T>>>, bits: usize) -> Self {
+        let buckets = table.len();
         Hash {
-            table: hash_vec,
-            bits: 5, // log_2(32)
+            table,
+            bits,
             split_bucket: 0,
+            count: 0,
+            generation: 0,
+            split_policy: SplitPolicy::default(),
+            two_choice: false,
+            incremental: false,
+            deferred: false,
+            migration: None,
+            prefix_index: None,
+            min_buckets: buckets,
+            hashing: Hashing::DEFAULT,
+            max_key_len: usize::MAX,
+        }
+    }
+
+    pub fn with_split_policy(policy: SplitPolicy) -> Self {
+        if let Err(e) = check_split_policy(policy) {
+            panic!("{}", e);
+        }
+
+        Hash {
+            split_policy: policy,
+            ..Hash::new()
+        }
+    }
+
+    // with_split_policy() that returns an error for a bad policy or a
+    // failed allocation instead of panicking or aborting
+    pub fn try_with_split_policy(policy: SplitPolicy) -> Result<Self, error::Error> {
+        check_split_policy(policy)?;
+
+        Ok(Hash {
+            split_policy: policy,
+            ..Hash::try_new()?
+        })
+    }
+
+    // A table set up as `config` says, or an error if the configuration
+    // doesn't validate. Eviction is left to BoundedHash::with_config(), so
+    // a configuration with an eviction policy is an error here.
+    pub fn with_config(config: &HashConfig) -> Result<Self, error::Error> {
+        config.validate()?;
+        if config.eviction != Eviction::None {
+            return Err(error::Error::InvalidConfig(
+                "a Hash can't evict; use BoundedHash::with_config()".to_string(),
+            ));
+        }
+
+        let buckets = config.initial_buckets;
+        Ok(Hash {
+            split_policy: config.split_policy,
+            two_choice: config.split_mode == SplitMode::TwoChoice,
+            incremental: config.split_mode == SplitMode::Incremental,
+            deferred: config.split_mode == SplitMode::Deferred,
+            hashing: Hashing {
+                multiplier: config.multiplier as usize,
+                seed: config.seed as usize,
+            },
+            max_key_len: config.max_key_len.unwrap_or(usize::MAX),
+            ..Hash::with_initial_buckets(buckets, buckets.trailing_zeros() as usize)
+        })
+    }
+
+    // The configuration that builds a table like this one, empty
+    pub fn config(&self) -> HashConfig {
+        let split_mode = if self.two_choice {
+            SplitMode::TwoChoice
+        } else if self.incremental {
+            SplitMode::Incremental
+        } else if self.deferred {
+            SplitMode::Deferred
+        } else {
+            SplitMode::Eager
+        };
+
+        HashConfig {
+            initial_buckets: self.min_buckets,
+            split_policy: self.split_policy,
+            split_mode,
+            multiplier: self.hashing.multiplier as u64,
+            seed: self.hashing.seed as u64,
+            max_key_len: Some(self.max_key_len).filter(|&len| len != usize::MAX),
+            eviction: Eviction::None,
+        }
+    }
+
+    pub fn split_policy(&self) -> SplitPolicy {
+        self.split_policy
+    }
+
+    // A table that hashes every key with two functions and inserts it into
+    // the shorter of the two buckets ("power of two choices"). Lookups check
+    // both buckets, but chains stay much shorter under skewed or unlucky
+    // keys.
+    pub fn with_two_choices() -> Self {
+        Hash {
+            two_choice: true,
+            ..Hash::new()
+        }
+    }
+
+    // A table that spreads the work of a split over the operations that
+    // follow it: the split itself only adds a bucket, and each later insert
+    // or removal moves at most a few entries into it. No single upsert pays
+    // for rehashing a whole bucket, which keeps latency flat. Has no effect
+    // in two-choice mode.
+    pub fn with_incremental_splits() -> Self {
+        Hash {
+            incremental: true,
+            ..Hash::new()
+        }
+    }
+
+    // A table whose upserts never split: once the load factor is exceeded
+    // the table just gets fuller until maintain() is called, say from a
+    // background tick or between requests. Lookups slow down as chains grow
+    // in the meantime. Has no effect with SplitPolicy::BucketOverflow.
+    pub fn with_deferred_splits() -> Self {
+        Hash {
+            deferred: true,
+            ..Hash::new()
         }
     }
 
+    // Number of entries stored in the table. Up to 0.1.1 this was the
+    // number of buckets, which is bucket_count() now; see CHANGELOG.md.
     pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    // Number of buckets the table is currently spread over
+    pub fn bucket_count(&self) -> usize {
         self.table.len()
     }
 
     fn hash(&self, name: &str) -> usize {
-        let mut h: usize = 0;
+        bucket_index(self.hashing.key(name), self.bits, self.table.len())
+    }
 
-        for p in name.bytes() {
-            let p = p as usize;
-            // Instead of silently wrapping (like most C implementations do,
-            // even if that is strictly undefined), rust panics if we overflow
-            // an integer value. So we need to use this magic instead.
-            h = h.wrapping_mul(MULTIPLIER).wrapping_add(p);
-        }
+    // The buckets a key may live in; both the same unless in two-choice mode.
+    // `h` is the key's full hash.
+    fn candidates(&self, name: &str, h: usize) -> (usize, usize) {
+        self.candidates_at(name, h, self.bits, self.table.len())
+    }
 
-        let m = h & ((1 << self.bits) - 1);
-        if m < self.len() {
-            m
+    // candidates() for a table addressed with `bits` and `len` buckets, as
+    // it will be after a split or merge
+    fn candidates_at(&self, name: &str, h: usize, bits: usize, len: usize) -> (usize, usize) {
+        let h1 = bucket_index(h, bits, len);
+
+        if self.two_choice {
+            let h2 = self.hashing.second(name);
+            (h1, bucket_index(h2, bits, len))
         } else {
-            m ^ (1 << (self.bits - 1))
+            (h1, h1)
+        }
+    }
+
+    // Bucket and position of the entry for a key
+    fn find(&self, name: &str) -> Option<(usize, usize)> {
+        self.find_hashed(name, self.hashing.key(name))
+    }
+
+    fn find_hashed(&self, name: &str, h: usize) -> Option<(usize, usize)> {
+        let (h1, h2) = self.candidates(name, h);
+        prefetch(&self.table[h1]);
+        if h2 != h1 {
+            prefetch(&self.table[h2]);
+        }
+        let position = |h: usize| {
+            self.table[h]
+                .iter()
+                .position(|entry| key::matches(&entry.name, name))
+                .map(|i| (h, i))
+        };
+
+        match self.migration {
+            // The key may not have been moved to its new bucket yet
+            Some(m) if h1 == m.to => position(h1).or_else(|| position(m.from)),
+            _ => position(h1).or_else(|| if h2 != h1 { position(h2) } else { None }),
         }
     }
 
+    // lookup() for a caller that already has hash_key(name) at hand. In
+    // two-choice mode the second hash still has to be computed, and a table
+    // configured with its own hash function ignores `hash`.
+    pub fn lookup_with_hash(&self, name: &str, hash: u64) -> Option<&T> {
+        debug_assert_eq!(hash, hash_key(name), "wrong hash for `{}`", name);
+        let (h, i) = self.find_hashed(name, self.own_hash(name, hash))?;
+        Some(&self.table[h][i].value)
+    }
+
     pub fn lookup(&self, name: &str) -> Option<&T> {
-        let h = self.hash(name);
-        let entries = &self.table[h];
-
-        match entries.len() {
-            0 => None,
-            _ => {
-                for entry in entries.iter() {
-                    if entry.name == name {
-                        return Some(&entry.value);
-                    }
-                }
-                None
+        let (h, i) = self.find(name)?;
+        Some(&self.table[h][i].value)
+    }
+
+    // Looks up every key in `names`, returning results in the same order.
+    // Hashes are computed up front and buckets visited in index order, which
+    // is kinder to the cache than independent lookups for big batches.
+    pub fn lookup_many<S: AsRef<str>>(&self, names: &[S]) -> Vec<Option<&T>> {
+        let mut order: Vec<(usize, usize)> = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (self.hash(name.as_ref()), i))
+            .collect();
+        order.sort_unstable();
+
+        let mut results = vec![None; names.len()];
+        for (k, &(h, i)) in order.iter().enumerate() {
+            if let Some(&(ahead, _)) = order.get(k + PREFETCH_AHEAD) {
+                prefetch(&self.table[ahead]);
             }
+            let name = names[i].as_ref();
+            results[i] = match self.table[h].iter().find(|entry| key::matches(&entry.name, name)) {
+                Some(entry) => Some(&entry.value),
+                // The key may live in its second choice bucket
+                None if self.two_choice || self.migration.is_some() => self.lookup(name),
+                None => None,
+            };
         }
+
+        results
+    }
+
+    pub fn lookup_mut(&mut self, name: &str) -> Option<&mut T> {
+        let (h, i) = self.find(name)?;
+        Some(&mut self.table[h][i].value)
     }
 
     // Returns true if the insert was a new key,
     // False if we overwrote a key
     pub fn upsert(&mut self, name: &str, value: T) -> bool {
-        let h = self.hash(name);
-        let entry_count;
+        self.upsert_hashed(name, self.hashing.key(name), value)
+    }
 
-        {
-            let entries = &mut self.table[h];
-            entry_count = entries.len();
+    // upsert() for a caller that already has hash_key(name) at hand
+    pub fn upsert_with_hash(&mut self, name: &str, hash: u64, value: T) -> bool {
+        debug_assert_eq!(hash, hash_key(name), "wrong hash for `{}`", name);
+        let h = self.own_hash(name, hash);
+        self.upsert_hashed(name, h, value)
+    }
 
-            for entry in entries.iter_mut() {
-                if entry.name == name {
-                    entry.value = value;
-                    return false;
-                }
-            }
+    // This table's hash of `name`, given its hash_key()
+    fn own_hash(&self, name: &str, hash: u64) -> usize {
+        if self.hashing == Hashing::DEFAULT {
+            hash as usize
+        } else {
+            self.hashing.key(name)
+        }
+    }
 
-            entries.push(NameVal {
-                name: name.to_string(),
-                value: value,
+    // Whether upsert() takes `name`, for wrappers that must know before they
+    // change anything of their own
+    pub(crate) fn check_key(&self, name: &str) -> Result<(), error::Error> {
+        if name.len() > self.max_key_len {
+            return Err(error::Error::KeyTooLong {
+                len: name.len(),
+                max: self.max_key_len,
             });
         }
+        Ok(())
+    }
 
-        if entry_count + 1 > (1 << self.bits) {
-            self.split();
+    fn check_key_len(&self, name: &str) {
+        if let Err(e) = self.check_key(name) {
+            panic!("{}", e);
         }
+    }
+
+    fn upsert_hashed(&mut self, name: &str, h: usize, value: T) -> bool {
+        self.check_key_len(name);
+        self.migrate(MIGRATE_STEP);
+        if let Some((b, i)) = self.find_hashed(name, h) {
+            self.table[b][i].value = value;
+            return false;
+        }
+
+        if let Some(ref mut index) = self.prefix_index {
+            index.insert(name);
+        }
+        self.insert_hashed(
+            NameVal {
+                name: key::from_str(name),
+                value,
+            },
+            h,
+        );
 
         true
     }
 
-    fn split(&mut self) {
-        let orig_bucket = self.table[self.split_bucket].to_vec(); 
-        self.table[self.split_bucket] = Vec::<NameVal<T>>::new(); 
-        self.table.push(Vec::<NameVal<T>>::new());
+    // Like upsert(), but if the key is already there `combine` is called
+    // with the stored value and the new one to merge them in place (sum,
+    // append, keep the max, ...). Returns true if the key was new.
+    pub fn upsert_with<F>(&mut self, name: &str, value: T, combine: F) -> bool
+    where
+        F: FnOnce(&mut T, T),
+    {
+        match self.lookup_mut(name) {
+            Some(old) => {
+                combine(old, value);
+                false
+            }
+            None => self.upsert(name, value),
+        }
+    }
 
-        if self.len() > ((1 << self.bits) - 1) {
-            self.bits += 1;
-            self.split_bucket = 0;
-        } else {
-            self.split_bucket += 1;
+    // The entry for `name`, to inspect or fill in place with one lookup.
+    // Only borrows the key: an owned copy is made only if the entry is
+    // vacant and a value actually gets inserted.
+    pub fn entry_ref<'a, 'k>(&'a mut self, name: &'k str) -> EntryRef<'a, 'k, T> {
+        self.migrate(MIGRATE_STEP);
+        let h = self.hashing.key(name);
+        match self.find_hashed(name, h) {
+            Some((bucket, index)) => EntryRef::Occupied(OccupiedEntryRef {
+                hash: self,
+                bucket,
+                index,
+            }),
+            None => EntryRef::Vacant(VacantEntryRef {
+                hash: self,
+                name,
+                h,
+            }),
         }
+    }
 
-        for entry in orig_bucket.iter() {
-            self.upsert(&entry.name, entry.value.clone());
+    // Replaces the value for `name` with `new`, but only if it currently
+    // equals `expected`. On failure `new` is handed back, along with what
+    // the value was instead.
+    pub fn cas(&mut self, name: &str, expected: &T, new: T) -> Result<(), CasError<T>>
+    where
+        T: PartialEq,
+    {
+        match self.lookup_mut(name) {
+            Some(current) if *current == *expected => {
+                *current = new;
+                Ok(())
+            }
+            Some(current) => Err(CasError::Mismatch {
+                current: current.clone(),
+                new,
+            }),
+            None => Err(CasError::NotFound(new)),
         }
     }
 
-    pub fn remove(&mut self, name: &str) -> Option<T> {
-        let h = self.hash(name);
-        let entries = &mut self.table[h];
+    // Inserts every pair, returning how many keys were new. The table is
+    // grown for the iterator's size hint before anything goes in, so a bulk
+    // load does its splits in one pass up front instead of one at a time
+    // while the chains fill up.
+    pub fn upsert_many<K, I>(&mut self, entries: I) -> usize
+    where
+        K: AsRef<str>,
+        I: IntoIterator<Item = (K, T)>,
+    {
+        let entries = entries.into_iter();
+        self.reserve(entries.size_hint().0);
 
-        for i in 0..entries.len() {
-            if entries[i].name == name {
-                return Some(entries.remove(i).value);
+        let mut added = 0;
+        for (name, value) in entries {
+            if self.upsert(name.as_ref(), value) {
+                added += 1;
             }
         }
+        added
+    }
 
-        None
+    // How many more keys are certain to fit before the next split. Under a
+    // load factor that's exact; under BucketOverflow it assumes every new
+    // key lands in the fullest bucket.
+    pub fn capacity(&self) -> usize {
+        match self.split_policy {
+            SplitPolicy::LoadFactor(load) => {
+                let max = (self.table.len() as f64 * load) as usize;
+                max.saturating_sub(self.count)
+            }
+            SplitPolicy::BucketOverflow => {
+                let longest = self.table.iter().map(|b| b.len()).max().unwrap_or(0);
+                (1usize << self.bits).saturating_sub(longest)
+            }
+        }
     }
-}
 
-#[test]
-fn basics() {
-    let mut hashtab = Hash::new();
-    assert_eq!(hashtab.lookup("abc"), None);
-    hashtab.upsert("abc", 64);
-    hashtab.upsert("abcdefghijklmnopq", 128);
-    assert_eq!(hashtab.lookup("abc"), Some(&64));
-    assert_eq!(hashtab.lookup("abc"), Some(&64));
-    assert_eq!(hashtab.lookup("abcdefghijklmnopq"), Some(&128));
-    hashtab.upsert("abc", 256);
-    assert_eq!(hashtab.lookup("abc"), Some(&256));
-    hashtab.remove("abc");
-    assert_eq!(hashtab.lookup("abc"), None);
-    assert_eq!(hashtab.lookup("abcd"), None);
-    let nippon = "私はガラスを食べられます。それは私を傷つけません。";
-    hashtab.upsert(nippon, 31337);
-    assert_eq!(hashtab.lookup(nippon), Some(&31337));
-    hashtab.remove(nippon);
-    assert_eq!(hashtab.lookup(nippon), None);
-    println!("done");
-}
+    // Splits buckets ahead of time so that `additional` more keys fit
+    // without the table having to grow
+    pub fn reserve(&mut self, additional: usize) {
+        let wanted = self.buckets_for(self.count.saturating_add(additional));
+        while self.table.len() < wanted {
+            self.split();
+        }
+        self.migrate(usize::MAX);
+    }
 
-#[test]
-fn test_split() {
-    let mut hashtab = Hash::new();
+    // Merges buckets until the table is down to `min_buckets`, but never
+    // below the initial size or what the split policy needs for the
+    // current entries, and hands unused memory back
+    pub fn shrink_to(&mut self, min_buckets: usize) {
+        let floor = min_buckets.max(self.min_buckets).max(self.buckets_for(self.count));
+        while self.table.len() > floor && self.merge() {}
+        self.trim_buckets();
+    }
 
-    let n_entries = 8192;
+    // Hands back every bucket's spare capacity without changing the number
+    // of buckets. Buckets already give memory back when they empty or get
+    // mostly empty; this also trims the rest, e.g. after a bulk delete.
+    // Returns how many entries' worth of space was freed.
+    pub fn trim_buckets(&mut self) -> usize {
+        let mut freed = 0;
+        for bucket in &mut self.table {
+            freed += bucket.capacity() - bucket.len();
+            bucket.shrink_to_fit();
+        }
+        self.table.shrink_to_fit();
+        freed
+    }
 
-    for i in 0..n_entries {
-        let k = i.to_string();
-        hashtab.upsert(&k, i);
+    // Shrinks the table as far as its split policy allows
+    pub fn shrink_to_fit(&mut self) {
+        self.shrink_to(0);
     }
 
-    for i in 0..n_entries {
-        let k = i.to_string();
-        let val = hashtab.lookup(&k);
+    // Like reserve(), but reports a failure to allocate instead of
+    // aborting. The splits done before the failure stay done.
+    pub fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        let wanted = self.buckets_for(self.count.saturating_add(additional));
+        if wanted > self.table.len() {
+            self.table.try_reserve(wanted - self.table.len())?;
+        }
+        while self.table.len() < wanted {
+            self.try_split()?;
+        }
+        self.reserve_migration(usize::MAX)?;
+        self.migrate(usize::MAX);
+        Ok(())
+    }
 
-        assert!(val.is_some());
-        let unwrapped = val.unwrap();
-        assert_eq!(*unwrapped, i);
+    // Splits a table with deferred splits would have done by now
+    pub fn splits_due(&self) -> usize {
+        match self.split_policy {
+            SplitPolicy::LoadFactor(_) if self.deferred => {
+                self.buckets_for(self.count).saturating_sub(self.table.len())
+            }
+            _ => 0,
+        }
+    }
+
+    // Does the splits that are due (see with_deferred_splits()) and
+    // finishes any incremental split in progress. Returns how many splits
+    // it did.
+    pub fn maintain(&mut self) -> usize {
+        let due = self.splits_due();
+        for _ in 0..due {
+            self.split();
+        }
+        self.migrate(usize::MAX);
+        due
+    }
+
+    // How many buckets the split policy wants for `entries` entries
+    fn buckets_for(&self, entries: usize) -> usize {
+        match self.split_policy {
+            SplitPolicy::LoadFactor(load) => (entries as f64 / load).ceil() as usize,
+            SplitPolicy::BucketOverflow => entries / RESERVE_LOAD,
+        }
+    }
+
+    // Like upsert(), but the allocations a new key needs (its name, room in
+    // its bucket) are made up front and fail with an error instead of
+    // aborting. If there's no memory for the split the insert calls for,
+    // the key goes in anyway and the table splits at a later insert.
+    pub fn try_upsert(&mut self, name: &str, value: T) -> Result<bool, TryReserveError> {
+        if let Some(old) = self.lookup_mut(name) {
+            *old = value;
+            return Ok(false);
+        }
+
+        self.check_key_len(name);
+        let key = key::try_from_str(name)?;
+        let h = self.hashing.key(name);
+        let (h1, h2) = self.candidates(name, h);
+        self.table[h1].try_reserve(1)?;
+        self.table[h2].try_reserve(1)?;
+
+        if let Some(ref mut index) = self.prefix_index {
+            index.insert(name);
+        }
+        if self.push_hashed(NameVal { name: key, value }, h) {
+            let _ = self.try_split();
+        }
+
+        Ok(true)
+    }
+
+    // upsert_with() that can fail the way try_upsert() does
+    pub fn try_upsert_with<F>(
+        &mut self,
+        name: &str,
+        value: T,
+        combine: F,
+    ) -> Result<bool, TryReserveError>
+    where
+        F: FnOnce(&mut T, T),
+    {
+        match self.lookup_mut(name) {
+            Some(old) => {
+                combine(old, value);
+                Ok(false)
+            }
+            None => self.try_upsert(name, value),
+        }
+    }
+
+    // try_upsert() for keys from outside: one over the table's max_key_len
+    // is an error rather than a panic, as is running out of memory
+    pub fn try_insert(&mut self, name: &str, value: T) -> Result<bool, error::Error> {
+        self.check_key(name)?;
+        Ok(self.try_upsert(name, value)?)
+    }
+
+    // clone() that reports a failure to allocate instead of aborting. The
+    // values are copied with their own clone(), which this can't vouch for.
+    pub fn try_clone(&self) -> Result<Self, TryReserveError> {
+        let mut table = Vec::new();
+        table.try_reserve_exact(self.table.len())?;
+        for bucket in &self.table {
+            let mut copy = Vec::new();
+            copy.try_reserve_exact(bucket.len())?;
+            for entry in bucket {
+                copy.push(NameVal {
+                    name: key::try_from_str(&entry.name)?,
+                    value: entry.value.clone(),
+                });
+            }
+            table.push(copy);
+        }
+
+        Ok(Hash {
+            table,
+            bits: self.bits,
+            split_bucket: self.split_bucket,
+            count: self.count,
+            generation: self.generation,
+            split_policy: self.split_policy,
+            two_choice: self.two_choice,
+            incremental: self.incremental,
+            deferred: self.deferred,
+            migration: self.migration,
+            prefix_index: self.prefix_index.clone(),
+            min_buckets: self.min_buckets,
+            hashing: self.hashing,
+            max_key_len: self.max_key_len,
+        })
+    }
+
+    // Adds an entry for a key that isn't in the table yet
+    fn insert_entry(&mut self, entry: NameVal<T>) {
+        let h = self.hashing.key(&entry.name);
+        self.insert_hashed(entry, h);
+    }
+
+    fn insert_hashed(&mut self, entry: NameVal<T>, h: usize) {
+        if self.push_hashed(entry, h) {
+            self.split();
+        }
+    }
+
+    // Adds the entry without splitting; returns whether the table should
+    // split now
+    fn push_hashed(&mut self, entry: NameVal<T>, h: usize) -> bool {
+        let (h1, h2) = self.candidates(&entry.name, h);
+        let h = if self.table[h2].len() < self.table[h1].len() {
+            h2
+        } else {
+            h1
+        };
+        let entry_count = self.table[h].len();
+
+        self.table[h].push(entry);
+        self.count += 1;
+        self.generation += 1;
+
+        match self.split_policy {
+            SplitPolicy::LoadFactor(load) => {
+                !self.deferred && self.count as f64 > self.table.len() as f64 * load
+            }
+            SplitPolicy::BucketOverflow => entry_count + 1 > (1 << self.bits),
+        }
+    }
+
+    fn split(&mut self) {
+        if self.incremental && !self.two_choice {
+            // Only one split is ever in flight
+            self.migrate(usize::MAX);
+
+            self.table.push(Vec::new());
+            self.migration = Some(Migration {
+                from: self.split_bucket,
+                to: self.table.len() - 1,
+                next: 0,
+            });
+            let (bits, split_bucket) = next_split(self.bits, self.split_bucket, self.table.len());
+            self.bits = bits;
+            self.split_bucket = split_bucket;
+            return;
+        }
+
+        let orig_bucket = mem::take(&mut self.table[self.split_bucket]);
+        self.table.push(Vec::<NameVal<T>>::new());
+        self.count -= orig_bucket.len();
+
+        let (bits, split_bucket) = next_split(self.bits, self.split_bucket, self.table.len());
+        self.bits = bits;
+        self.split_bucket = split_bucket;
+
+        for entry in orig_bucket {
+            self.insert_entry(entry);
+        }
+    }
+
+    // Like split(), but gets all the memory the split needs before changing
+    // anything. Every entry of the bucket being split ends up in one of its
+    // candidate buckets, so each of those gets room for the whole bucket;
+    // buckets are short enough for that to cost little. Unlike split() it
+    // never goes on to split again under BucketOverflow.
+    fn try_split(&mut self) -> Result<(), TryReserveError> {
+        self.table.try_reserve(1)?;
+        if self.incremental && !self.two_choice {
+            self.reserve_migration(usize::MAX)?;
+            self.split();
+            return Ok(());
+        }
+
+        let s = self.split_bucket;
+        let (len, n) = (self.table.len() + 1, self.table[s].len());
+        let (bits, split_bucket) = next_split(self.bits, s, len);
+
+        let mut stays = Vec::new();
+        stays.try_reserve(n)?;
+        let mut fresh = Vec::new();
+        fresh.try_reserve(n)?;
+        for i in 0..n {
+            let name = &self.table[s][i].name;
+            let (h1, h2) = self.candidates_at(name, self.hashing.key(name), bits, len);
+            for &b in &[h1, h2] {
+                if b != s && b < len - 1 {
+                    self.table[b].try_reserve(n)?;
+                }
+            }
+        }
+
+        let orig_bucket = mem::replace(&mut self.table[s], stays);
+        self.table.push(fresh);
+        self.count -= orig_bucket.len();
+        self.bits = bits;
+        self.split_bucket = split_bucket;
+
+        for entry in orig_bucket {
+            let h = self.hashing.key(&entry.name);
+            self.push_hashed(entry, h);
+        }
+        Ok(())
+    }
+
+    // Makes room for the next `limit` entries an incremental split would
+    // move, so migrate() won't allocate
+    fn reserve_migration(&mut self, limit: usize) -> Result<(), TryReserveError> {
+        if let Some(m) = self.migration {
+            let rest = self.table[m.from].len() - m.next;
+            self.table[m.to].try_reserve(rest.min(limit))?;
+        }
+        Ok(())
+    }
+
+    // Moves up to `limit` entries of an incremental split to the bucket they
+    // now belong in
+    fn migrate(&mut self, limit: usize) {
+        let mut m = match self.migration {
+            Some(m) => m,
+            None => return,
+        };
+
+        let mut moved = 0;
+        while moved < limit && m.next < self.table[m.from].len() {
+            let h = self.hash(&self.table[m.from][m.next].name);
+            if h == m.from {
+                m.next += 1;
+            } else {
+                let entry = self.table[m.from].swap_remove(m.next);
+                self.table[h].push(entry);
+                moved += 1;
+            }
+        }
+
+        if moved > 0 {
+            self.generation += 1;
+        }
+        self.migration = if m.next < self.table[m.from].len() {
+            Some(m)
+        } else {
+            release(&mut self.table[m.from]);
+            None
+        };
+    }
+
+    // Never aborts for lack of memory: if memory is short, moving entries
+    // for an incremental split, merging buckets and shrinking the bucket the
+    // key came out of wait for later
+    pub fn remove(&mut self, name: &str) -> Option<T> {
+        if self.reserve_migration(MIGRATE_STEP).is_ok() {
+            self.migrate(MIGRATE_STEP);
+        }
+        let (h, i) = self.find(name)?;
+        Some(self.remove_at(h, i).value)
+    }
+
+    // Removes and returns some entry, or None if the table is empty. Which
+    // one is unspecified; handy for draining a table like a work queue.
+    pub fn pop(&mut self) -> Option<(String, T)> {
+        let h = self.table.iter().position(|bucket| !bucket.is_empty())?;
+        let i = self.table[h].len() - 1;
+
+        let entry = self.remove_at(h, i);
+        Some((key::into_string(entry.name), entry.value))
+    }
+
+    fn remove_at(&mut self, h: usize, mut i: usize) -> NameVal<T> {
+        // An incremental split has already looked at the entries before
+        // `next`; the hole must not be filled with one it hasn't seen
+        if let Some(ref mut m) = self.migration {
+            if h == m.from && i < m.next {
+                self.table[h].swap(i, m.next - 1);
+                i = m.next - 1;
+                m.next -= 1;
+            }
+        }
+
+        // Order within a bucket carries no meaning (see iter()), so the last
+        // entry can simply move into the hole
+        let entry = self.table[h].swap_remove(i);
+        release(&mut self.table[h]);
+        if let Some(ref mut index) = self.prefix_index {
+            index.remove(&entry.name);
+        }
+        self.count -= 1;
+        self.generation += 1;
+
+        if let SplitPolicy::LoadFactor(load) = self.split_policy {
+            let min_load = load * MIN_LOAD_FRACTION;
+            while self.table.len() > self.min_buckets
+                && (self.count as f64) < self.table.len() as f64 * min_load
+                && self.merge()
+            {}
+        }
+
+        entry
+    }
+
+    // Undoes the last split: the last bucket goes back into the one it was
+    // split from, and the address space halves once a whole round is undone.
+    // Makes room for the entries first, as try_split() does; returns false,
+    // having changed nothing, if that fails.
+    fn merge(&mut self) -> bool {
+        if self.reserve_migration(usize::MAX).is_err() {
+            return false;
+        }
+        self.migrate(usize::MAX);
+
+        let len = self.table.len() - 1;
+        let (bits, split_bucket) = prev_split(self.bits, self.split_bucket, len);
+        let n = self.table[len].len();
+        for i in 0..n {
+            let name = &self.table[len][i].name;
+            let (h1, h2) = self.candidates_at(name, self.hashing.key(name), bits, len);
+            for &b in &[h1, h2] {
+                if self.table[b].try_reserve(n).is_err() {
+                    return false;
+                }
+            }
+        }
+
+        let last = self.table.pop().unwrap();
+        self.count -= last.len();
+        self.bits = bits;
+        self.split_bucket = split_bucket;
+
+        for entry in last {
+            self.insert_entry(entry);
+        }
+        true
+    }
+
+    // Keeps a trie of the keys next to the table from now on, so prefix
+    // queries don't have to scan every entry. Costs a trie update on every
+    // insert and remove.
+    pub fn enable_prefix_index(&mut self) {
+        if self.prefix_index.is_none() {
+            let mut index = Trie::new();
+            for (name, _) in self.iter() {
+                index.insert(name);
+            }
+            self.prefix_index = Some(index);
+        }
+    }
+
+    pub fn disable_prefix_index(&mut self) {
+        self.prefix_index = None;
+    }
+
+    // Entries whose keys are within `max_distance` edits (Levenshtein, by
+    // character) of `name`, closest first. Scans the whole table.
+    pub fn lookup_fuzzy(&self, name: &str, max_distance: usize) -> Vec<(&str, &T, usize)> {
+        let target: Vec<char> = name.chars().collect();
+        let mut found: Vec<(&str, &T, usize)> = self
+            .iter()
+            .filter_map(|(key, value)| {
+                edit_distance(&target, key, max_distance).map(|d| (key, value, d))
+            })
+            .collect();
+
+        found.sort_by(|a, b| a.2.cmp(&b.2).then(a.0.cmp(b.0)));
+        found
+    }
+
+    // Lazily yields the entries whose keys match a shell-style wildcard
+    // pattern (see `glob::Pattern`), bucket by bucket
+    pub fn scan_glob(&self, pattern: &str) -> ScanGlob<'_, T> {
+        ScanGlob {
+            iter: self.iter(),
+            pattern: Pattern::new(pattern),
+        }
+    }
+
+    fn entry(&self, name: &str) -> Option<(&str, &T)> {
+        let (h, i) = self.find(name)?;
+        let entry = &self.table[h][i];
+        Some((&entry.name, &entry.value))
+    }
+
+    // Every entry whose key starts with `prefix`, in no particular order.
+    // Scans the whole table unless the prefix index is enabled.
+    pub fn keys_with_prefix(&self, prefix: &str) -> Vec<(&str, &T)> {
+        match self.prefix_index {
+            Some(ref index) => index
+                .with_prefix(prefix)
+                .iter()
+                .filter_map(|name| self.entry(name))
+                .collect(),
+            None => self.iter().filter(|e| e.0.starts_with(prefix)).collect(),
+        }
+    }
+
+    // The entry with the longest key that is a prefix of `name` (routing
+    // table style matching)
+    pub fn longest_prefix_match(&self, name: &str) -> Option<(&str, &T)> {
+        match self.prefix_index {
+            Some(ref index) => self.entry(&name[..index.longest_prefix(name)?]),
+            None => (0..name.len() + 1)
+                .rev()
+                .filter(|&i| name.is_char_boundary(i))
+                .filter_map(|i| self.entry(&name[..i]))
+                .next(),
+        }
+    }
+
+    // A view of the keys starting with `prefix`, as a map of its own: the
+    // prefix is added to names going in and taken off names coming out.
+    // Namespaces with distinct prefixes, neither a prefix of the other,
+    // never see each other's keys. Listing or clearing a namespace scans
+    // the table unless the prefix index is enabled.
+    pub fn namespace(&mut self, prefix: &str) -> Namespace<'_, T> {
+        Namespace {
+            hash: self,
+            prefix: prefix.to_string(),
+        }
+    }
+
+    // Raw access to the buckets, for code that wants to work with the
+    // table's layout directly (custom persistence, distribution analysis).
+    // Bucket indexes are only meaningful until the next insert or removal,
+    // which may split or merge buckets. Values can be changed in place, but
+    // entries can't be added, removed or renamed this way, so the table
+    // stays consistent.
+    pub fn bucket(&self, index: usize) -> Option<&[NameVal<T>]> {
+        self.table.get(index).map(|bucket| &bucket[..])
+    }
+
+    pub fn bucket_mut(&mut self, index: usize) -> Option<&mut [NameVal<T>]> {
+        self.table.get_mut(index).map(|bucket| &mut bucket[..])
+    }
+
+    // Every bucket with its index, in index order; empty buckets included
+    pub fn buckets(&self) -> Buckets<'_, T> {
+        Buckets {
+            inner: self.table.iter().enumerate(),
+        }
+    }
+
+    // The bucket `name` hashes to. In two-choice mode this is the first
+    // choice; the key may live in its second one instead.
+    pub fn bucket_index_for(&self, name: &str) -> usize {
+        self.hash(name)
+    }
+
+    // Walks the whole table checking what every operation has to preserve:
+    // the count matches the entries, each key sits in a bucket it hashes to
+    // and is the one lookups find, and the prefix index holds exactly the
+    // keys. Returns the first violation found. For tests; O(n).
+    #[cfg(any(test, feature = "test-utils"))]
+    pub(crate) fn check_invariants(&self) -> Result<(), String> {
+        let entries: usize = self.table.iter().map(Vec::len).sum();
+        if entries != self.count {
+            return Err(format!("count is {} but {} entries are stored", self.count, entries));
+        }
+        let (len, half) = (self.table.len(), 1 << self.bits >> 1);
+        if len > half * 2 || len <= half {
+            return Err(format!("{} buckets addressed with {} bits", len, self.bits));
+        }
+        if self.split_bucket != (len - half) % half {
+            return Err(format!("split bucket {} with {} buckets", self.split_bucket, len));
+        }
+
+        for (b, bucket) in self.table.iter().enumerate() {
+            for (i, entry) in bucket.iter().enumerate() {
+                let (h1, h2) = self.candidates(&entry.name, self.hashing.key(&entry.name));
+                let unmoved = self.migration.is_some_and(|m| m.from == b && m.to == h1);
+                if b != h1 && b != h2 && !unmoved {
+                    return Err(format!("`{}` is in bucket {}, not {}", entry.name, b, h1));
+                }
+                if self.find(&entry.name) != Some((b, i)) {
+                    return Err(format!("`{}` at {}/{} is shadowed", entry.name, b, i));
+                }
+            }
+        }
+
+        if let Some(ref index) = self.prefix_index {
+            let mut indexed = index.with_prefix("");
+            let mut names: Vec<String> = self.iter().map(|(name, _)| name.to_string()).collect();
+            indexed.sort();
+            names.sort();
+            if indexed != names {
+                return Err("prefix index is out of sync with the table".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    // Visits every entry, bucket by bucket. The order is unspecified: it
+    // depends on the split history, and removing a key may move another
+    // key of the same bucket into its place.
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            buckets: self.table.iter(),
+            entries: [].iter(),
+        }
+    }
+
+    // Like iter(), with the values mutable
+    pub fn iter_mut(&mut self) -> IterMut<'_, T> {
+        IterMut {
+            buckets: self.table.iter_mut(),
+            entries: [].iter_mut(),
+        }
+    }
+
+    // Visits every entry in an order that depends only on the keys: by hash,
+    // then by key. Two tables holding the same keys iterate the same way
+    // whatever their split policy, insertion order or removal history, which
+    // makes it the order to use for exports and golden files. Costs a sort.
+    pub fn iter_stable(&self) -> vec::IntoIter<(&str, &T)> {
+        let mut entries: Vec<(&str, &T)> = self.iter().collect();
+        entries.sort_by_cached_key(|&(name, _)| (hash_str(name), name));
+        entries.into_iter()
+    }
+
+    // A detached position for walking the table without holding a borrow
+    // across calls (paging through keys between requests, say)
+    pub fn cursor(&self) -> Cursor {
+        Cursor {
+            bucket: 0,
+            entry: 0,
+            generation: self.generation,
+        }
+    }
+
+    // The entry at `cursor`, moving the cursor past it. Fails once the table
+    // has had keys added or removed since the cursor was made, since the
+    // position may then skip or repeat entries.
+    pub fn cursor_next(&self, cursor: &mut Cursor) -> Result<Option<(&str, &T)>, StaleCursor> {
+        if cursor.generation != self.generation {
+            return Err(StaleCursor);
+        }
+
+        while let Some(bucket) = self.table.get(cursor.bucket) {
+            if let Some(entry) = bucket.get(cursor.entry) {
+                cursor.entry += 1;
+                return Ok(Some((&entry.name, &entry.value)));
+            }
+            cursor.bucket += 1;
+            cursor.entry = 0;
+        }
+
+        Ok(None)
+    }
+
+    // Calls `f` on every value, spreading the buckets over one thread per
+    // core. Each thread gets a disjoint run of buckets, so no locking is
+    // involved; worth it when `f` is expensive or the table is big.
+    pub fn par_values_mut<F>(&mut self, f: F)
+    where
+        T: Send,
+        F: Fn(&str, &mut T) + Sync,
+    {
+        let chunk = self.table.len().div_ceil(worker_threads());
+        let f = &f;
+
+        thread::scope(|scope| {
+            for buckets in self.table.chunks_mut(chunk) {
+                scope.spawn(move || {
+                    for entry in buckets.iter_mut().flat_map(|bucket| bucket.iter_mut()) {
+                        f(&entry.name, &mut entry.value);
+                    }
+                });
+            }
+        });
+    }
+
+    // Bulk-inserts `entries` using one thread per core, returning how many
+    // keys were new. The table is grown for all of them up front, so the
+    // layout stays fixed while the threads work: keys are hashed in
+    // parallel, then each thread fills its own disjoint run of buckets.
+    // Tables in two-choice mode, with a prefix index or without a load
+    // factor policy fall back to plain upserts.
+    pub fn par_extend<K, I>(&mut self, entries: I) -> usize
+    where
+        T: Send,
+        K: Into<String>,
+        I: IntoIterator<Item = (K, T)>,
+    {
+        let entries: Vec<(String, T)> = entries.into_iter().map(|(k, v)| (k.into(), v)).collect();
+        for (name, _) in &entries {
+            self.check_key_len(name);
+        }
+        let parallel = match self.split_policy {
+            SplitPolicy::LoadFactor(_) => !self.two_choice && self.prefix_index.is_none(),
+            SplitPolicy::BucketOverflow => false,
+        };
+        if !parallel {
+            return self.upsert_many(entries);
+        }
+
+        self.reserve(entries.len());
+        let threads = worker_threads();
+        let chunk = self.table.len().div_ceil(threads);
+        let (bits, len, hashing) = (self.bits, self.table.len(), self.hashing);
+
+        // Hash in parallel, grouping each thread's share by the run of
+        // buckets it belongs to
+        let per_thread = entries.len().div_ceil(threads).max(1);
+        let mut shares: Vec<Vec<(String, T)>> = Vec::new();
+        let mut entries = entries.into_iter();
+        loop {
+            let share: Vec<(String, T)> = entries.by_ref().take(per_thread).collect();
+            if share.is_empty() {
+                break;
+            }
+            shares.push(share);
+        }
+
+        let runs = len.div_ceil(chunk);
+        let sorted: Vec<Vec<Vec<(usize, String, T)>>> = thread::scope(|scope| {
+            let handles: Vec<_> = shares
+                .into_iter()
+                .map(|share| {
+                    scope.spawn(move || {
+                        let mut by_run: Vec<Vec<(usize, String, T)>> =
+                            (0..runs).map(|_| Vec::new()).collect();
+                        for (name, value) in share {
+                            let b = bucket_index(hashing.key(&name), bits, len);
+                            by_run[b / chunk].push((b, name, value));
+                        }
+                        by_run
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        let mut work: Vec<Vec<(usize, String, T)>> = (0..runs).map(|_| Vec::new()).collect();
+        for by_run in sorted {
+            for (run, part) in by_run.into_iter().enumerate() {
+                work[run].extend(part);
+            }
+        }
+
+        let added: usize = thread::scope(|scope| {
+            let handles: Vec<_> = self
+                .table
+                .chunks_mut(chunk)
+                .zip(work)
+                .enumerate()
+                .map(|(run, (buckets, work))| {
+                    scope.spawn(move || {
+                        let mut added = 0;
+                        for (b, name, value) in work {
+                            let bucket = &mut buckets[b - run * chunk];
+                            match bucket.iter_mut().find(|entry| key::matches(&entry.name, &name)) {
+                                Some(entry) => entry.value = value,
+                                None => {
+                                    let name = key::from_string(name);
+                                    bucket.push(NameVal { name, value });
+                                    added += 1;
+                                }
+                            }
+                        }
+                        added
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).sum()
+        });
+
+        self.count += added;
+        self.generation += 1;
+        added
+    }
+
+    // Copies of the values, in iter() order, leaving the table as it is
+    pub fn values_cloned(&self) -> ValuesCloned<'_, T> {
+        ValuesCloned { inner: self.iter() }
+    }
+
+    // The keys, moved out of the table
+    pub fn into_keys(self) -> IntoKeys<T> {
+        IntoKeys {
+            inner: self.into_iter(),
+        }
+    }
+
+    // The values, moved out of the table
+    pub fn into_values(self) -> IntoValues<T> {
+        IntoValues {
+            inner: self.into_iter(),
+        }
+    }
+
+    // Every entry, moved out of the table in key order. Handy for writing
+    // out a table deterministically; see iter_stable() for an order that
+    // doesn't need the keys compared.
+    pub fn into_iter_sorted(self) -> vec::IntoIter<(String, T)> {
+        let mut entries: Vec<(String, T)> = self.into_iter().collect();
+        entries.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        entries.into_iter()
+    }
+
+    // A table with the same keys and every value passed through `f`. Keys
+    // stay in the buckets they're in, so nothing is rehashed.
+    pub fn map_values<U, F>(self, mut f: F) -> Hash<U>
+    where
+        U: Clone,
+        F: FnMut(T) -> U,
+    {
+        self.filter_map(|_, value| Some(f(value)))
+    }
+
+    // Like map_values(), but entries for which `f` returns None are dropped
+    pub fn filter_map<U, F>(mut self, mut f: F) -> Hash<U>
+    where
+        U: Clone,
+        F: FnMut(&str, T) -> Option<U>,
+    {
+        // Dropping entries would leave a split's cursor pointing past them
+        self.migrate(usize::MAX);
+        let mut prefix_index = self.prefix_index;
+        let mut count = 0;
+        let table = self
+            .table
+            .into_iter()
+            .map(|bucket| {
+                bucket
+                    .into_iter()
+                    .filter_map(|entry| match f(&entry.name, entry.value) {
+                        Some(value) => {
+                            count += 1;
+                            Some(NameVal {
+                                name: entry.name,
+                                value,
+                            })
+                        }
+                        None => {
+                            if let Some(ref mut index) = prefix_index {
+                                index.remove(&entry.name);
+                            }
+                            None
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+
+        Hash {
+            table,
+            bits: self.bits,
+            split_bucket: self.split_bucket,
+            count,
+            generation: 0,
+            split_policy: self.split_policy,
+            two_choice: self.two_choice,
+            incremental: self.incremental,
+            deferred: self.deferred,
+            migration: None,
+            prefix_index,
+            min_buckets: self.min_buckets,
+            hashing: self.hashing,
+            max_key_len: self.max_key_len,
+        }
+    }
+
+    // Whether every key here is also in `other`; values aren't compared.
+    // Works across value types.
+    pub fn is_subset<U: Clone>(&self, other: &Hash<U>) -> bool {
+        self.len() <= other.len() && self.iter().all(|(name, _)| other.lookup(name).is_some())
+    }
+
+    pub fn is_superset<U: Clone>(&self, other: &Hash<U>) -> bool {
+        other.is_subset(self)
+    }
+
+    // Whether both tables hold exactly the same keys
+    pub fn same_keys<U: Clone>(&self, other: &Hash<U>) -> bool {
+        self.len() == other.len() && self.is_subset(other)
+    }
+
+    // `k` entries chosen uniformly at random (all of them if there are
+    // fewer), each entry equally likely however the buckets are filled.
+    // `random` is the source of randomness: anything returning uniformly
+    // distributed u64s, such as a closure around the caller's RNG.
+    pub fn sample<R>(&self, k: usize, mut random: R) -> Vec<(&str, &T)>
+    where
+        R: FnMut() -> u64,
+    {
+        // Reservoir sampling: the i-th entry replaces a random member of
+        // the sample with probability k / i
+        let mut sample = Vec::with_capacity(k.min(self.count));
+        for (i, entry) in self.iter().enumerate() {
+            if i < k {
+                sample.push(entry);
+            } else {
+                let j = (random() % (i as u64 + 1)) as usize;
+                if j < k {
+                    sample[j] = entry;
+                }
+            }
+        }
+        sample
+    }
+
+    // How `other` differs from this table: keys only it has, keys only this
+    // table has, and keys whose values differ. Each list is sorted.
+    pub fn diff<'a>(&'a self, other: &'a Hash<T>) -> Diff<'a>
+    where
+        T: PartialEq,
+    {
+        let mut diff = Diff::default();
+
+        for (name, value) in self.iter() {
+            match other.lookup(name) {
+                None => diff.removed.push(name),
+                Some(theirs) if *theirs != *value => diff.changed.push(name),
+                Some(_) => {}
+            }
+        }
+        diff.added = other
+            .iter()
+            .filter(|&(name, _)| self.lookup(name).is_none())
+            .map(|(name, _)| name)
+            .collect();
+
+        diff.added.sort_unstable();
+        diff.removed.sort_unstable();
+        diff.changed.sort_unstable();
+        diff
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            entries: self.count,
+            buckets: self.table.len(),
+            bits: self.bits,
+            split_bucket: self.split_bucket,
+            empty_buckets: self.table.iter().filter(|b| b.is_empty()).count(),
+            longest_bucket: self.table.iter().map(|b| b.len()).max().unwrap_or(0),
+        }
+    }
+
+    // Streams the table to `w` a bucket at a time, so a snapshot can go
+    // straight into a socket or a compressor without being built in memory
+    // first. The format is SNAPSHOT_MAGIC and the entry count, then every
+    // key and value (encoded by LogValue), each prefixed by its length as a
+    // big-endian u32. Writes are small: hand it a BufWriter.
+    pub fn write_snapshot<W: Write>(&self, mut w: W) -> io::Result<()>
+    where
+        T: LogValue,
+    {
+        w.write_all(SNAPSHOT_MAGIC)?;
+        w.write_all(&(self.count as u64).to_be_bytes())?;
+        for (name, value) in self.iter() {
+            oplog::write_bytes(&mut w, name.as_bytes())?;
+            oplog::write_bytes(&mut w, &value.encode())?;
+        }
+        w.flush()
+    }
+
+    // Reads back a table written by write_snapshot()
+    pub fn read_snapshot<R: Read>(mut r: R) -> io::Result<Self>
+    where
+        T: LogValue,
+    {
+        let mut magic = [0; 8];
+        r.read_exact(&mut magic)?;
+        if magic != *SNAPSHOT_MAGIC {
+            return Err(oplog::invalid("not a snapshot"));
+        }
+        let mut count = [0; 8];
+        r.read_exact(&mut count)?;
+        let count = u64::from_be_bytes(count);
+
+        let mut hash = Hash::new();
+        // The count comes from outside, so it only sizes the table up to a
+        // point; past that the table grows as usual
+        hash.reserve(count.min(SNAPSHOT_PRESIZE) as usize);
+        for _ in 0..count {
+            let name = String::from_utf8(oplog::read_bytes(&mut r)?)
+                .map_err(|_| oplog::invalid("key is not UTF-8"))?;
+            let value = T::decode(oplog::read_bytes(&mut r)?)
+                .ok_or_else(|| oplog::invalid("bad value"))?;
+            hash.upsert(&name, value);
+        }
+        Ok(hash)
+    }
+}
+
+// The number of address bits for N buckets. As an associated const it's
+// evaluated when with_buckets::<N>() is compiled, so a bad N fails the build.
+struct InitialBuckets<const N: usize>;
+
+impl<const N: usize> InitialBuckets<N> {
+    const BITS: usize = {
+        assert!(N.is_power_of_two() && N >= NHASH, "initial buckets must be a power of two >= 32");
+        N.trailing_zeros() as usize
+    };
+}
+
+// Starts every snapshot; the last byte is the format version
+const SNAPSHOT_MAGIC: &[u8; 8] = b"STUPIDH\x01";
+const SNAPSHOT_PRESIZE: u64 = 1 << 20;
+
+// A snapshot of the table's internal layout, for debugging and for studying
+// how the table splits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Stats {
+    pub entries: usize,
+    pub buckets: usize,
+    pub bits: usize,
+    pub split_bucket: usize,
+    pub empty_buckets: usize,
+    pub longest_bucket: usize,
+}
+
+// The result of Hash::diff()
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Diff<'a> {
+    pub added: Vec<&'a str>,
+    pub removed: Vec<&'a str>,
+    pub changed: Vec<&'a str>,
+}
+
+impl<'a> Diff<'a> {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+pub struct Iter<'a, T: 'a + Clone> {
+    buckets: slice::Iter<'a, Vec<NameVal<T>>>,
+    entries: slice::Iter<'a, NameVal<T>>,
+}
+
+impl<'a, T> Iterator for Iter<'a, T>
+where
+    T: Clone,
+{
+    type Item = (&'a str, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.entries.next() {
+                return Some((&entry.name, &entry.value));
+            }
+            self.entries = self.buckets.next()?.iter();
+        }
+    }
+}
+
+pub struct IterMut<'a, T: 'a + Clone> {
+    buckets: slice::IterMut<'a, Vec<NameVal<T>>>,
+    entries: slice::IterMut<'a, NameVal<T>>,
+}
+
+impl<'a, T> Iterator for IterMut<'a, T>
+where
+    T: Clone,
+{
+    type Item = (&'a str, &'a mut T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.entries.next() {
+                return Some((&entry.name, &mut entry.value));
+            }
+            self.entries = self.buckets.next()?.iter_mut();
+        }
+    }
+}
+
+pub struct Buckets<'a, T: 'a + Clone> {
+    inner: iter::Enumerate<slice::Iter<'a, Vec<NameVal<T>>>>,
+}
+
+impl<'a, T> Iterator for Buckets<'a, T>
+where
+    T: Clone,
+{
+    type Item = (usize, &'a [NameVal<T>]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(i, bucket)| (i, &bucket[..]))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<'a, T> ExactSizeIterator for Buckets<'a, T> where T: Clone {}
+
+// Moves the entries out of a table, bucket by bucket
+pub struct IntoIter<T: Clone> {
+    buckets: vec::IntoIter<Vec<NameVal<T>>>,
+    entries: vec::IntoIter<NameVal<T>>,
+}
+
+impl<T> Iterator for IntoIter<T>
+where
+    T: Clone,
+{
+    type Item = (String, T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.entries.next() {
+                return Some((key::into_string(entry.name), entry.value));
+            }
+            self.entries = self.buckets.next()?.into_iter();
+        }
+    }
+}
+
+impl<T> IntoIterator for Hash<T>
+where
+    T: Clone,
+{
+    type Item = (String, T);
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> IntoIter<T> {
+        IntoIter {
+            buckets: self.table.into_iter(),
+            entries: Vec::new().into_iter(),
+        }
+    }
+}
+
+pub struct IntoKeys<T: Clone> {
+    inner: IntoIter<T>,
+}
+
+impl<T> Iterator for IntoKeys<T>
+where
+    T: Clone,
+{
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        self.inner.next().map(|(name, _)| name)
+    }
+}
+
+pub struct IntoValues<T: Clone> {
+    inner: IntoIter<T>,
+}
+
+impl<T> Iterator for IntoValues<T>
+where
+    T: Clone,
+{
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.inner.next().map(|(_, value)| value)
+    }
+}
+
+pub struct ValuesCloned<'a, T: 'a + Clone> {
+    inner: Iter<'a, T>,
+}
+
+impl<'a, T> Iterator for ValuesCloned<'a, T>
+where
+    T: Clone,
+{
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.inner.next().map(|(_, value)| value.clone())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CasError<T> {
+    // The key isn't in the table; holds the rejected new value
+    NotFound(T),
+    // The stored value wasn't the expected one
+    Mismatch { current: T, new: T },
+}
+
+impl<T> fmt::Display for CasError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CasError::NotFound(_) => write!(f, "key not found"),
+            CasError::Mismatch { .. } => write!(f, "value changed"),
+        }
+    }
+}
+
+impl<T: fmt::Debug> Error for CasError<T> {}
+
+// A key's place in the table, from Hash::entry_ref()
+pub enum EntryRef<'a, 'k, T: 'a + Clone> {
+    Occupied(OccupiedEntryRef<'a, T>),
+    Vacant(VacantEntryRef<'a, 'k, T>),
+}
+
+pub struct OccupiedEntryRef<'a, T: 'a + Clone> {
+    hash: &'a mut Hash<T>,
+    bucket: usize,
+    index: usize,
+}
+
+pub struct VacantEntryRef<'a, 'k, T: 'a + Clone> {
+    hash: &'a mut Hash<T>,
+    name: &'k str,
+    // The key's full hash, so inserting doesn't compute it again
+    h: usize,
+}
+
+impl<'a, 'k, T> EntryRef<'a, 'k, T>
+where
+    T: Clone,
+{
+    pub fn key(&self) -> &str {
+        match *self {
+            EntryRef::Occupied(ref entry) => entry.key(),
+            EntryRef::Vacant(ref entry) => entry.key(),
+        }
+    }
+
+    pub fn or_insert(self, default: T) -> &'a mut T {
+        self.or_insert_with(|| default)
+    }
+
+    pub fn or_insert_with<F: FnOnce() -> T>(self, default: F) -> &'a mut T {
+        match self {
+            EntryRef::Occupied(entry) => entry.into_mut(),
+            EntryRef::Vacant(entry) => entry.insert(default()),
+        }
+    }
+
+    // Like or_insert_with(), for defaults computed from the key itself
+    pub fn or_insert_with_key<F: FnOnce(&str) -> T>(self, default: F) -> &'a mut T {
+        match self {
+            EntryRef::Occupied(entry) => entry.into_mut(),
+            EntryRef::Vacant(entry) => {
+                let value = default(entry.key());
+                entry.insert(value)
+            }
+        }
+    }
+
+    pub fn or_default(self) -> &'a mut T
+    where
+        T: Default,
+    {
+        self.or_insert_with(T::default)
+    }
+
+    // Updates the value if there is one, then hands the entry back
+    pub fn and_modify<F: FnOnce(&mut T)>(mut self, f: F) -> Self {
+        if let EntryRef::Occupied(ref mut entry) = self {
+            f(entry.get_mut());
+        }
+        self
+    }
+}
+
+impl<'a, T> OccupiedEntryRef<'a, T>
+where
+    T: Clone,
+{
+    pub fn key(&self) -> &str {
+        &self.hash.table[self.bucket][self.index].name
+    }
+
+    pub fn get(&self) -> &T {
+        &self.hash.table[self.bucket][self.index].value
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.hash.table[self.bucket][self.index].value
+    }
+
+    pub fn into_mut(self) -> &'a mut T {
+        &mut self.hash.table[self.bucket][self.index].value
+    }
+
+    // Replaces the value, returning the old one
+    pub fn insert(&mut self, value: T) -> T {
+        mem::replace(self.get_mut(), value)
+    }
+
+    pub fn remove(self) -> T {
+        self.hash.remove_at(self.bucket, self.index).value
+    }
+}
+
+impl<'a, 'k, T> VacantEntryRef<'a, 'k, T>
+where
+    T: Clone,
+{
+    pub fn key(&self) -> &'k str {
+        self.name
+    }
+
+    // Inserts the value under an owned copy of the key
+    pub fn insert(self, value: T) -> &'a mut T {
+        let (hash, name, h) = (self.hash, self.name, self.h);
+        hash.check_key_len(name);
+        if let Some(ref mut index) = hash.prefix_index {
+            index.insert(name);
+        }
+        let entry = NameVal {
+            name: key::from_str(name),
+            value,
+        };
+        hash.insert_hashed(entry, h);
+
+        // The insert may have split the bucket it went into
+        let (b, i) = hash.find_hashed(name, h).unwrap();
+        &mut hash.table[b][i].value
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cursor {
+    bucket: usize,
+    entry: usize,
+    generation: u64,
+}
+
+// The table was modified after the cursor was made
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StaleCursor;
+
+impl fmt::Display for StaleCursor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "table modified since the cursor was created")
+    }
+}
+
+impl Error for StaleCursor {}
+
+// A prefix-scoped view of a table, from Hash::namespace()
+pub struct Namespace<'a, T: 'a + Clone> {
+    hash: &'a mut Hash<T>,
+    prefix: String,
+}
+
+impl<'a, T> Namespace<'a, T>
+where
+    T: Clone,
+{
+    fn key(&self, name: &str) -> String {
+        let mut key = String::with_capacity(self.prefix.len() + name.len());
+        key.push_str(&self.prefix);
+        key.push_str(name);
+        key
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<&T> {
+        self.hash.lookup(&self.key(name))
+    }
+
+    pub fn lookup_mut(&mut self, name: &str) -> Option<&mut T> {
+        let key = self.key(name);
+        self.hash.lookup_mut(&key)
+    }
+
+    pub fn upsert(&mut self, name: &str, value: T) -> bool {
+        let key = self.key(name);
+        self.hash.upsert(&key, value)
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<T> {
+        let key = self.key(name);
+        self.hash.remove(&key)
+    }
+
+    // The namespace's entries, with their names as seen through it
+    pub fn entries(&self) -> Vec<(&str, &T)> {
+        let skip = self.prefix.len();
+        let mut entries = self.hash.keys_with_prefix(&self.prefix);
+        for entry in entries.iter_mut() {
+            entry.0 = &entry.0[skip..];
+        }
+        entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries().is_empty()
+    }
+
+    // Removes every key in the namespace, leaving the rest of the table
+    // alone. Returns how many were removed.
+    pub fn clear_namespace(&mut self) -> usize {
+        let names: Vec<String> = self
+            .hash
+            .keys_with_prefix(&self.prefix)
+            .into_iter()
+            .map(|(name, _)| name.to_string())
+            .collect();
+        for name in &names {
+            self.hash.remove(name);
+        }
+        names.len()
+    }
+
+    // A namespace inside this one: its prefix follows this one's
+    pub fn namespace(&mut self, prefix: &str) -> Namespace<'_, T> {
+        let prefix = self.key(prefix);
+        Namespace {
+            hash: self.hash,
+            prefix,
+        }
+    }
+}
+
+impl<T> Clone for Hash<T>
+where
+    T: Clone,
+{
+    fn clone(&self) -> Self {
+        Hash {
+            table: self.table.clone(),
+            bits: self.bits,
+            split_bucket: self.split_bucket,
+            count: self.count,
+            generation: self.generation,
+            split_policy: self.split_policy,
+            two_choice: self.two_choice,
+            incremental: self.incremental,
+            deferred: self.deferred,
+            migration: self.migration,
+            prefix_index: self.prefix_index.clone(),
+            min_buckets: self.min_buckets,
+            hashing: self.hashing,
+            max_key_len: self.max_key_len,
+        }
+    }
+
+    // Copies `source` into this table's existing allocations: buckets that
+    // exist in both keep their buffers, and so do the keys in them.
+    fn clone_from(&mut self, source: &Self) {
+        self.table.clone_from(&source.table);
+        self.bits = source.bits;
+        self.split_bucket = source.split_bucket;
+        self.count = source.count;
+        // Cursors on this table must not carry over to the new contents
+        self.generation = self.generation.max(source.generation) + 1;
+        self.split_policy = source.split_policy;
+        self.two_choice = source.two_choice;
+        self.incremental = source.incremental;
+        self.deferred = source.deferred;
+        self.min_buckets = source.min_buckets;
+        self.hashing = source.hashing;
+        self.max_key_len = source.max_key_len;
+        self.migration = source.migration;
+        self.prefix_index.clone_from(&source.prefix_index);
+    }
+}
+
+impl<T> Default for Hash<T>
+where
+    T: Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// `{:?}` prints the entries like a map. `{:#?}` prints the physical layout
+// instead: the addressing state and which keys sit in which bucket, which
+// is the interesting part when studying how linear hashing splits.
+impl<T> fmt::Debug for Hash<T>
+where
+    T: Clone + fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !f.alternate() {
+            return f.debug_map().entries(self.iter()).finish();
+        }
+
+        // The keys of each bucket, by bucket index
+        struct Layout<'a, T: 'a + Clone>(&'a [Vec<NameVal<T>>]);
+        struct Keys<'a, T: 'a + Clone>(&'a [NameVal<T>]);
+
+        impl<'a, T: Clone> fmt::Debug for Layout<'a, T> {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.debug_map().entries(self.0.iter().map(|b| Keys(b)).enumerate()).finish()
+            }
+        }
+        impl<'a, T: Clone> fmt::Debug for Keys<'a, T> {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.debug_list().entries(self.0.iter().map(|entry| entry.name())).finish()
+            }
+        }
+
+        let mut s = f.debug_struct("Hash");
+        s.field("bits", &self.bits)
+            .field("split_bucket", &self.split_bucket)
+            .field("count", &self.count)
+            .field("split_policy", &self.split_policy);
+        if let Some(ref m) = self.migration {
+            s.field("migration", m);
+        }
+        s.field("buckets", &Layout(&self.table)).finish()
+    }
+}
+
+// Hash::from([("a", 1), ("b", 2)]), sized for the N entries up front. A
+// repeated key keeps its last value.
+impl<K, T, const N: usize> From<[(K, T); N]> for Hash<T>
+where
+    K: AsRef<str>,
+    T: Clone,
+{
+    fn from(entries: [(K, T); N]) -> Self {
+        let mut hash = Hash::new();
+        hash.upsert_many(entries);
+        hash
+    }
+}
+
+// Borrowed keys are only copied for the entries that are new
+impl<'a, T> Extend<(&'a str, T)> for Hash<T>
+where
+    T: Clone,
+{
+    fn extend<I: IntoIterator<Item = (&'a str, T)>>(&mut self, iter: I) {
+        self.upsert_many(iter);
+    }
+}
+
+pub struct ScanGlob<'a, T: 'a + Clone> {
+    iter: Iter<'a, T>,
+    pattern: Pattern,
+}
+
+impl<'a, T> Iterator for ScanGlob<'a, T>
+where
+    T: Clone,
+{
+    type Item = (&'a str, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let pattern = &self.pattern;
+        self.iter.find(|&(name, _)| pattern.matches(name))
+    }
+}
+
+#[test]
+fn basics() {
+    let mut hashtab = Hash::new();
+    assert_eq!(hashtab.lookup("abc"), None);
+    hashtab.upsert("abc", 64);
+    hashtab.upsert("abcdefghijklmnopq", 128);
+    assert_eq!(hashtab.lookup("abc"), Some(&64));
+    assert_eq!(hashtab.lookup("abc"), Some(&64));
+    assert_eq!(hashtab.lookup("abcdefghijklmnopq"), Some(&128));
+    hashtab.upsert("abc", 256);
+    assert_eq!(hashtab.lookup("abc"), Some(&256));
+    hashtab.remove("abc");
+    assert_eq!(hashtab.lookup("abc"), None);
+    assert_eq!(hashtab.lookup("abcd"), None);
+    let nippon = "私はガラスを食べられます。それは私を傷つけません。";
+    hashtab.upsert(nippon, 31337);
+    assert_eq!(hashtab.lookup(nippon), Some(&31337));
+    hashtab.remove(nippon);
+    assert_eq!(hashtab.lookup(nippon), None);
+    println!("done");
+}
+
+#[test]
+fn test_hash_key() {
+    // Pinned values: changing any of these means bumping HASH_VERSION
+    assert_eq!(hash_key(""), 0);
+    assert_eq!(hash_key("a"), 97);
+    assert_eq!(hash_key("ab"), 97 * 31 + 98);
+    assert_eq!(hash_key("stupid_hash"), 0x015a_2189_2c4c_5176);
+
+    for name in &["", "key", "私はガラス", "a much longer key than the others"] {
+        assert_eq!(hash_key(name) as usize, hash_str(name));
+    }
+}
+
+#[test]
+fn test_precomputed_hash() {
+    let mut hashtab = Hash::with_two_choices();
+    let hashes: Vec<(String, u64)> = (0..500)
+        .map(|i| {
+            let name = format!("user:{}", i);
+            let h = hash_key(&name);
+            (name, h)
+        })
+        .collect();
+
+    for (i, &(ref name, h)) in hashes.iter().enumerate() {
+        assert!(hashtab.upsert_with_hash(name, h, i));
+    }
+    for (i, &(ref name, h)) in hashes.iter().enumerate() {
+        assert_eq!(hashtab.lookup_with_hash(name, h), Some(&i));
+        assert_eq!(hashtab.lookup(name), Some(&i));
+    }
+    assert!(!hashtab.upsert_with_hash("user:0", hash_key("user:0"), 7));
+    assert_eq!(hashtab.lookup("user:0"), Some(&7));
+}
+
+#[test]
+fn test_raw_buckets() {
+    let mut hashtab = Hash::new();
+    for i in 0..200 {
+        hashtab.upsert(&i.to_string(), i);
+    }
+
+    let b = hashtab.bucket_index_for("42");
+    let bucket = hashtab.bucket(b).unwrap();
+    assert!(bucket.iter().any(|entry| entry.name() == "42" && *entry.value() == 42));
+    assert!(bucket.iter().all(|entry| hashtab.bucket_index_for(entry.name()) == b));
+    assert!(hashtab.bucket(hashtab.bucket_count()).is_none());
+
+    for entry in hashtab.bucket_mut(b).unwrap() {
+        *entry.value_mut() += 1000;
+    }
+    assert_eq!(hashtab.lookup("42"), Some(&1042));
+}
+
+#[test]
+fn test_buckets() {
+    let mut hashtab = Hash::new();
+    for i in 0..1000 {
+        hashtab.upsert(&i.to_string(), i);
+    }
+
+    let buckets = hashtab.buckets();
+    assert_eq!(buckets.len(), hashtab.bucket_count());
+
+    let mut total = 0;
+    for (i, bucket) in buckets {
+        assert_eq!(bucket.len(), hashtab.bucket(i).unwrap().len());
+        assert!(bucket.iter().all(|entry| hashtab.bucket_index_for(entry.name()) == i));
+        total += bucket.len();
+    }
+    assert_eq!(total, 1000);
+}
+
+#[test]
+fn test_split() {
+    let mut hashtab = Hash::new();
+
+    let n_entries = 8192;
+
+    for i in 0..n_entries {
+        let k = i.to_string();
+        hashtab.upsert(&k, i);
+    }
+
+    for i in 0..n_entries {
+        let k = i.to_string();
+        let val = hashtab.lookup(&k);
+
+        assert!(val.is_some());
+        let unwrapped = val.unwrap();
+        assert_eq!(*unwrapped, i);
+    }
+}
+
+#[test]
+fn test_upsert_many() {
+    let mut hashtab = Hash::new();
+    hashtab.upsert("7", 0);
+
+    let added = hashtab.upsert_many((0..5000).map(|i| (i.to_string(), i)));
+    assert_eq!(added, 4999);
+    assert_eq!(hashtab.len(), 5000);
+    assert!(hashtab.bucket_count() >= 2500);
+    for i in 0..5000 {
+        assert_eq!(hashtab.lookup(&i.to_string()), Some(&i));
+    }
+
+    // No size hint to go on, so the table grows as usual
+    let mut lazy = Hash::new();
+    let added = lazy.upsert_many((0..5000).filter(|i| i % 2 == 0).map(|i| (i.to_string(), i)));
+    assert_eq!(added, 2500);
+    for i in (0..5000).filter(|i| i % 2 == 0) {
+        assert_eq!(lazy.lookup(&i.to_string()), Some(&i));
+    }
+}
+
+#[test]
+fn test_cas() {
+    let mut hashtab = Hash::new();
+    hashtab.upsert("balance", 100);
+
+    assert_eq!(hashtab.cas("balance", &100, 80), Ok(()));
+    assert_eq!(
+        hashtab.cas("balance", &100, 60),
+        Err(CasError::Mismatch { current: 80, new: 60 })
+    );
+    assert_eq!(hashtab.lookup("balance"), Some(&80));
+    assert_eq!(hashtab.cas("missing", &0, 1), Err(CasError::NotFound(1)));
+    assert_eq!(hashtab.lookup("missing"), None);
+}
+
+#[test]
+fn test_upsert_with() {
+    let mut totals = Hash::new();
+    for &(name, n) in &[("a", 1), ("b", 2), ("a", 3), ("a", 5)] {
+        totals.upsert_with(name, n, |old, new| *old += new);
+    }
+    assert_eq!(totals.lookup("a"), Some(&9));
+    assert_eq!(totals.lookup("b"), Some(&2));
+
+    let mut lists: Hash<Vec<&str>> = Hash::new();
+    assert!(lists.upsert_with("k", vec!["x"], |old, new| old.extend(new)));
+    assert!(!lists.upsert_with("k", vec!["y", "z"], |old, new| old.extend(new)));
+    assert_eq!(lists.lookup("k"), Some(&vec!["x", "y", "z"]));
+}
+
+#[test]
+fn test_split_policy() {
+    let mut controlled = Hash::new();
+    let mut overflow = Hash::with_split_policy(SplitPolicy::BucketOverflow);
+    assert_eq!(controlled.split_policy(), SplitPolicy::LoadFactor(DEFAULT_MAX_LOAD));
+
+    for i in 0..4000 {
+        controlled.upsert(&i.to_string(), i);
+        overflow.upsert(&i.to_string(), i);
+        assert!(controlled.len() as f64 <= controlled.bucket_count() as f64 * DEFAULT_MAX_LOAD);
+    }
+
+    assert_eq!(controlled.bucket_count(), 2000);
+    assert!(overflow.bucket_count() < controlled.bucket_count());
+    assert!(controlled.stats().longest_bucket < overflow.stats().longest_bucket);
+    for i in 0..4000 {
+        assert_eq!(controlled.lookup(&i.to_string()), Some(&i));
+        assert_eq!(overflow.lookup(&i.to_string()), Some(&i));
+    }
+
+    let mut sparse = Hash::with_split_policy(SplitPolicy::LoadFactor(0.5));
+    sparse.upsert_many((0..100).map(|i| (i.to_string(), i)));
+    assert_eq!(sparse.bucket_count(), 200);
+}
+
+#[test]
+fn test_remove_within_bucket() {
+    // Few buckets and no splits, so every bucket holds several keys
+    let mut hashtab = Hash::with_split_policy(SplitPolicy::BucketOverflow);
+    for i in 0..200 {
+        hashtab.upsert(&i.to_string(), i);
+    }
+    assert!(hashtab.stats().longest_bucket > 2);
+
+    for i in (0..200).filter(|i| i % 3 == 1) {
+        assert_eq!(hashtab.remove(&i.to_string()), Some(i));
+    }
+    for i in 0..200 {
+        let expected = if i % 3 == 1 { None } else { Some(&i) };
+        assert_eq!(hashtab.lookup(&i.to_string()), expected);
+    }
+}
+
+#[test]
+fn test_try_reserve() {
+    let mut hashtab = Hash::new();
+    assert_eq!(hashtab.try_upsert("a", 1), Ok(true));
+    assert_eq!(hashtab.try_upsert("a", 2), Ok(false));
+    assert_eq!(hashtab.lookup("a"), Some(&2));
+
+    assert!(hashtab.try_reserve(1000).is_ok());
+    assert!(hashtab.bucket_count() >= 500);
+    assert!(hashtab.try_reserve(usize::MAX).is_err());
+    assert_eq!(hashtab.lookup("a"), Some(&2));
+}
+
+#[test]
+fn test_fallible_ops() {
+    assert!(Hash::<u8>::try_new().is_ok());
+    for &load in &[0.0, -1.0, f64::NAN] {
+        let e = Hash::<u8>::try_with_split_policy(SplitPolicy::LoadFactor(load)).err();
+        assert!(matches!(e, Some(error::Error::InvalidConfig(_))));
+    }
+
+    // The fallible paths end up with the same table as the ordinary ones,
+    // in every split mode
+    let modes: [fn() -> Hash<usize>; 4] = [
+        Hash::new,
+        Hash::with_two_choices,
+        Hash::with_incremental_splits,
+        || Hash::with_split_policy(SplitPolicy::BucketOverflow),
+    ];
+    for make in &modes {
+        let (mut plain, mut fallible) = (make(), make());
+        for i in 0..3000 {
+            plain.upsert(&i.to_string(), i);
+            assert_eq!(fallible.try_upsert(&i.to_string(), i), Ok(true));
+        }
+        assert_eq!(fallible.try_upsert_with("7", 1, |old, new| *old += new), Ok(false));
+        fallible.check_invariants().unwrap();
+        assert_eq!(fallible.lookup("7"), Some(&8));
+        assert_eq!(fallible.bucket_count(), plain.bucket_count());
+
+        let copy = fallible.try_clone().unwrap();
+        let entries: Vec<(&str, &usize)> = fallible.iter_stable().collect();
+        assert_eq!(copy.iter_stable().collect::<Vec<_>>(), entries);
+
+        let (mut reserved, mut reserved_plain) = (make(), make());
+        reserved.try_reserve(5000).unwrap();
+        reserved_plain.reserve(5000);
+        assert_eq!(reserved.bucket_count(), reserved_plain.bucket_count());
+        reserved.check_invariants().unwrap();
+
+        for i in 0..3000 {
+            assert_eq!(fallible.remove(&i.to_string()), Some(i + (i == 7) as usize));
+        }
+        fallible.check_invariants().unwrap();
+        assert!(fallible.is_empty());
+    }
+}
+
+#[test]
+fn test_with_config() {
+    let text = "initial_buckets = 64\nmultiplier = 131\nseed = 99\nmax_key_len = 8\n";
+    for mode in &["eager", "two-choice", "incremental", "deferred"] {
+        let config: HashConfig = format!("{}split_mode = \"{}\"", text, mode).parse().unwrap();
+        let mut hashtab = Hash::with_config(&config).unwrap();
+        assert_eq!(hashtab.config(), config);
+        assert_eq!(hashtab.bucket_count(), 64);
+
+        for i in 0..2000 {
+            let name = i.to_string();
+            assert!(hashtab.upsert_with_hash(&name, hash_key(&name), i));
+        }
+        hashtab.maintain();
+        hashtab.check_invariants().unwrap();
+        assert_eq!(hashtab.lookup_with_hash("1234", hash_key("1234")), Some(&1234));
+        assert_eq!(hashtab.clone().lookup("1999"), Some(&1999));
+
+        let e = hashtab.try_insert("much too long", 0).unwrap_err();
+        assert_eq!(e.to_string(), "key of 13 bytes is over the limit of 8");
+        assert!(hashtab.try_insert("12345678", 0).unwrap());
+        assert_eq!(hashtab.lookup("much too long"), None);
+    }
+
+    // Another seed puts the keys elsewhere
+    let mut plain = Hash::new();
+    let mut seeded = Hash::with_config(&"seed = 1".parse().unwrap()).unwrap();
+    for i in 0..100 {
+        plain.upsert(&i.to_string(), i);
+        seeded.upsert(&i.to_string(), i);
+    }
+    let moved = (0..100).filter(|i| {
+        let name = i.to_string();
+        plain.bucket_index_for(&name) != seeded.bucket_index_for(&name)
+    });
+    assert!(moved.count() > 50);
+    assert_eq!(Hash::<u8>::new().config(), HashConfig::default());
+
+    let bounded = "eviction = \"oldest\"\ncapacity = 10\nmax_backlog = 1".parse().unwrap();
+    assert!(matches!(
+        Hash::<u8>::with_config(&bounded),
+        Err(error::Error::InvalidConfig(_))
+    ));
+}
+
+#[test]
+fn test_remove_without_memory() {
+    use testutil::without_memory;
+
+    let modes: [fn() -> Hash<usize>; 3] = [Hash::new, Hash::with_incremental_splits, || Hash {
+        incremental: true,
+        ..Hash::with_split_policy(SplitPolicy::BucketOverflow)
+    }];
+    for make in &modes {
+        let mut hashtab = make();
+        for i in 0..3000 {
+            hashtab.upsert(&i.to_string(), i);
+        }
+        let buckets = hashtab.bucket_count();
+        let names: Vec<String> = (0..2950).map(|i| i.to_string()).collect();
+
+        // Merges, migration steps and shrinking buckets all wait. (pop() is
+        // the same, but has to allocate the String it returns for a key
+        // stored inline.)
+        without_memory(|| {
+            for (i, name) in names.iter().enumerate() {
+                assert_eq!(hashtab.remove(name), Some(i));
+            }
+        });
+        hashtab.check_invariants().unwrap();
+        assert_eq!(hashtab.len(), 50);
+
+        // ...and happen as soon as there's memory again
+        let rest: Vec<String> = hashtab.iter().map(|(name, _)| name.to_string()).collect();
+        for name in &rest {
+            hashtab.remove(name);
+        }
+        hashtab.check_invariants().unwrap();
+        assert!(hashtab.bucket_count() <= buckets);
+        assert!(hashtab.is_empty());
+    }
+}
+
+#[test]
+fn test_iter_stable() {
+    let mut forwards = Hash::new();
+    let mut backwards = Hash::with_split_policy(SplitPolicy::BucketOverflow);
+    for i in 0..3000 {
+        forwards.upsert(&i.to_string(), i);
+    }
+    for i in (0..3500).rev() {
+        backwards.upsert(&i.to_string(), i);
+    }
+    for i in 3000..3500 {
+        backwards.remove(&i.to_string());
+    }
+    assert_ne!(forwards.bucket_count(), backwards.bucket_count());
+
+    let a: Vec<(&str, &i32)> = forwards.iter_stable().collect();
+    let b: Vec<(&str, &i32)> = backwards.iter_stable().collect();
+    assert_eq!(a.len(), 3000);
+    assert_eq!(a, b);
+}
+
+#[test]
+fn test_cursor() {
+    let mut hashtab = Hash::new();
+    for i in 0..500 {
+        hashtab.upsert(&i.to_string(), i);
+    }
+
+    let mut cursor = hashtab.cursor();
+    let mut seen = Vec::new();
+    while let Some((_, &value)) = hashtab.cursor_next(&mut cursor).unwrap() {
+        seen.push(value);
+        // Overwriting values doesn't move anything
+        *hashtab.lookup_mut("0").unwrap() = 0;
+    }
+    seen.sort();
+    assert_eq!(seen, (0..500).collect::<Vec<_>>());
+    assert_eq!(hashtab.cursor_next(&mut cursor), Ok(None));
+
+    let mut cursor = hashtab.cursor();
+    hashtab.cursor_next(&mut cursor).unwrap();
+    hashtab.upsert("new", 1);
+    assert_eq!(hashtab.cursor_next(&mut cursor), Err(StaleCursor));
+
+    let mut cursor = hashtab.cursor();
+    hashtab.remove("new");
+    assert_eq!(hashtab.cursor_next(&mut cursor), Err(StaleCursor));
+}
+
+#[test]
+fn test_diff() {
+    let mut observed = Hash::new();
+    let mut desired = Hash::new();
+    for &(name, replicas) in &[("web", 3), ("db", 1), ("cache", 2)] {
+        observed.upsert(name, replicas);
+    }
+    for &(name, replicas) in &[("web", 5), ("db", 1), ("queue", 1), ("auth", 2)] {
+        desired.upsert(name, replicas);
+    }
+
+    let diff = observed.diff(&desired);
+    assert_eq!(diff.added, vec!["auth", "queue"]);
+    assert_eq!(diff.removed, vec!["cache"]);
+    assert_eq!(diff.changed, vec!["web"]);
+    assert!(!diff.is_empty());
+    assert!(desired.diff(&desired).is_empty());
+}
+
+#[test]
+fn test_key_set_comparisons() {
+    let mut defaults = Hash::new();
+    let mut config = Hash::new();
+    for name in &["host", "port", "timeout"] {
+        defaults.upsert(name, 0);
+    }
+    config.upsert("host", "example.com");
+    config.upsert("port", "80");
+
+    assert!(config.is_subset(&defaults));
+    assert!(!defaults.is_subset(&config));
+    assert!(defaults.is_superset(&config));
+    assert!(!config.same_keys(&defaults));
+
+    config.upsert("timeout", "30");
+    assert!(config.same_keys(&defaults));
+    config.upsert("retries", "3");
+    config.remove("port");
+    assert!(!config.same_keys(&defaults));
+    assert!(!config.is_subset(&defaults) && !config.is_superset(&defaults));
+}
+
+#[test]
+fn test_map_values() {
+    let mut hashtab = Hash::new();
+    for i in 0..1000 {
+        hashtab.upsert(&i.to_string(), i);
+    }
+    let buckets = hashtab.bucket_count();
+
+    let strings = hashtab.map_values(|i| format!("#{}", i));
+    assert_eq!(strings.len(), 1000);
+    assert_eq!(strings.bucket_count(), buckets);
+    assert_eq!(strings.lookup("42"), Some(&"#42".to_string()));
+
+    let mut odd = strings.filter_map(|name, value| {
+        if name.ends_with(|c: char| "13579".contains(c)) {
+            Some(value.len())
+        } else {
+            None
+        }
+    });
+    assert_eq!(odd.len(), 500);
+    assert_eq!(odd.lookup("41"), Some(&3));
+    assert_eq!(odd.lookup("42"), None);
+    assert!(odd.upsert("1001", 5));
+    assert_eq!(odd.len(), 501);
+}
+
+#[test]
+fn test_into_iter() {
+    let build = || {
+        let mut hashtab = Hash::new();
+        for i in 0..300 {
+            hashtab.upsert(&i.to_string(), vec![i]);
+        }
+        hashtab
+    };
+
+    let mut pairs: Vec<(String, Vec<i32>)> = build().into_iter().collect();
+    pairs.sort_by_key(|pair| pair.1[0]);
+    assert_eq!(pairs.len(), 300);
+    assert_eq!(pairs[7], ("7".to_string(), vec![7]));
+
+    let mut keys: Vec<String> = build().into_keys().collect();
+    keys.sort();
+    assert_eq!(keys.len(), 300);
+    assert_eq!(keys[0], "0");
+
+    let mut values: Vec<i32> = build().into_values().map(|v| v[0]).collect();
+    values.sort();
+    assert_eq!(values, (0..300).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_par_values_mut() {
+    let mut hashtab = Hash::new();
+    for i in 0..10_000 {
+        hashtab.upsert(&i.to_string(), i as f64);
+    }
+
+    // Decay everything, and zero a few keys by name
+    hashtab.par_values_mut(|name, value| {
+        *value *= 0.5;
+        if name.starts_with("99") {
+            *value = 0.0;
+        }
+    });
+
+    assert_eq!(hashtab.lookup("10"), Some(&5.0));
+    assert_eq!(hashtab.lookup("9999"), Some(&0.0));
+    assert_eq!(hashtab.len(), 10_000);
+}
+
+#[test]
+fn test_pop() {
+    let mut queue = Hash::new();
+    queue.enable_prefix_index();
+    for i in 0..2000 {
+        queue.upsert(&format!("job{}", i), i);
+    }
+
+    let mut done = Vec::new();
+    while let Some((name, i)) = queue.pop() {
+        assert_eq!(name, format!("job{}", i));
+        done.push(i);
+    }
+    done.sort();
+    assert_eq!(done, (0..2000).collect::<Vec<_>>());
+    assert!(queue.is_empty());
+    assert_eq!(queue.bucket_count(), NHASH);
+    assert!(queue.keys_with_prefix("job").is_empty());
+    assert_eq!(queue.pop(), None);
+}
+
+#[test]
+fn test_sample() {
+    let mut hashtab = Hash::new();
+    for i in 0..100 {
+        hashtab.upsert(&i.to_string(), i);
+    }
+
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut xorshift = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+
+    let mut hits = vec![0; 100];
+    for _ in 0..2000 {
+        let sample = hashtab.sample(10, &mut xorshift);
+        assert_eq!(sample.len(), 10);
+        for (_, &value) in sample {
+            hits[value] += 1;
+        }
+    }
+    // Each entry is expected 200 times
+    assert!(hits.iter().all(|&n| n > 120 && n < 280), "{:?}", hits);
+
+    assert_eq!(hashtab.sample(1000, &mut xorshift).len(), 100);
+    assert!(hashtab.sample(0, &mut xorshift).is_empty());
+}
+
+#[test]
+fn test_par_extend() {
+    let mut hashtab = Hash::new();
+    hashtab.upsert("7", -1);
+
+    let added = hashtab.par_extend((0..20_000).map(|i| (i.to_string(), i)));
+    assert_eq!(added, 19_999);
+    assert_eq!(hashtab.len(), 20_000);
+    for i in 0..20_000 {
+        assert_eq!(hashtab.lookup(&i.to_string()), Some(&i));
+    }
+    assert!(hashtab.len() as f64 <= hashtab.bucket_count() as f64 * DEFAULT_MAX_LOAD);
+
+    // Later inserts and removals carry on from the bulk-loaded state
+    assert!(hashtab.upsert("new", 1));
+    assert_eq!(hashtab.remove("19999"), Some(19_999));
+    assert_eq!(hashtab.len(), 20_000);
+
+    let mut indexed = Hash::new();
+    indexed.enable_prefix_index();
+    assert_eq!(indexed.par_extend(vec![("a", 1), ("ab", 2)]), 2);
+    assert_eq!(indexed.keys_with_prefix("a").len(), 2);
+    assert_eq!(Hash::<i32>::new().par_extend(Vec::<(String, i32)>::new()), 0);
+}
+
+#[test]
+fn test_capacity() {
+    let mut hashtab = Hash::new();
+    assert_eq!(hashtab.capacity(), 64);
+
+    for i in 0..1000 {
+        let (before, buckets) = (hashtab.capacity(), hashtab.bucket_count());
+        hashtab.upsert(&i.to_string(), i);
+        if before > 0 {
+            assert_eq!(hashtab.bucket_count(), buckets);
+            assert_eq!(hashtab.capacity(), before - 1);
+        } else {
+            assert!(hashtab.bucket_count() > buckets);
+        }
+    }
+
+    hashtab.reserve(500);
+    assert!(hashtab.capacity() >= 500);
+
+    let mut overflow = Hash::with_split_policy(SplitPolicy::BucketOverflow);
+    overflow.upsert("a", 1);
+    assert_eq!(overflow.capacity(), 31);
+}
+
+#[test]
+fn test_shrink_to() {
+    let mut hashtab = Hash::new();
+    hashtab.reserve(10_000);
+    for i in 0..1000 {
+        hashtab.upsert(&i.to_string(), i);
+    }
+    assert_eq!(hashtab.bucket_count(), 5000);
+
+    hashtab.shrink_to(2000);
+    assert_eq!(hashtab.bucket_count(), 2000);
+    hashtab.shrink_to(3000);
+    assert_eq!(hashtab.bucket_count(), 2000);
+
+    // Not below what 1000 entries need at the default load
+    hashtab.shrink_to(10);
+    assert_eq!(hashtab.bucket_count(), 500);
+    for i in 0..1000 {
+        assert_eq!(hashtab.lookup(&i.to_string()), Some(&i));
+    }
+
+    for i in 0..900 {
+        hashtab.remove(&i.to_string());
+    }
+    hashtab.shrink_to_fit();
+    assert_eq!(hashtab.bucket_count(), 50);
+    assert_eq!(hashtab.lookup("950"), Some(&950));
+}
+
+#[test]
+fn test_with_buckets() {
+    let mut hashtab = Hash::with_buckets::<256>();
+    assert_eq!((hashtab.stats().buckets, hashtab.stats().bits), (256, 8));
+
+    // 500 entries fit at the default load without a single split
+    for i in 0..500 {
+        hashtab.upsert(&i.to_string(), i);
+    }
+    assert_eq!(hashtab.bucket_count(), 256);
+    hashtab.check_invariants().unwrap();
+
+    // Growing past that splits as usual, and shrinking stops at 256
+    for i in 500..2000 {
+        hashtab.upsert(&i.to_string(), i);
+    }
+    assert!(hashtab.bucket_count() > 256);
+    for i in 0..2000 {
+        hashtab.remove(&i.to_string());
+    }
+    hashtab.shrink_to_fit();
+    assert_eq!(hashtab.bucket_count(), 256);
+    assert_eq!(hashtab.clone().bucket_count(), 256);
+    hashtab.check_invariants().unwrap();
+}
+
+#[test]
+fn test_clone_from() {
+    let fill = |offset: i32| {
+        let mut hashtab = Hash::new();
+        for i in 0..500 {
+            hashtab.upsert(&format!("key{:04}", i), i + offset);
+        }
+        hashtab
+    };
+    let source = fill(1000);
+    let mut dest = fill(0);
+    let cursor = dest.cursor();
+
+    let buffers: Vec<*const NameVal<i32>> = (0..dest.bucket_count())
+        .map(|i| dest.bucket(i).unwrap().as_ptr())
+        .collect();
+    let key = dest.bucket(0).unwrap()[0].name.as_ptr();
+
+    dest.clone_from(&source);
+    assert_eq!(dest.len(), 500);
+    for i in 0..500 {
+        assert_eq!(dest.lookup(&format!("key{:04}", i)), Some(&(i + 1000)));
+    }
+    for (i, &buffer) in buffers.iter().enumerate() {
+        assert_eq!(dest.bucket(i).unwrap().as_ptr(), buffer);
+    }
+    assert_eq!(dest.bucket(0).unwrap()[0].name.as_ptr(), key);
+    assert_eq!(dest.cursor_next(&mut cursor.clone()), Err(StaleCursor));
+
+    let copy = source.clone();
+    assert_eq!(copy.len(), 500);
+    assert!(copy.diff(&source).is_empty());
+}
+
+#[test]
+fn test_filter_map_mid_migration() {
+    let mut hashtab = Hash {
+        incremental: true,
+        ..Hash::with_split_policy(SplitPolicy::BucketOverflow)
+    };
+    let mut i = 0;
+    while hashtab.migration.is_none_or(|m| m.next == 0) {
+        hashtab.upsert(&i.to_string(), i);
+        i += 1;
+    }
+
+    // Most of the bucket being split goes, which used to leave the cursor
+    // past its end
+    let mut kept = hashtab.filter_map(|_, value| if value % 8 == 0 { Some(value) } else { None });
+    kept.check_invariants().unwrap();
+    for j in (0..i).filter(|j| j % 8 == 0) {
+        assert_eq!(kept.remove(&j.to_string()), Some(j));
+        kept.check_invariants().unwrap();
+    }
+    assert!(kept.is_empty());
+}
+
+#[test]
+fn test_incremental_splits() {
+    let mut hashtab = Hash::with_incremental_splits();
+    let mut plain = Hash::new();
+    let mut deferred = 0;
+
+    for i in 0..5000 {
+        hashtab.upsert(&i.to_string(), i);
+        plain.upsert(&i.to_string(), i);
+        if hashtab.migration.is_some() {
+            deferred += 1;
+        }
+        if i % 97 == 0 {
+            for j in 0..i + 1 {
+                assert_eq!(hashtab.lookup(&j.to_string()), Some(&j));
+            }
+        }
+    }
+    assert!(deferred > 0);
+    assert_eq!(hashtab.bucket_count(), plain.bucket_count());
+    assert_eq!(hashtab.len(), 5000);
+
+    let keys: Vec<String> = (0..5000).map(|i| i.to_string()).collect();
+    let values: Vec<Option<&i32>> = (0..5000).map(|i| plain.lookup(&i.to_string())).collect();
+    assert_eq!(hashtab.lookup_many(&keys), values);
+
+    for i in 0..4000 {
+        assert_eq!(hashtab.remove(&i.to_string()), Some(i));
+    }
+    for i in 4000..5000 {
+        assert_eq!(hashtab.lookup(&i.to_string()), Some(&i));
+    }
+
+    // Removals landing in a bucket that is halfway through being split;
+    // buckets only get long enough for that without a load factor
+    let mut churn = Hash {
+        incremental: true,
+        ..Hash::with_split_policy(SplitPolicy::BucketOverflow)
+    };
+    for i in 0..20_000 {
+        churn.upsert(&i.to_string(), i);
+        if i % 3 == 0 {
+            assert_eq!(churn.remove(&(i / 2).to_string()), Some(i / 2));
+        }
+    }
+    let expected: Vec<i32> = {
+        let mut model = std::collections::HashMap::new();
+        for i in 0..20_000 {
+            model.insert(i, ());
+            if i % 3 == 0 {
+                model.remove(&(i / 2));
+            }
+        }
+        let mut keys: Vec<i32> = model.keys().cloned().collect();
+        keys.sort();
+        keys
+    };
+    let mut found: Vec<i32> = churn.iter().map(|(_, &v)| v).collect();
+    found.sort();
+    assert_eq!(found, expected);
+    for &i in &expected {
+        assert_eq!(churn.lookup(&i.to_string()), Some(&i));
+    }
+
+    // Removing an entry the migration has already passed over
+    let mut partial = Hash {
+        incremental: true,
+        ..Hash::with_split_policy(SplitPolicy::BucketOverflow)
+    };
+    let mut removed = Vec::new();
+    for i in 0..5000 {
+        partial.upsert(&i.to_string(), i);
+        if let Some(m) = partial.migration {
+            if m.next > 0 && m.next < partial.table[m.from].len() {
+                removed.push(partial.remove_at(m.from, 0).value);
+            }
+        }
+    }
+    assert!(!removed.is_empty());
+    for i in (0..5000).filter(|i| !removed.contains(i)) {
+        assert_eq!(partial.lookup(&i.to_string()), Some(&i));
+    }
+}
+
+#[test]
+fn test_contraction() {
+    let mut hashtab = Hash::new();
+    for i in 0..5000 {
+        hashtab.upsert(&i.to_string(), i);
+    }
+    let grown = hashtab.bucket_count();
+
+    for i in 0..4900 {
+        assert_eq!(hashtab.remove(&i.to_string()), Some(i));
+    }
+    assert!(hashtab.bucket_count() < grown / 4);
+    for i in 4900..5000 {
+        assert_eq!(hashtab.lookup(&i.to_string()), Some(&i));
+    }
+
+    for i in 4900..5000 {
+        hashtab.remove(&i.to_string());
+    }
+    let stats = hashtab.stats();
+    assert_eq!((stats.buckets, stats.bits, stats.split_bucket), (NHASH, 5, 0));
+
+    // Growing again after shrinking all the way down
+    for i in 0..1000 {
+        hashtab.upsert(&i.to_string(), i);
+    }
+    for i in 0..1000 {
+        assert_eq!(hashtab.lookup(&i.to_string()), Some(&i));
+    }
+}
+
+#[test]
+fn test_two_choices() {
+    let mut hashtab = Hash::with_two_choices();
+    let mut plain = Hash::new();
+
+    for i in 0..1000 {
+        assert!(hashtab.upsert(&i.to_string(), i));
+        plain.upsert(&i.to_string(), i);
+    }
+    assert!(!hashtab.upsert("7", 70));
+    assert_eq!(hashtab.lookup("7"), Some(&70));
+    assert_eq!(hashtab.remove("7"), Some(70));
+    assert_eq!(hashtab.lookup("7"), None);
+    assert_eq!(hashtab.len(), 999);
+
+    assert!(hashtab.stats().longest_bucket < plain.stats().longest_bucket);
+}
+
+#[test]
+fn test_prefix_queries() {
+    let mut indexed = Hash::new();
+    indexed.enable_prefix_index();
+    let mut plain = Hash::new();
+
+    for &(k, v) in &[("10.0", 1), ("10.0.1", 2), ("10.0.1.5", 3), ("192.168", 4), ("10.1", 5)] {
+        indexed.upsert(k, v);
+        plain.upsert(k, v);
+    }
+    indexed.remove("10.1");
+    plain.remove("10.1");
+
+    for hashtab in &[&indexed, &plain] {
+        let mut found = hashtab.keys_with_prefix("10.");
+        found.sort();
+        assert_eq!(found, vec![("10.0", &1), ("10.0.1", &2), ("10.0.1.5", &3)]);
+        assert!(hashtab.keys_with_prefix("172").is_empty());
+
+        assert_eq!(hashtab.longest_prefix_match("10.0.1.9"), Some(("10.0.1", &2)));
+        assert_eq!(hashtab.longest_prefix_match("10.0.1.5"), Some(("10.0.1.5", &3)));
+        assert_eq!(hashtab.longest_prefix_match("10.1.2.3"), None);
+    }
+}
+
+#[test]
+fn test_lookup_fuzzy() {
+    let mut hashtab = Hash::new();
+    for (i, &k) in ["color", "colour", "collar", "cooler", "dollar", "über"].iter().enumerate() {
+        hashtab.upsert(k, i);
+    }
+
+    assert_eq!(hashtab.lookup_fuzzy("colr", 1), vec![("color", &0, 1)]);
+    assert_eq!(
+        hashtab.lookup_fuzzy("color", 2),
+        vec![("color", &0, 0), ("colour", &1, 1), ("collar", &2, 2), ("cooler", &3, 2)]
+    );
+    assert_eq!(hashtab.lookup_fuzzy("uber", 1), vec![("über", &5, 1)]);
+    assert!(hashtab.lookup_fuzzy("zzzzzz", 2).is_empty());
+}
+
+#[test]
+fn test_scan_glob() {
+    let mut hashtab = Hash::new();
+    hashtab.upsert("user:1:session", 1);
+    hashtab.upsert("user:2:session", 2);
+    hashtab.upsert("user:2:profile", 3);
+    hashtab.upsert("admin:1:session", 4);
+
+    let mut found: Vec<(&str, &i32)> = hashtab.scan_glob("user:*:session").collect();
+    found.sort();
+    assert_eq!(found, vec![("user:1:session", &1), ("user:2:session", &2)]);
+    assert_eq!(hashtab.scan_glob("*:1:*").count(), 2);
+    assert_eq!(hashtab.scan_glob("nobody*").next(), None);
+}
+
+#[test]
+fn test_lookup_many() {
+    let mut hashtab = Hash::new();
+    let mut two_choice = Hash::with_two_choices();
+    for i in 0..100 {
+        hashtab.upsert(&i.to_string(), i);
+        two_choice.upsert(&i.to_string(), i);
+    }
+
+    let keys = ["5", "nope", "99", "5", "0"];
+    let expected = vec![Some(&5), None, Some(&99), Some(&5), Some(&0)];
+    assert_eq!(hashtab.lookup_many(&keys), expected);
+    assert_eq!(two_choice.lookup_many(&keys), expected);
+    assert!(hashtab.lookup_many::<&str>(&[]).is_empty());
+}
+
+#[test]
+fn test_iter_and_stats() {
+    let mut hashtab = Hash::new();
+    hashtab.upsert("a", 1);
+    hashtab.upsert("b", 2);
+    hashtab.upsert("c", 3);
+    hashtab.remove("b");
+
+    let mut entries: Vec<(&str, &i32)> = hashtab.iter().collect();
+    entries.sort();
+    assert_eq!(entries, vec![("a", &1), ("c", &3)]);
+
+    let stats = hashtab.stats();
+    assert_eq!(stats.entries, 2);
+    assert_eq!(stats.buckets, NHASH);
+    assert_eq!(stats.empty_buckets, NHASH - 2);
+    assert_eq!(stats.longest_bucket, 1);
+}
+
+#[test]
+fn test_check_invariants() {
+    let mut hash = Hash::new();
+    for i in 0..500 {
+        hash.upsert(&i.to_string(), i);
+    }
+    assert_eq!(hash.check_invariants(), Ok(()));
+
+    let mut miscounted = hash.clone();
+    miscounted.count += 1;
+    assert!(miscounted.check_invariants().is_err());
+
+    // Move an entry to a bucket it doesn't hash to
+    let mut misplaced = hash.clone();
+    let b = misplaced.bucket_index_for("7");
+    let i = misplaced.table[b].iter().position(|e| e.name == "7").unwrap();
+    let entry = misplaced.table[b].swap_remove(i);
+    let other = (b + 1) % misplaced.table.len();
+    misplaced.table[other].push(entry);
+    assert!(misplaced.check_invariants().unwrap_err().contains("`7`"));
+
+    let mut indexed = hash.clone();
+    indexed.enable_prefix_index();
+    assert_eq!(indexed.check_invariants(), Ok(()));
+    indexed.prefix_index.as_mut().unwrap().remove("7");
+    assert!(indexed.check_invariants().is_err());
+}
+
+#[test]
+fn test_deferred_splits() {
+    let mut eager = Hash::new();
+    let mut lazy = Hash::with_deferred_splits();
+    for i in 0..1000 {
+        eager.upsert(&i.to_string(), i);
+        lazy.upsert(&i.to_string(), i);
+    }
+    assert_eq!(lazy.bucket_count(), NHASH);
+    assert_eq!(lazy.splits_due(), eager.bucket_count() - NHASH);
+    assert_eq!(lazy.lookup("999"), Some(&999));
+
+    assert_eq!(lazy.maintain(), eager.bucket_count() - NHASH);
+    assert_eq!(lazy.bucket_count(), eager.bucket_count());
+    assert_eq!(lazy.splits_due(), 0);
+    assert_eq!(lazy.maintain(), 0);
+    assert_eq!(lazy.check_invariants(), Ok(()));
+    for i in 0..1000 {
+        assert_eq!(lazy.lookup(&i.to_string()), Some(&i));
+    }
+
+    // Together with incremental splits, maintain() also finishes moving
+    let mut both = Hash {
+        incremental: true,
+        ..Hash::with_deferred_splits()
+    };
+    for i in 0..1000 {
+        both.upsert(&i.to_string(), i);
+    }
+    both.maintain();
+    assert!(both.migration.is_none());
+    assert_eq!(both.check_invariants(), Ok(()));
+
+    // Nothing is ever due without a load factor
+    let mut overflow = Hash {
+        deferred: true,
+        ..Hash::with_split_policy(SplitPolicy::BucketOverflow)
+    };
+    for i in 0..1000 {
+        overflow.upsert(&i.to_string(), i);
+    }
+    assert!(overflow.bucket_count() > NHASH);
+    assert_eq!(overflow.splits_due(), 0);
+}
+
+#[test]
+fn test_trim_buckets() {
+    let mut hashtab = Hash::new();
+    for i in 0..10_000 {
+        hashtab.upsert(&i.to_string(), i);
+    }
+    for i in 0..10_000 {
+        if i % 10 != 0 {
+            hashtab.remove(&i.to_string());
+        }
+    }
+
+    // Emptied buckets hold nothing, and none is left mostly unused beyond
+    // the smallest allocation a Vec makes
+    for bucket in &hashtab.table {
+        if bucket.is_empty() {
+            assert_eq!(bucket.capacity(), 0);
+        } else {
+            assert!(bucket.capacity() <= (bucket.len() * 4).max(4));
+        }
+    }
+
+    let buckets = hashtab.bucket_count();
+    let freed = hashtab.trim_buckets();
+    assert!(freed > 0);
+    assert_eq!(hashtab.bucket_count(), buckets);
+    assert!(hashtab.table.iter().all(|bucket| bucket.capacity() == bucket.len()));
+    assert_eq!(hashtab.trim_buckets(), 0);
+    for i in (0..10_000).step_by(10) {
+        assert_eq!(hashtab.lookup(&i.to_string()), Some(&i));
+    }
+}
+
+#[test]
+fn test_entry_ref() {
+    let mut hashtab = Hash::new();
+    *hashtab.entry_ref("a").or_insert(0) += 1;
+    *hashtab.entry_ref("a").or_insert(0) += 1;
+    assert_eq!(hashtab.lookup("a"), Some(&2));
+
+    // The occupied path doesn't touch the table
+    let generation = hashtab.generation;
+    hashtab.entry_ref("a").and_modify(|v| *v *= 10).or_insert(0);
+    assert_eq!(hashtab.lookup("a"), Some(&20));
+    assert_eq!(hashtab.generation, generation);
+
+    match hashtab.entry_ref("b") {
+        EntryRef::Vacant(entry) => {
+            assert_eq!(entry.key(), "b");
+            assert_eq!(*entry.insert(5), 5);
+        }
+        EntryRef::Occupied(_) => panic!("b isn't there yet"),
+    }
+    match hashtab.entry_ref("b") {
+        EntryRef::Occupied(mut entry) => {
+            assert_eq!(entry.key(), "b");
+            assert_eq!(entry.insert(6), 5);
+            assert_eq!(*entry.get(), 6);
+            assert_eq!(entry.remove(), 6);
+        }
+        EntryRef::Vacant(_) => panic!("b was inserted"),
+    }
+    assert_eq!(hashtab.lookup("b"), None);
+    assert_eq!(hashtab.len(), 1);
+
+    // Inserts that split still hand back the right value, in every mode
+    let mut modes: Vec<Hash<i32>> =
+        vec![Hash::new(), Hash::with_two_choices(), Hash::with_incremental_splits()];
+    for hashtab in &mut modes {
+        hashtab.enable_prefix_index();
+        for i in 0..2000 {
+            let value = hashtab.entry_ref(&i.to_string()).or_default();
+            assert_eq!(*value, 0);
+            *value = i;
+        }
+        for i in 0..2000 {
+            assert_eq!(hashtab.lookup(&i.to_string()), Some(&i));
+        }
+        assert_eq!(hashtab.keys_with_prefix("199").len(), 11);
+        assert_eq!(hashtab.check_invariants(), Ok(()));
+    }
+}
+
+#[test]
+fn test_or_insert_with_key() {
+    let mut hashtab = Hash::new();
+    let mut calls = 0;
+    for name in &["12", "7", "12"] {
+        hashtab.entry_ref(name).or_insert_with_key(|key| {
+            calls += 1;
+            key.parse::<i32>().unwrap()
+        });
+    }
+    assert_eq!(calls, 2);
+    assert_eq!(hashtab.lookup("12"), Some(&12));
+    assert_eq!(hashtab.lookup("7"), Some(&7));
+}
+
+#[test]
+fn test_into_iter_sorted() {
+    let mut hashtab = Hash::new();
+    for i in (0..1000).rev() {
+        hashtab.upsert(&format!("{:04}", i), i);
+    }
+    let entries: Vec<(String, i32)> = hashtab.into_iter_sorted().collect();
+    assert_eq!(entries.len(), 1000);
+    for (i, (name, value)) in entries.into_iter().enumerate() {
+        assert_eq!(name, format!("{:04}", i));
+        assert_eq!(value, i as i32);
+    }
+    assert_eq!(Hash::<i32>::new().into_iter_sorted().count(), 0);
+}
+
+#[test]
+fn test_extend_borrowed() {
+    let input = String::from("a=1 b=2 a=3 c=4");
+    let pairs = input.split(' ').map(|pair| {
+        let (name, value) = pair.split_at(1);
+        (name, value[1..].parse::<i32>().unwrap())
+    });
+
+    let mut hashtab = Hash::new();
+    hashtab.extend(pairs);
+    assert_eq!(hashtab.len(), 3);
+    assert_eq!(hashtab.lookup("a"), Some(&3));
+    assert_eq!(hashtab.lookup("c"), Some(&4));
+}
+
+#[test]
+fn test_from_array() {
+    let hashtab = Hash::from([("a", 1), ("b", 2), ("a", 3)]);
+    assert_eq!(hashtab.len(), 2);
+    assert_eq!(hashtab.lookup("a"), Some(&3));
+    assert_eq!(hashtab.lookup("b"), Some(&2));
+
+    let owned = Hash::from([(String::from("x"), ())]);
+    assert!(owned.lookup("x").is_some());
+
+    let empty: Hash<i32> = Hash::from([] as [(&str, i32); 0]);
+    assert!(empty.is_empty());
+
+    // Sized for exactly N entries at the default load factor
+    let names: Vec<String> = (0..1000).map(|i| i.to_string()).collect();
+    let mut entries = [("", 0); 1000];
+    for (i, name) in names.iter().enumerate() {
+        entries[i] = (name, i);
+    }
+    let big = Hash::from(entries);
+    assert_eq!(big.len(), 1000);
+    assert_eq!(big.bucket_count(), 500);
+}
+
+#[test]
+fn test_literal_macro() {
+    let hashtab = stupid_hash! { "a" => 1, "b" => 2, };
+    assert_eq!(hashtab.len(), 2);
+    assert_eq!(hashtab.lookup("b"), Some(&2));
+
+    let name = String::from("key");
+    let single = stupid_hash! { &name[..] => vec![1, 2] };
+    assert_eq!(single.lookup("key"), Some(&vec![1, 2]));
+
+    let empty: Hash<i32> = stupid_hash! {};
+    assert!(empty.is_empty());
+}
+
+#[test]
+fn test_debug() {
+    let mut hashtab = Hash::new();
+    hashtab.upsert("a", 1);
+    assert_eq!(format!("{:?}", hashtab), "{\"a\": 1}");
+
+    let layout = format!("{:#?}", hashtab);
+    assert!(layout.starts_with("Hash {\n    bits: 5,\n    split_bucket: 0,\n    count: 1,"));
+    let b = hashtab.bucket_index_for("a");
+    assert!(layout.contains(&format!("{}: [\n            \"a\",\n        ],", b)));
+    assert!(layout.contains("31: [],"));
+    assert!(!layout.contains("migration"));
+}
+
+#[test]
+fn test_values_cloned() {
+    let hashtab = stupid_hash! { "a" => vec![1], "b" => vec![2, 3] };
+    let mut values: Vec<Vec<i32>> = hashtab.values_cloned().collect();
+    values.sort();
+    assert_eq!(values, vec![vec![1], vec![2, 3]]);
+    assert_eq!(hashtab.len(), 2);
+}
+
+#[test]
+fn test_snapshot_stream() {
+    let mut hashtab = Hash::new();
+    for i in 0..1000 {
+        hashtab.upsert(&format!("key{}", i), format!("value {}", i));
+    }
+    hashtab.upsert("", String::new());
+
+    let mut bytes = Vec::new();
+    hashtab.write_snapshot(&mut bytes).unwrap();
+    let loaded: Hash<String> = Hash::read_snapshot(&bytes[..]).unwrap();
+    assert_eq!(loaded.len(), 1001);
+    let expected: Vec<_> = hashtab.iter_stable().collect();
+    assert_eq!(loaded.iter_stable().collect::<Vec<_>>(), expected);
+
+    // Truncated, or not a snapshot at all
+    let truncated = Hash::<String>::read_snapshot(&bytes[..bytes.len() - 1]);
+    assert_eq!(truncated.err().map(|e| e.kind()), Some(io::ErrorKind::UnexpectedEof));
+    assert!(Hash::<String>::read_snapshot(&b"key\tvalue\n"[..]).is_err());
+    let empty = Hash::<Vec<u8>>::new();
+    bytes.clear();
+    empty.write_snapshot(&mut bytes).unwrap();
+    assert!(Hash::<Vec<u8>>::read_snapshot(&bytes[..]).unwrap().is_empty());
+}
+
+#[test]
+fn test_namespace() {
+    let mut hashtab = Hash::new();
+    hashtab.upsert("global", 0);
+    {
+        let mut users = hashtab.namespace("user:");
+        assert!(users.upsert("alice", 1));
+        assert!(users.upsert("bob", 2));
+        *users.lookup_mut("bob").unwrap() += 10;
+        assert_eq!(users.lookup("bob"), Some(&12));
+        assert_eq!(users.lookup("global"), None);
+        let mut sessions = users.namespace("session:");
+        sessions.upsert("alice", 3);
+        assert_eq!(sessions.prefix(), "user:session:");
+    }
+    hashtab.namespace("group:").upsert("alice", 4);
+    assert_eq!(hashtab.lookup("user:alice"), Some(&1));
+    assert_eq!(hashtab.lookup("user:session:alice"), Some(&3));
+
+    {
+        let users = hashtab.namespace("user:");
+        let mut names: Vec<&str> = users.entries().into_iter().map(|(name, _)| name).collect();
+        names.sort();
+        assert_eq!(names, vec!["alice", "bob", "session:alice"]);
+    }
+
+    hashtab.enable_prefix_index();
+    let mut users = hashtab.namespace("user:");
+    assert_eq!(users.remove("bob"), Some(12));
+    assert_eq!(users.clear_namespace(), 2);
+    assert!(users.is_empty());
+    assert_eq!(hashtab.len(), 2);
+    assert_eq!(hashtab.namespace("group:").lookup("alice"), Some(&4));
+}
+
+#[test]
+fn test_iter_mut() {
+    let mut hashtab = Hash::new();
+    for i in 0..100 {
+        hashtab.upsert(&i.to_string(), i);
+    }
+    for (name, value) in hashtab.iter_mut() {
+        *value += name.len();
     }
+    assert_eq!(hashtab.lookup("5"), Some(&6));
+    assert_eq!(hashtab.lookup("50"), Some(&52));
+    assert_eq!(hashtab.iter_mut().count(), 100);
 }
@@ -0,0 +1,125 @@
+// A HyperLogLog estimator for the number of distinct keys in a stream,
+// using 2^precision one-byte registers. The standard error is about
+// 1.04 / sqrt(2^precision), so the default precision of 14 (16 KiB) gives
+// roughly 0.8%.
+
+use hash::{hash_str, mix64};
+
+pub const DEFAULT_PRECISION: u8 = 14;
+
+#[derive(Clone, Debug)]
+pub struct HyperLogLog {
+    precision: u8,
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    pub fn new() -> Self {
+        HyperLogLog::with_precision(DEFAULT_PRECISION)
+    }
+
+    // Precision must be between 4 and 16
+    pub fn with_precision(precision: u8) -> Self {
+        assert!((4..=16).contains(&precision), "precision must be between 4 and 16");
+
+        HyperLogLog {
+            precision,
+            registers: vec![0; 1 << precision],
+        }
+    }
+
+    pub fn precision(&self) -> u8 {
+        self.precision
+    }
+
+    pub fn add(&mut self, name: &str) {
+        let p = self.precision as u32;
+        let x = mix64(hash_str(name));
+        let index = (x >> (64 - p)) as usize;
+        // Position of the first set bit in what's left, capped so that an
+        // all-zero remainder still counts as a run of 64 - p zeros
+        let rank = ((x << p).leading_zeros()).min(64 - p) as u8 + 1;
+
+        if rank > self.registers[index] {
+            self.registers[index] = rank;
+        }
+    }
+
+    pub fn estimate(&self) -> u64 {
+        let m = self.registers.len() as f64;
+        let alpha = match self.registers.len() {
+            16 => 0.673,
+            32 => 0.697,
+            64 => 0.709,
+            _ => 0.7213 / (1.0 + 1.079 / m),
+        };
+
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|&r| 1.0 / (1u64 << r) as f64)
+            .sum();
+        let raw = alpha * m * m / sum;
+
+        // Small-range correction: count empty registers instead
+        let zeros = self.registers.iter().filter(|&&r| r == 0).count();
+        if raw <= 2.5 * m && zeros > 0 {
+            (m * (m / zeros as f64).ln()).round() as u64
+        } else {
+            raw.round() as u64
+        }
+    }
+
+    // Folds another estimator of the same precision into this one; the result
+    // estimates the size of the union of both streams.
+    pub fn merge(&mut self, other: &HyperLogLog) {
+        assert_eq!(self.precision, other.precision, "cannot merge different precisions");
+
+        for (r, &o) in self.registers.iter_mut().zip(other.registers.iter()) {
+            if o > *r {
+                *r = o;
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        for r in self.registers.iter_mut() {
+            *r = 0;
+        }
+    }
+}
+
+impl Default for HyperLogLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[test]
+fn test_hll_estimate() {
+    let mut hll = HyperLogLog::new();
+    assert_eq!(hll.estimate(), 0);
+
+    for _ in 0..3 {
+        for i in 0..100_000 {
+            hll.add(&format!("user:{}", i));
+        }
+    }
+
+    let estimate = hll.estimate() as f64;
+    assert!((estimate - 100_000.0).abs() < 3_000.0, "estimate {}", estimate);
+}
+
+#[test]
+fn test_hll_merge() {
+    let mut a = HyperLogLog::with_precision(12);
+    let mut b = HyperLogLog::with_precision(12);
+    for i in 0..1000 {
+        a.add(&i.to_string());
+        b.add(&(i + 500).to_string());
+    }
+
+    a.merge(&b);
+    let estimate = a.estimate() as f64;
+    assert!((estimate - 1500.0).abs() < 100.0, "estimate {}", estimate);
+}
@@ -0,0 +1,319 @@
+// A hopscotch-hashed alternative to `Hash` with the same lookup/upsert/remove
+// interface, for tables that need to run nearly full. Entries live in one
+// flat array of slots, each within NEIGHBORHOOD slots of the slot its key
+// hashes to (its home), and every home keeps a bitmap of which of those
+// slots hold its keys. A lookup reads one bitmap and compares only the keys
+// it points at. An insert takes the nearest free slot and, while that is too
+// far from home, hops it closer by moving an entry that may live there.
+// Only when no such move exists does the table double. Keys whose full
+// hashes collide can't be spread by doubling; once more than NEIGHBORHOOD of
+// them share a home the rest go in a small overflow list.
+
+use std::mem;
+
+use hash::{hash_str, mix64};
+
+const INITIAL_SLOTS: usize = 32;
+// Bits in a hop bitmap
+const NEIGHBORHOOD: usize = 32;
+// Neighborhoods keep working far past where chaining or cuckoo tables
+// would have grown
+const MAX_LOAD: f64 = 0.9;
+
+struct Slot<T> {
+    name: String,
+    value: T,
+}
+
+pub struct HopscotchHash<T> {
+    slots: Vec<Option<Slot<T>>>,
+    // Bit j of hops[i] is set when slots[i + j] holds a key whose home is i
+    hops: Vec<u32>,
+    // Entries whose home's neighborhood is all taken by keys of that home
+    overflow: Vec<Slot<T>>,
+    count: usize,
+}
+
+fn empty_slots<T>(slots: usize) -> Vec<Option<Slot<T>>> {
+    (0..slots).map(|_| None).collect()
+}
+
+impl<T> HopscotchHash<T> {
+    pub fn new() -> Self {
+        HopscotchHash::with_slots(INITIAL_SLOTS)
+    }
+
+    fn with_slots(slots: usize) -> Self {
+        HopscotchHash {
+            slots: empty_slots(slots),
+            hops: vec![0; slots],
+            overflow: Vec::new(),
+            count: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    fn mask(&self) -> usize {
+        self.slots.len() - 1
+    }
+
+    fn home(&self, name: &str) -> usize {
+        mix64(hash_str(name)) as usize & self.mask()
+    }
+
+    // How far `to` is past `from`, wrapping around the end
+    fn distance(&self, from: usize, to: usize) -> usize {
+        to.wrapping_sub(from) & self.mask()
+    }
+
+    fn find(&self, name: &str) -> Option<usize> {
+        let home = self.home(name);
+        let mut hops = self.hops[home];
+        while hops != 0 {
+            let i = (home + hops.trailing_zeros() as usize) & self.mask();
+            match self.slots[i] {
+                Some(ref slot) if slot.name == name => return Some(i),
+                _ => hops &= hops - 1,
+            }
+        }
+        None
+    }
+
+    fn find_overflow(&self, name: &str) -> Option<usize> {
+        self.overflow.iter().position(|slot| slot.name == name)
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<&T> {
+        match self.find(name) {
+            Some(i) => self.slots[i].as_ref().map(|slot| &slot.value),
+            None => self.find_overflow(name).map(|i| &self.overflow[i].value),
+        }
+    }
+
+    pub fn lookup_mut(&mut self, name: &str) -> Option<&mut T> {
+        match self.find(name) {
+            Some(i) => self.slots[i].as_mut().map(|slot| &mut slot.value),
+            None => self.find_overflow(name).map(move |i| &mut self.overflow[i].value),
+        }
+    }
+
+    // Returns true if the insert was a new key,
+    // False if we overwrote a key
+    pub fn upsert(&mut self, name: &str, value: T) -> bool {
+        if let Some(value_ref) = self.lookup_mut(name) {
+            *value_ref = value;
+            return false;
+        }
+
+        if (self.count + 1) as f64 > self.capacity() as f64 * MAX_LOAD {
+            self.grow();
+        }
+
+        let mut slot = Slot {
+            name: name.to_string(),
+            value,
+        };
+        loop {
+            match self.place(slot) {
+                Ok(()) => break,
+                Err(unplaced) => {
+                    slot = unplaced;
+                    self.grow();
+                }
+            }
+        }
+        self.count += 1;
+
+        true
+    }
+
+    // Puts a key that isn't in the table into its neighborhood, handing it
+    // back if the neighborhood can't be made to have room
+    fn place(&mut self, slot: Slot<T>) -> Result<(), Slot<T>> {
+        let home = self.home(&slot.name);
+        let mask = self.mask();
+        if self.hops[home] == !0 {
+            self.overflow.push(slot);
+            return Ok(());
+        }
+        let mut free = match (0..self.slots.len())
+            .map(|d| (home + d) & mask)
+            .find(|&i| self.slots[i].is_none())
+        {
+            Some(free) => free,
+            None => return Err(slot),
+        };
+
+        while self.distance(home, free) >= NEIGHBORHOOD {
+            free = match self.hop_closer(free) {
+                Some(free) => free,
+                None => return Err(slot),
+            };
+        }
+
+        self.slots[free] = Some(slot);
+        self.hops[home] |= 1 << self.distance(home, free);
+        Ok(())
+    }
+
+    // Moves an entry from the NEIGHBORHOOD - 1 slots before `free` into it,
+    // choosing the one furthest back that stays in its own neighborhood.
+    // Returns the slot that was vacated.
+    fn hop_closer(&mut self, free: usize) -> Option<usize> {
+        let mask = self.mask();
+        for back in (1..NEIGHBORHOOD).rev() {
+            let home = free.wrapping_sub(back) & mask;
+            // Only entries at or after `home` and before `free` can move
+            let movable = self.hops[home] & ((1 << back) - 1);
+            if movable != 0 {
+                let offset = movable.trailing_zeros() as usize;
+                let from = (home + offset) & mask;
+                self.slots[free] = self.slots[from].take();
+                self.hops[home] &= !(1 << offset);
+                self.hops[home] |= 1 << back;
+                return Some(from);
+            }
+        }
+        None
+    }
+
+    // Doubles the table, and keeps doubling until every entry fits
+    fn grow(&mut self) {
+        let mut entries: Vec<Slot<T>> = mem::take(&mut self.slots)
+            .into_iter()
+            .flatten()
+            .chain(self.overflow.drain(..))
+            .collect();
+        let mut slots = self.hops.len() * 2;
+        loop {
+            self.slots = empty_slots(slots);
+            self.hops = vec![0; slots];
+
+            let mut rest = entries.into_iter();
+            match rest.by_ref().find_map(|slot| self.place(slot).err()) {
+                None => return,
+                Some(unplaced) => {
+                    entries = mem::take(&mut self.slots)
+                        .into_iter()
+                        .flatten()
+                        .chain(self.overflow.drain(..))
+                        .chain(Some(unplaced))
+                        .chain(rest)
+                        .collect();
+                    slots *= 2;
+                }
+            }
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<T> {
+        let value = match self.find(name) {
+            Some(i) => {
+                let home = self.home(name);
+                self.hops[home] &= !(1 << self.distance(home, i));
+                self.slots[i].take().map(|slot| slot.value)
+            }
+            None => {
+                let i = self.find_overflow(name)?;
+                Some(self.overflow.swap_remove(i).value)
+            }
+        };
+
+        self.count -= 1;
+        value
+    }
+}
+
+impl<T> Default for HopscotchHash<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[test]
+fn test_hopscotch_basics() {
+    let mut hashtab = HopscotchHash::new();
+    assert_eq!(hashtab.lookup("abc"), None);
+    assert!(hashtab.upsert("abc", 64));
+    assert!(!hashtab.upsert("abc", 256));
+    assert_eq!(hashtab.lookup("abc"), Some(&256));
+    *hashtab.lookup_mut("abc").unwrap() += 1;
+    assert_eq!(hashtab.remove("abc"), Some(257));
+    assert_eq!(hashtab.remove("abc"), None);
+    assert!(hashtab.is_empty());
+}
+
+#[test]
+fn test_hopscotch_growth() {
+    let mut hashtab = HopscotchHash::new();
+    let n_entries = 20000;
+
+    for i in 0..n_entries {
+        assert!(hashtab.upsert(&i.to_string(), i));
+    }
+    assert_eq!(hashtab.len(), n_entries);
+    // Stays dense: well over half the slots are in use
+    assert!(hashtab.capacity() < 2 * n_entries);
+
+    for i in 0..n_entries {
+        assert_eq!(hashtab.lookup(&i.to_string()), Some(&i));
+    }
+    for i in (0..n_entries).filter(|i| i % 2 == 0) {
+        assert_eq!(hashtab.remove(&i.to_string()), Some(i));
+    }
+    assert_eq!(hashtab.lookup("2"), None);
+    assert_eq!(hashtab.lookup("3"), Some(&3));
+
+    // Every entry is in its home's neighborhood and marked there
+    let mut marked = 0;
+    for (i, slot) in hashtab.slots.iter().enumerate() {
+        if let Some(ref slot) = *slot {
+            let home = hashtab.home(&slot.name);
+            let d = hashtab.distance(home, i);
+            assert!(d < NEIGHBORHOOD);
+            assert!(hashtab.hops[home] & (1 << d) != 0);
+            marked += 1;
+        }
+    }
+    assert_eq!(marked, hashtab.len());
+    let bits: u32 = hashtab.hops.iter().map(|h| h.count_ones()).sum();
+    assert_eq!(bits as usize, hashtab.len());
+}
+
+#[test]
+fn test_hopscotch_collisions() {
+    // "Aa" and "BB" hash the same, so all of these share one home
+    let keys: Vec<String> = (0..100)
+        .map(|i: usize| (0..7).map(|b| if i >> b & 1 == 0 { "Aa" } else { "BB" }).collect())
+        .collect();
+
+    let mut hashtab = HopscotchHash::new();
+    for (i, key) in keys.iter().enumerate() {
+        assert!(hashtab.upsert(key, i));
+    }
+    assert_eq!(hashtab.len(), 100);
+    assert_eq!(hashtab.overflow.len(), 100 - NEIGHBORHOOD);
+    assert!(hashtab.capacity() <= 256);
+
+    for (i, key) in keys.iter().enumerate() {
+        assert!(!hashtab.upsert(key, i + 1));
+    }
+    for (i, key) in keys.iter().enumerate().step_by(3) {
+        assert_eq!(hashtab.remove(key), Some(i + 1));
+        assert_eq!(hashtab.lookup(key), None);
+    }
+    for (i, key) in keys.iter().enumerate().filter(|&(i, _)| i % 3 != 0) {
+        assert_eq!(hashtab.lookup(key), Some(&(i + 1)));
+    }
+}
@@ -0,0 +1,213 @@
+// Linear hashing with the first entry of every bucket stored in the bucket
+// itself and only the rest spilled to a heap vector. The table splits at a
+// load factor of one, so most buckets hold at most one entry and a lookup
+// usually finds (or rules out) its key in the bucket array alone. Each
+// entry keeps its full hash, so a miss is decided without reading the
+// stored key.
+
+use std::mem;
+
+use hash::{bucket_index, hash_str, next_split};
+
+const INITIAL_BUCKETS: usize = 32;
+const MAX_LOAD: usize = 1;
+
+struct Entry<T> {
+    hash: usize,
+    name: String,
+    value: T,
+}
+
+struct Bucket<T> {
+    // Only None when `rest` is empty too
+    first: Option<Entry<T>>,
+    rest: Vec<Entry<T>>,
+}
+
+impl<T> Bucket<T> {
+    fn new() -> Self {
+        Bucket {
+            first: None,
+            rest: Vec::new(),
+        }
+    }
+
+    fn entries(&self) -> impl Iterator<Item = &Entry<T>> {
+        self.first.iter().chain(self.rest.iter())
+    }
+
+    fn entries_mut(&mut self) -> impl Iterator<Item = &mut Entry<T>> {
+        self.first.iter_mut().chain(self.rest.iter_mut())
+    }
+
+    fn push(&mut self, entry: Entry<T>) {
+        if self.first.is_none() {
+            self.first = Some(entry);
+        } else {
+            self.rest.push(entry);
+        }
+    }
+}
+
+pub struct InlineHash<T> {
+    buckets: Vec<Bucket<T>>,
+    bits: usize,
+    split_bucket: usize,
+    count: usize,
+}
+
+impl<T> InlineHash<T> {
+    pub fn new() -> Self {
+        InlineHash {
+            buckets: (0..INITIAL_BUCKETS).map(|_| Bucket::new()).collect(),
+            bits: 5, // log_2(INITIAL_BUCKETS)
+            split_bucket: 0,
+            count: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn bucket_count(&self) -> usize {
+        self.buckets.len()
+    }
+
+    // Number of buckets holding more than their inline entry
+    pub fn spilled_buckets(&self) -> usize {
+        self.buckets.iter().filter(|b| !b.rest.is_empty()).count()
+    }
+
+    fn index(&self, h: usize) -> usize {
+        bucket_index(h, self.bits, self.buckets.len())
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<&T> {
+        let h = hash_str(name);
+        self.buckets[self.index(h)]
+            .entries()
+            .find(|e| e.hash == h && e.name == name)
+            .map(|e| &e.value)
+    }
+
+    pub fn lookup_mut(&mut self, name: &str) -> Option<&mut T> {
+        let h = hash_str(name);
+        let i = self.index(h);
+        self.buckets[i]
+            .entries_mut()
+            .find(|e| e.hash == h && e.name == name)
+            .map(|e| &mut e.value)
+    }
+
+    // Returns true if the insert was a new key,
+    // False if we overwrote a key
+    pub fn upsert(&mut self, name: &str, value: T) -> bool {
+        if let Some(old) = self.lookup_mut(name) {
+            *old = value;
+            return false;
+        }
+
+        let hash = hash_str(name);
+        let i = self.index(hash);
+        self.buckets[i].push(Entry {
+            hash,
+            name: name.to_string(),
+            value,
+        });
+        self.count += 1;
+
+        if self.count > self.buckets.len() * MAX_LOAD {
+            self.split();
+        }
+        true
+    }
+
+    fn split(&mut self) {
+        let orig = mem::replace(&mut self.buckets[self.split_bucket], Bucket::new());
+        self.buckets.push(Bucket::new());
+
+        let (bits, split_bucket) = next_split(self.bits, self.split_bucket, self.buckets.len());
+        self.bits = bits;
+        self.split_bucket = split_bucket;
+
+        // The stored hashes make this a move, not a rehash
+        for entry in orig.first.into_iter().chain(orig.rest) {
+            let i = self.index(entry.hash);
+            self.buckets[i].push(entry);
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<T> {
+        let h = hash_str(name);
+        let i = self.index(h);
+        let bucket = &mut self.buckets[i];
+        let matches = |e: &Entry<T>| e.hash == h && e.name == name;
+
+        let entry = if bucket.first.as_ref().is_some_and(matches) {
+            // Another entry moves inline, if there is one
+            let replacement = bucket.rest.pop();
+            mem::replace(&mut bucket.first, replacement)
+        } else {
+            let j = bucket.rest.iter().position(matches)?;
+            Some(bucket.rest.swap_remove(j))
+        };
+        if bucket.rest.is_empty() {
+            bucket.rest = Vec::new();
+        }
+
+        self.count -= 1;
+        entry.map(|e| e.value)
+    }
+}
+
+impl<T> Default for InlineHash<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[test]
+fn test_inline_basics() {
+    let mut table = InlineHash::new();
+    assert_eq!(table.lookup("a"), None);
+    assert!(table.upsert("a", 1));
+    assert!(table.upsert("b", 2));
+    assert!(!table.upsert("a", 10));
+    assert_eq!(table.lookup("a"), Some(&10));
+    *table.lookup_mut("b").unwrap() += 1;
+    assert_eq!(table.remove("b"), Some(3));
+    assert_eq!(table.remove("b"), None);
+    assert_eq!(table.len(), 1);
+}
+
+#[test]
+fn test_inline_many() {
+    let mut table = InlineHash::new();
+    for i in 0..5000 {
+        assert!(table.upsert(&i.to_string(), i));
+    }
+
+    assert_eq!(table.len(), 5000);
+    assert!(table.bucket_count() >= 5000);
+    // Most keys are found without leaving the bucket array
+    assert!(table.spilled_buckets() < table.bucket_count() / 2);
+    for i in 0..5000 {
+        assert_eq!(table.lookup(&i.to_string()), Some(&i));
+    }
+
+    for i in (0..5000).filter(|i| i % 2 == 0) {
+        assert_eq!(table.remove(&i.to_string()), Some(i));
+    }
+    assert_eq!(table.len(), 2500);
+    for i in 0..5000 {
+        let expected = if i % 2 == 0 { None } else { Some(&i) };
+        assert_eq!(table.lookup(&i.to_string()), expected);
+    }
+    // Removing an inline entry promotes a spilled one
+    assert!(table.buckets.iter().all(|b| b.first.is_some() || b.rest.is_empty()));
+}
@@ -0,0 +1,181 @@
+// A table that remembers every change made through it, so changes can be
+// undone and redone like edits to a document. Writes go through the wrapper;
+// reads can use the inner table directly.
+
+use hash::Hash;
+
+struct Change<T> {
+    name: String,
+    // None for a key that didn't exist before, or doesn't after
+    old: Option<T>,
+    new: Option<T>,
+}
+
+pub struct JournaledHash<T: Clone> {
+    hash: Hash<T>,
+    done: Vec<Change<T>>,
+    undone: Vec<Change<T>>,
+}
+
+impl<T> JournaledHash<T>
+where
+    T: Clone,
+{
+    pub fn new() -> Self {
+        JournaledHash::from_hash(Hash::new())
+    }
+
+    // Starts a journal on an existing table; its current contents are where
+    // undo() stops
+    pub fn from_hash(hash: Hash<T>) -> Self {
+        JournaledHash {
+            hash,
+            done: Vec::new(),
+            undone: Vec::new(),
+        }
+    }
+
+    pub fn inner(&self) -> &Hash<T> {
+        &self.hash
+    }
+
+    pub fn into_inner(self) -> Hash<T> {
+        self.hash
+    }
+
+    pub fn len(&self) -> usize {
+        self.hash.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hash.is_empty()
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<&T> {
+        self.hash.lookup(name)
+    }
+
+    // Number of changes undo() and redo() can step through
+    pub fn undo_depth(&self) -> usize {
+        self.done.len()
+    }
+
+    pub fn redo_depth(&self) -> usize {
+        self.undone.len()
+    }
+
+    fn set(&mut self, name: &str, value: Option<T>) {
+        match value {
+            Some(value) => {
+                self.hash.upsert(name, value);
+            }
+            None => {
+                self.hash.remove(name);
+            }
+        }
+    }
+
+    fn record(&mut self, change: Change<T>) {
+        self.done.push(change);
+        // A new change starts a new branch of history
+        self.undone.clear();
+    }
+
+    pub fn upsert(&mut self, name: &str, value: T) -> bool {
+        let old = self.hash.lookup(name).cloned();
+        let new = old.is_none();
+
+        self.hash.upsert(name, value.clone());
+        self.record(Change {
+            name: name.to_string(),
+            old,
+            new: Some(value),
+        });
+        new
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<T> {
+        let old = self.hash.remove(name)?;
+
+        self.record(Change {
+            name: name.to_string(),
+            old: Some(old.clone()),
+            new: None,
+        });
+        Some(old)
+    }
+
+    // Reverts the last `n` changes, or as many as there are; returns how
+    // many were reverted
+    pub fn undo(&mut self, n: usize) -> usize {
+        let mut steps = 0;
+        while steps < n {
+            let change = match self.done.pop() {
+                Some(change) => change,
+                None => break,
+            };
+            self.set(&change.name, change.old.clone());
+            self.undone.push(change);
+            steps += 1;
+        }
+        steps
+    }
+
+    // Reapplies the last `n` undone changes; returns how many were
+    pub fn redo(&mut self, n: usize) -> usize {
+        let mut steps = 0;
+        while steps < n {
+            let change = match self.undone.pop() {
+                Some(change) => change,
+                None => break,
+            };
+            self.set(&change.name, change.new.clone());
+            self.done.push(change);
+            steps += 1;
+        }
+        steps
+    }
+
+    // Forgets all history, keeping the current contents
+    pub fn clear_history(&mut self) {
+        self.done.clear();
+        self.undone.clear();
+    }
+}
+
+impl<T> Default for JournaledHash<T>
+where
+    T: Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[test]
+fn test_undo_redo() {
+    let mut doc = JournaledHash::new();
+    doc.upsert("title", "draft");
+    doc.upsert("body", "hello");
+    doc.upsert("title", "final");
+    doc.remove("body");
+
+    assert_eq!(doc.undo(2), 2);
+    assert_eq!(doc.lookup("title"), Some(&"draft"));
+    assert_eq!(doc.lookup("body"), Some(&"hello"));
+
+    assert_eq!(doc.redo(1), 1);
+    assert_eq!(doc.lookup("title"), Some(&"final"));
+    assert_eq!(doc.redo_depth(), 1);
+
+    assert_eq!(doc.undo(10), 3);
+    assert!(doc.is_empty());
+    assert_eq!(doc.redo(10), 4);
+    assert_eq!(doc.lookup("body"), None);
+
+    doc.undo(1);
+    doc.upsert("author", "me");
+    assert_eq!(doc.redo(1), 0);
+    assert_eq!(doc.lookup("body"), Some(&"hello"));
+    assert_eq!(doc.undo_depth(), 4);
+}
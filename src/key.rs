@@ -0,0 +1,189 @@
+// How the table stores and compares its keys. Normally a key is a String.
+// With the `small-keys` feature, keys of up to INLINE bytes are stored
+// inside the entry itself, so comparing against them doesn't have to chase
+// a pointer, and longer ones in a Box<str>. Either way a key takes 24
+// bytes. The conversions below keep the rest of the table agnostic of
+// which is in use.
+
+use std::collections::TryReserveError;
+
+#[cfg(not(feature = "small-keys"))]
+pub(crate) type Key = String;
+
+// Whether a stored key is `name`. Keys sharing a bucket mostly differ in
+// length or at one of their ends (think "user:1041" and "user:977"), so
+// those are checked before comparing everything. Misses rarely get as far
+// as the full comparison.
+#[inline]
+pub(crate) fn matches(key: &Key, name: &str) -> bool {
+    let (a, b) = (key.as_bytes(), name.as_bytes());
+    a.len() == b.len() && a.first() == b.first() && a.last() == b.last() && a == b
+}
+
+// from_str() that reports a failure to allocate instead of aborting
+pub(crate) fn try_from_str(name: &str) -> Result<Key, TryReserveError> {
+    let mut key = String::new();
+    key.try_reserve_exact(name.len())?;
+    key.push_str(name);
+    Ok(from_string(key))
+}
+
+#[cfg(not(feature = "small-keys"))]
+pub(crate) fn from_str(name: &str) -> Key {
+    name.to_string()
+}
+
+#[cfg(not(feature = "small-keys"))]
+pub(crate) fn from_string(name: String) -> Key {
+    name
+}
+
+#[cfg(not(feature = "small-keys"))]
+pub(crate) fn into_string(key: Key) -> String {
+    key
+}
+
+#[cfg(feature = "small-keys")]
+pub(crate) use self::small::SmallKey as Key;
+
+#[cfg(feature = "small-keys")]
+pub(crate) fn from_str(name: &str) -> Key {
+    Key::new(name)
+}
+
+#[cfg(feature = "small-keys")]
+pub(crate) fn from_string(name: String) -> Key {
+    Key::from_string(name)
+}
+
+#[cfg(feature = "small-keys")]
+pub(crate) fn into_string(key: Key) -> String {
+    key.into_string()
+}
+
+#[cfg(feature = "small-keys")]
+mod small {
+    use std::fmt;
+    use std::ops::Deref;
+    use std::str;
+
+    // The most a key can have and still fit next to its length and the
+    // enum tag in 24 bytes
+    pub(crate) const INLINE: usize = 22;
+
+    #[derive(Clone)]
+    pub(crate) enum SmallKey {
+        Inline(u8, [u8; INLINE]),
+        Heap(Box<str>),
+    }
+
+    impl SmallKey {
+        pub(crate) fn new(name: &str) -> Self {
+            if name.len() <= INLINE {
+                let mut bytes = [0; INLINE];
+                bytes[..name.len()].copy_from_slice(name.as_bytes());
+                SmallKey::Inline(name.len() as u8, bytes)
+            } else {
+                SmallKey::Heap(name.into())
+            }
+        }
+
+        pub(crate) fn from_string(name: String) -> Self {
+            if name.len() <= INLINE {
+                SmallKey::new(&name)
+            } else {
+                SmallKey::Heap(name.into_boxed_str())
+            }
+        }
+
+        pub(crate) fn into_string(self) -> String {
+            match self {
+                SmallKey::Inline(..) => self.as_str().to_string(),
+                SmallKey::Heap(name) => name.into_string(),
+            }
+        }
+
+        pub(crate) fn as_bytes(&self) -> &[u8] {
+            match *self {
+                SmallKey::Inline(len, ref bytes) => &bytes[..len as usize],
+                SmallKey::Heap(ref name) => name.as_bytes(),
+            }
+        }
+
+        pub(crate) fn as_str(&self) -> &str {
+            match *self {
+                // Safety: the bytes were copied from a &str in new()
+                SmallKey::Inline(..) => unsafe { str::from_utf8_unchecked(self.as_bytes()) },
+                SmallKey::Heap(ref name) => name,
+            }
+        }
+    }
+
+    impl Deref for SmallKey {
+        type Target = str;
+
+        fn deref(&self) -> &str {
+            self.as_str()
+        }
+    }
+
+    // Compares bytes directly, without going through &str
+    impl PartialEq<str> for SmallKey {
+        fn eq(&self, other: &str) -> bool {
+            self.as_bytes() == other.as_bytes()
+        }
+    }
+
+    impl<'a> PartialEq<&'a str> for SmallKey {
+        fn eq(&self, other: &&'a str) -> bool {
+            self.as_bytes() == other.as_bytes()
+        }
+    }
+
+    impl fmt::Debug for SmallKey {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            fmt::Debug::fmt(self.as_str(), f)
+        }
+    }
+
+    impl fmt::Display for SmallKey {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str(self.as_str())
+        }
+    }
+
+    #[test]
+    fn test_small_key() {
+        use std::mem;
+
+        assert_eq!(mem::size_of::<SmallKey>(), mem::size_of::<String>());
+
+        let short = SmallKey::new("héllo");
+        assert!(matches!(short, SmallKey::Inline(..)));
+        assert_eq!(short.as_str(), "héllo");
+        assert!(short == "héllo");
+        assert!(short != "hello");
+
+        let edge = "x".repeat(INLINE);
+        assert!(matches!(SmallKey::new(&edge), SmallKey::Inline(..)));
+        let long = SmallKey::from_string("y".repeat(INLINE + 1));
+        assert!(matches!(long, SmallKey::Heap(_)));
+        assert_eq!(long.len(), INLINE + 1);
+
+        assert_eq!(SmallKey::new("").into_string(), "");
+        assert_eq!(SmallKey::from_string(edge.clone()).into_string(), edge);
+        assert_eq!(format!("{:?} {}", short, short), "\"héllo\" héllo");
+    }
+}
+
+#[test]
+fn test_matches() {
+    let key = from_str("user:1041");
+    assert!(matches(&key, "user:1041"));
+    assert!(!matches(&key, "user:104"));
+    assert!(!matches(&key, "user:1042"));
+    assert!(!matches(&key, "xser:1041"));
+    assert!(!matches(&key, "user:1141"));
+    assert!(matches(&from_str(""), ""));
+    assert!(!matches(&from_str(""), "a"));
+}
@@ -1 +1,81 @@
+#![cfg_attr(feature = "allocator_api", feature(allocator_api))]
+
+// A table literal: `stupid_hash!{ "a" => 1, "b" => 2 }`. Expands to
+// Hash::from() over an array of the pairs, so the table is sized for them
+// up front. Defined before the modules so they can use it too.
+#[macro_export]
+macro_rules! stupid_hash {
+    () => {
+        $crate::hash::Hash::new()
+    };
+    ($($key:expr => $value:expr),+ $(,)?) => {
+        $crate::hash::Hash::from([$(($key, $value)),+])
+    };
+}
+
+// A read-only table laid out at compile time:
+// `static KEYWORDS: StaticHash<u32> = static_hash!{ "fn" => 1, "let" => 2 };`
+// The keys must be string literals; a duplicate is a compile error. The
+// stupid_hash_macros crate does the work and gets this crate's path from
+// here, so callers needn't depend on it themselves.
+#[macro_export]
+macro_rules! static_hash {
+    ($($body:tt)*) => {
+        $crate::__static_hash!($crate; $($body)*)
+    };
+}
+
+extern crate stupid_hash_macros;
+
+#[doc(hidden)]
+pub use stupid_hash_macros::static_hash_impl as __static_hash;
+
+pub mod bimap;
+pub mod bits;
+pub mod bloom;
+pub mod bounded;
+pub mod cached;
+pub mod compat;
+pub mod config;
+pub mod counter;
+pub mod cuckoo;
+pub mod error;
+pub mod expiring;
+pub mod glob;
 pub mod hash;
+pub mod hll;
+pub mod hopscotch;
+pub mod inline;
+pub mod journal;
+mod key;
+pub mod lww;
+pub mod minhash;
+pub mod multi;
+pub mod mvcc;
+pub mod observe;
+pub mod oplog;
+pub mod ordered;
+pub mod paged;
+pub mod perfect;
+pub mod persistent;
+pub mod ring;
+pub mod sharded;
+pub mod sketch;
+mod trie;
+pub mod txn;
+pub mod typed;
+pub mod writebehind;
+
+pub use error::Error;
+
+#[cfg(feature = "allocator_api")]
+pub mod allocator;
+
+#[cfg(feature = "ffi")]
+pub mod ffi;
+
+#[cfg(feature = "server")]
+pub mod server;
+
+#[cfg(any(test, feature = "test-utils"))]
+pub mod testutil;
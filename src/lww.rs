@@ -0,0 +1,209 @@
+// A table whose copies can be updated independently and reconciled later:
+// every write carries a logical timestamp, and merge_lww() keeps whichever
+// write to a key came last. Timestamps are Lamport clocks paired with the id
+// of the replica that wrote them, so every two writes are ordered and any
+// two replicas that have seen the same writes hold the same contents,
+// whatever order they merged in. Removals are kept as stamped tombstones;
+// otherwise merging with a replica that missed the removal would bring the
+// key back.
+
+use hash::Hash;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Stamp {
+    pub time: u64,
+    // Breaks ties between replicas writing at the same time
+    pub replica: u64,
+}
+
+#[derive(Clone, Debug)]
+struct Stamped<T> {
+    stamp: Stamp,
+    // None for a removal
+    value: Option<T>,
+}
+
+pub struct LwwHash<T: Clone> {
+    entries: Hash<Stamped<T>>,
+    replica: u64,
+    // The latest time written or merged in
+    clock: u64,
+    // Entries that aren't tombstones
+    count: usize,
+}
+
+impl<T> LwwHash<T>
+where
+    T: Clone,
+{
+    // Each replica needs an id of its own
+    pub fn new(replica: u64) -> Self {
+        LwwHash {
+            entries: Hash::new(),
+            replica,
+            clock: 0,
+            count: 0,
+        }
+    }
+
+    pub fn replica(&self) -> u64 {
+        self.replica
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    // Removed keys still taking up an entry
+    pub fn tombstones(&self) -> usize {
+        self.entries.len() - self.count
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<&T> {
+        self.entries.lookup(name)?.value.as_ref()
+    }
+
+    // When `name` was last written or removed
+    pub fn stamp(&self, name: &str) -> Option<Stamp> {
+        self.entries.lookup(name).map(|entry| entry.stamp)
+    }
+
+    fn tick(&mut self) -> Stamp {
+        self.clock += 1;
+        Stamp {
+            time: self.clock,
+            replica: self.replica,
+        }
+    }
+
+    // Puts `entry` in unless the key already has a later write. Returns
+    // whether it went in.
+    fn apply(&mut self, name: &str, entry: Stamped<T>) -> bool {
+        self.clock = self.clock.max(entry.stamp.time);
+        let live = entry.value.is_some();
+
+        match self.entries.lookup_mut(name) {
+            Some(current) if current.stamp >= entry.stamp => return false,
+            Some(current) => {
+                match (current.value.is_some(), live) {
+                    (false, true) => self.count += 1,
+                    (true, false) => self.count -= 1,
+                    _ => {}
+                }
+                *current = entry;
+                return true;
+            }
+            None => {}
+        }
+        self.entries.upsert(name, entry);
+        if live {
+            self.count += 1;
+        }
+        true
+    }
+
+    // Returns the stamp the write was given
+    pub fn upsert(&mut self, name: &str, value: T) -> Stamp {
+        let stamp = self.tick();
+        self.apply(
+            name,
+            Stamped {
+                stamp,
+                value: Some(value),
+            },
+        );
+        stamp
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<T> {
+        let old = self.lookup(name).cloned()?;
+        let stamp = self.tick();
+        self.apply(name, Stamped { stamp, value: None });
+        Some(old)
+    }
+
+    // Takes in every write from `other` that is later than this table's
+    // write to the same key. Returns how many keys changed.
+    pub fn merge_lww(&mut self, other: &LwwHash<T>) -> usize {
+        let mut changed = 0;
+        for (name, entry) in other.entries.iter() {
+            if self.apply(name, entry.clone()) {
+                changed += 1;
+            }
+        }
+        changed
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &T)> {
+        self.entries.iter().filter_map(|(name, entry)| entry.value.as_ref().map(|v| (name, v)))
+    }
+}
+
+#[test]
+fn test_lww_merge() {
+    let mut a = LwwHash::new(1);
+    let mut b = LwwHash::new(2);
+    a.upsert("colour", "red");
+    a.upsert("size", "small");
+    b.upsert("colour", "blue");
+
+    // Same time on both sides: the higher replica id wins
+    assert_eq!(a.stamp("colour").unwrap().time, b.stamp("colour").unwrap().time);
+    assert_eq!(b.merge_lww(&a), 1);
+    assert_eq!(b.lookup("colour"), Some(&"blue"));
+
+    // b has now seen a's writes, so its next write is later than them all
+    b.upsert("size", "large");
+    a.remove("colour");
+    assert_eq!(a.merge_lww(&b), 1);
+    assert_eq!(a.lookup("size"), Some(&"large"));
+    assert_eq!(a.lookup("colour"), None);
+
+    // a's removal came after b's write to "colour"
+    assert_eq!(b.merge_lww(&a), 1);
+    let mut left: Vec<_> = a.iter().collect();
+    let mut right: Vec<_> = b.iter().collect();
+    left.sort();
+    right.sort();
+    assert_eq!(left, right);
+    assert_eq!(left, vec![("size", &"large")]);
+    assert_eq!((a.len(), a.tombstones()), (1, 1));
+    assert_eq!(a.merge_lww(&b), 0);
+}
+
+#[test]
+fn test_lww_merge_order() {
+    // Three replicas make conflicting writes; merging in any order ends up
+    // in the same place
+    let mut replicas: Vec<LwwHash<u64>> = (0..3).map(LwwHash::new).collect();
+    for (r, table) in replicas.iter_mut().enumerate() {
+        for i in 0..50u64 {
+            let name = (i % 20).to_string();
+            if (i + r as u64).is_multiple_of(7) {
+                table.remove(&name);
+            } else {
+                table.upsert(&name, i * 10 + r as u64);
+            }
+        }
+    }
+
+    let orders = [[0, 1, 2], [2, 1, 0], [1, 0, 2]];
+    let results: Vec<Vec<(String, u64)>> = orders
+        .iter()
+        .map(|order| {
+            let mut merged = LwwHash::new(99);
+            for &r in order {
+                merged.merge_lww(&replicas[r]);
+            }
+            let mut entries: Vec<_> = merged.iter().map(|(k, &v)| (k.to_string(), v)).collect();
+            entries.sort();
+            entries
+        })
+        .collect();
+    assert_eq!(results[0], results[1]);
+    assert_eq!(results[0], results[2]);
+}
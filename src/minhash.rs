@@ -0,0 +1,104 @@
+// MinHash signatures of key sets. Each of the k positions keeps the minimum
+// of one seeded variant of the crate hash over every key, so the fraction of
+// positions two signatures share estimates the Jaccard similarity of the
+// sets, with error around 1 / sqrt(k).
+
+use hash::{hash_str_seeded, mix64, Hash};
+
+const SEED_STEP: usize = 0x9e37_79b9;
+
+#[derive(Clone, Debug)]
+pub struct MinHasher {
+    seeds: Vec<usize>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Signature {
+    mins: Vec<u64>,
+}
+
+impl MinHasher {
+    // A hasher producing signatures of `k` positions. Signatures are only
+    // comparable if they come from hashers with the same k.
+    pub fn new(k: usize) -> Self {
+        MinHasher {
+            seeds: (1..k.max(1) + 1).map(|i| i.wrapping_mul(SEED_STEP)).collect(),
+        }
+    }
+
+    pub fn signature<'a, I>(&self, keys: I) -> Signature
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let mut mins = vec![u64::MAX; self.seeds.len()];
+
+        for name in keys {
+            for (min, &seed) in mins.iter_mut().zip(self.seeds.iter()) {
+                let h = mix64(hash_str_seeded(name, seed));
+                if h < *min {
+                    *min = h;
+                }
+            }
+        }
+
+        Signature { mins }
+    }
+
+    // Signature of a table's key set
+    pub fn signature_of<T: Clone>(&self, hash: &Hash<T>) -> Signature {
+        self.signature(hash.iter().map(|(name, _)| name))
+    }
+}
+
+impl Signature {
+    pub fn len(&self) -> usize {
+        self.mins.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mins.is_empty()
+    }
+
+    // Estimated |A ∩ B| / |A ∪ B| of the two key sets
+    pub fn jaccard(&self, other: &Signature) -> f64 {
+        assert_eq!(self.len(), other.len(), "signatures of different lengths");
+
+        let same = self
+            .mins
+            .iter()
+            .zip(other.mins.iter())
+            .filter(|&(a, b)| a == b)
+            .count();
+        same as f64 / self.len() as f64
+    }
+}
+
+#[test]
+fn test_minhash_jaccard() {
+    let hasher = MinHasher::new(256);
+    let mut a = Hash::new();
+    let mut b = Hash::new();
+
+    // 750 shared keys out of 1250 total: similarity 0.6
+    for i in 0..1000 {
+        a.upsert(&format!("key{}", i), ());
+    }
+    for i in 250..1250 {
+        b.upsert(&format!("key{}", i), ());
+    }
+
+    let sa = hasher.signature_of(&a);
+    let sb = hasher.signature_of(&b);
+    let estimate = sa.jaccard(&sb);
+    assert!((estimate - 0.6).abs() < 0.1, "estimate {}", estimate);
+    assert_eq!(sa.jaccard(&sa), 1.0);
+}
+
+#[test]
+fn test_minhash_disjoint() {
+    let hasher = MinHasher::new(64);
+    let a = hasher.signature(vec!["a", "b", "c"]);
+    let b = hasher.signature(vec!["x", "y", "z"]);
+    assert!(a.jaccard(&b) < 0.2);
+    assert_eq!(a.len(), 64);
+}
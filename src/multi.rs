@@ -0,0 +1,124 @@
+// A multimap: each key maps to every value inserted under it, in insertion
+// order. Stored as a `Hash<Vec<T>>`, but updated in place so inserting a
+// value never clones the ones already there.
+
+use std::slice;
+
+use hash::Hash;
+
+pub struct MultiHash<T: Clone> {
+    inner: Hash<Vec<T>>,
+    count: usize,
+}
+
+impl<T> MultiHash<T>
+where
+    T: Clone,
+{
+    pub fn new() -> Self {
+        MultiHash {
+            inner: Hash::new(),
+            count: 0,
+        }
+    }
+
+    // Total number of values across all keys
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn key_count(&self) -> usize {
+        self.inner.len()
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.inner.lookup(name).is_some()
+    }
+
+    pub fn insert(&mut self, name: &str, value: T) {
+        match self.inner.lookup_mut(name) {
+            Some(values) => values.push(value),
+            None => {
+                self.inner.upsert(name, vec![value]);
+            }
+        }
+        self.count += 1;
+    }
+
+    // Every value stored under the key, oldest first
+    pub fn get_all(&self, name: &str) -> slice::Iter<'_, T> {
+        match self.inner.lookup(name) {
+            Some(values) => values.iter(),
+            None => [].iter(),
+        }
+    }
+
+    // Removes the first occurrence of `value` under the key. Returns false if
+    // it wasn't there.
+    pub fn remove_one(&mut self, name: &str, value: &T) -> bool
+    where
+        T: PartialEq,
+    {
+        let now_empty = match self.inner.lookup_mut(name) {
+            Some(values) => match values.iter().position(|v| v == value) {
+                Some(i) => {
+                    values.remove(i);
+                    values.is_empty()
+                }
+                None => return false,
+            },
+            None => return false,
+        };
+
+        if now_empty {
+            self.inner.remove(name);
+        }
+        self.count -= 1;
+        true
+    }
+
+    // Removes the key and returns all its values, oldest first
+    pub fn remove_all(&mut self, name: &str) -> Vec<T> {
+        let values = self.inner.remove(name).unwrap_or_default();
+        self.count -= values.len();
+        values
+    }
+}
+
+impl<T> Default for MultiHash<T>
+where
+    T: Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[test]
+fn test_multi_basics() {
+    let mut multi = MultiHash::new();
+    multi.insert("fruit", "apple");
+    multi.insert("fruit", "pear");
+    multi.insert("fruit", "apple");
+    multi.insert("veg", "leek");
+
+    assert_eq!(multi.len(), 4);
+    assert_eq!(multi.key_count(), 2);
+    assert_eq!(multi.get_all("fruit").collect::<Vec<_>>(), vec![&"apple", &"pear", &"apple"]);
+    assert_eq!(multi.get_all("nothing").count(), 0);
+
+    assert!(multi.remove_one("fruit", &"apple"));
+    assert!(!multi.remove_one("fruit", &"plum"));
+    assert_eq!(multi.get_all("fruit").collect::<Vec<_>>(), vec![&"pear", &"apple"]);
+
+    assert!(multi.remove_one("veg", &"leek"));
+    assert!(!multi.contains_key("veg"));
+
+    assert_eq!(multi.remove_all("fruit"), vec!["pear", "apple"]);
+    assert!(multi.remove_all("fruit").is_empty());
+    assert!(multi.is_empty());
+}
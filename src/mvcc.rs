@@ -0,0 +1,205 @@
+// A table that keeps old values around. Every write gets the next version
+// number, and reads can ask for the table as it was at any version: a
+// snapshot taken at version v keeps giving the same answers however many
+// writes come after it. gc() throws away history nobody needs any more.
+
+use hash::Hash;
+
+pub struct VersionedHash<T: Clone> {
+    // Every key's history, oldest first; None marks a removal
+    versions: Hash<Vec<(u64, Option<T>)>>,
+    version: u64,
+    count: usize,
+}
+
+// Value of a history as of `version`
+fn at<T>(history: &[(u64, Option<T>)], version: u64) -> Option<&T> {
+    history
+        .iter()
+        .rev()
+        .find(|&&(v, _)| v <= version)
+        .and_then(|entry| entry.1.as_ref())
+}
+
+impl<T> VersionedHash<T>
+where
+    T: Clone,
+{
+    pub fn new() -> Self {
+        VersionedHash {
+            versions: Hash::new(),
+            version: 0,
+            count: 0,
+        }
+    }
+
+    // Number of keys in the latest version
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    // The version of the last write; 0 before anything was written
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<&T> {
+        self.lookup_at(name, self.version)
+    }
+
+    pub fn lookup_at(&self, name: &str, version: u64) -> Option<&T> {
+        at(self.versions.lookup(name)?, version)
+    }
+
+    fn write(&mut self, name: &str, value: Option<T>) -> u64 {
+        let existed = self.lookup(name).is_some();
+        match (existed, value.is_some()) {
+            (false, true) => self.count += 1,
+            (true, false) => self.count -= 1,
+            _ => {}
+        }
+
+        self.version += 1;
+        let entry = (self.version, value);
+        match self.versions.lookup_mut(name) {
+            Some(history) => history.push(entry),
+            None => {
+                self.versions.upsert(name, vec![entry]);
+            }
+        }
+        self.version
+    }
+
+    // Sets `name` to `value`; returns the new version
+    pub fn upsert(&mut self, name: &str, value: T) -> u64 {
+        self.write(name, Some(value))
+    }
+
+    // Removes `name` from later versions, or returns None if it isn't
+    // there now
+    pub fn remove(&mut self, name: &str) -> Option<u64> {
+        self.lookup(name)?;
+        Some(self.write(name, None))
+    }
+
+    // A read-only view of the table as of `version`
+    pub fn snapshot_at(&self, version: u64) -> Snapshot<'_, T> {
+        Snapshot {
+            table: self,
+            version,
+        }
+    }
+
+    // Drops history only reads older than `before` could see. Afterwards,
+    // reads at versions from `before` on are unaffected; older ones may see
+    // keys as missing.
+    pub fn gc(&mut self, before: u64) {
+        let names: Vec<String> = self.versions.iter().map(|(name, _)| name.to_string()).collect();
+
+        for name in names {
+            let emptied = {
+                let history = self.versions.lookup_mut(&name).unwrap();
+                // The newest version visible at `before` stays, everything
+                // older than it goes
+                if let Some(keep) = history.iter().rposition(|&(v, _)| v <= before) {
+                    history.drain(..keep);
+                    if history[0].1.is_none() {
+                        history.remove(0);
+                    }
+                }
+                history.is_empty()
+            };
+
+            if emptied {
+                self.versions.remove(&name);
+            }
+        }
+    }
+}
+
+impl<T> Default for VersionedHash<T>
+where
+    T: Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct Snapshot<'a, T: 'a + Clone> {
+    table: &'a VersionedHash<T>,
+    version: u64,
+}
+
+impl<'a, T> Snapshot<'a, T>
+where
+    T: Clone,
+{
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<&'a T> {
+        self.table.lookup_at(name, self.version)
+    }
+
+    // Every key present at the snapshot's version
+    pub fn iter(&self) -> impl Iterator<Item = (&'a str, &'a T)> + 'a {
+        let version = self.version;
+        self.table
+            .versions
+            .iter()
+            .filter_map(move |(name, history)| at(history, version).map(|value| (name, value)))
+    }
+}
+
+#[test]
+fn test_mvcc_snapshots() {
+    let mut table = VersionedHash::new();
+    let v1 = table.upsert("a", 1);
+    let v2 = table.upsert("b", 2);
+    table.upsert("a", 10);
+    let v4 = table.remove("b").unwrap();
+    assert_eq!(table.remove("b"), None);
+
+    assert_eq!(table.len(), 1);
+    assert_eq!(table.lookup("a"), Some(&10));
+    assert_eq!(table.lookup("b"), None);
+
+    let old = table.snapshot_at(v2);
+    assert_eq!(old.lookup("a"), Some(&1));
+    assert_eq!(old.lookup("b"), Some(&2));
+    let mut keys: Vec<(&str, &i32)> = old.iter().collect();
+    keys.sort();
+    assert_eq!(keys, vec![("a", &1), ("b", &2)]);
+
+    assert_eq!(table.lookup_at("b", v1), None);
+    assert_eq!(table.lookup_at("b", v4), None);
+    assert_eq!(table.lookup_at("a", 0), None);
+}
+
+#[test]
+fn test_mvcc_gc() {
+    let mut table = VersionedHash::new();
+    table.upsert("a", 1);
+    table.upsert("b", 2);
+    let v3 = table.upsert("a", 3);
+    table.remove("b");
+    let v5 = table.upsert("a", 5);
+
+    table.gc(v5);
+    assert_eq!(table.lookup("a"), Some(&5));
+    assert_eq!(table.lookup_at("a", v3), None);
+    assert_eq!(table.versions.lookup("a").map(|h| h.len()), Some(1));
+    // Nothing is left of a key that was removed before the cutoff
+    assert_eq!(table.versions.lookup("b"), None);
+
+    let v6 = table.upsert("c", 6);
+    table.gc(v6 - 1);
+    assert_eq!(table.lookup("c"), Some(&6));
+    assert_eq!(table.len(), 2);
+}
@@ -0,0 +1,373 @@
+// A table that tells interested parties about every change made through it.
+// Callbacks run synchronously, in registration order, right after the change
+// is made, so whatever they maintain (a secondary index, metrics) is never
+// behind the table. Other threads can subscribe to a channel of the same
+// changes instead. A Reservoir fed from such a channel keeps a uniform
+// sample of the keys, for looking at a huge table without walking it.
+
+use std::mem;
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use hash::Hash;
+
+type InsertHook<T> = Box<dyn FnMut(&str, &T)>;
+// Called with the old value, then the new one
+type UpdateHook<T> = Box<dyn FnMut(&str, &T, &T)>;
+type RemoveHook<T> = Box<dyn FnMut(&str, &T)>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChangeKind {
+    Insert,
+    Update,
+    Remove,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChangeEvent<T> {
+    pub key: String,
+    pub kind: ChangeKind,
+    // The value after the change; None for a removal
+    pub value: Option<T>,
+}
+
+// Sends an event to every subscriber, forgetting those that hung up
+fn publish<T: Clone>(
+    subscribers: &mut Vec<Sender<ChangeEvent<T>>>,
+    name: &str,
+    kind: ChangeKind,
+    value: Option<&T>,
+) {
+    subscribers.retain(|subscriber| {
+        let event = ChangeEvent {
+            key: name.to_string(),
+            kind,
+            value: value.cloned(),
+        };
+        subscriber.send(event).is_ok()
+    });
+}
+
+pub struct ObservedHash<T: Clone> {
+    hash: Hash<T>,
+    on_insert: Vec<InsertHook<T>>,
+    on_update: Vec<UpdateHook<T>>,
+    on_remove: Vec<RemoveHook<T>>,
+    subscribers: Vec<Sender<ChangeEvent<T>>>,
+}
+
+impl<T> ObservedHash<T>
+where
+    T: Clone,
+{
+    pub fn new() -> Self {
+        ObservedHash::from_hash(Hash::new())
+    }
+
+    pub fn from_hash(hash: Hash<T>) -> Self {
+        ObservedHash {
+            hash,
+            on_insert: Vec::new(),
+            on_update: Vec::new(),
+            on_remove: Vec::new(),
+            subscribers: Vec::new(),
+        }
+    }
+
+    pub fn inner(&self) -> &Hash<T> {
+        &self.hash
+    }
+
+    pub fn into_inner(self) -> Hash<T> {
+        self.hash
+    }
+
+    pub fn len(&self) -> usize {
+        self.hash.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hash.is_empty()
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<&T> {
+        self.hash.lookup(name)
+    }
+
+    pub fn on_insert<F: FnMut(&str, &T) + 'static>(&mut self, hook: F) {
+        self.on_insert.push(Box::new(hook));
+    }
+
+    pub fn on_update<F: FnMut(&str, &T, &T) + 'static>(&mut self, hook: F) {
+        self.on_update.push(Box::new(hook));
+    }
+
+    pub fn on_remove<F: FnMut(&str, &T) + 'static>(&mut self, hook: F) {
+        self.on_remove.push(Box::new(hook));
+    }
+
+    // A channel that receives an event for every change from now on.
+    // Subscribers that hang up are forgotten at the next change.
+    pub fn subscribe(&mut self) -> Receiver<ChangeEvent<T>> {
+        let (sender, receiver) = channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    pub fn upsert(&mut self, name: &str, value: T) -> bool {
+        if let Some(current) = self.hash.lookup_mut(name) {
+            let old = mem::replace(current, value);
+            for hook in &mut self.on_update {
+                hook(name, &old, current);
+            }
+            publish(&mut self.subscribers, name, ChangeKind::Update, Some(current));
+            return false;
+        }
+
+        self.hash.upsert(name, value);
+        let value = self.hash.lookup(name).unwrap();
+        for hook in &mut self.on_insert {
+            hook(name, value);
+        }
+        publish(&mut self.subscribers, name, ChangeKind::Insert, Some(value));
+        true
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<T> {
+        let value = self.hash.remove(name)?;
+        for hook in &mut self.on_remove {
+            hook(name, &value);
+        }
+        publish(&mut self.subscribers, name, ChangeKind::Remove, None);
+        Some(value)
+    }
+}
+
+impl<T> Default for ObservedHash<T>
+where
+    T: Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// A k-entry uniform sample of a table's keys, kept up to date from its
+// change events while the table carries on being written. Each insert
+// takes a place in the sample with probability k / len, like
+// Hash::sample() but one key at a time. Updates refresh sampled values and
+// removals drop sampled keys, leaving a place for a later insert; after
+// many removals the sample is close to uniform rather than exactly so.
+pub struct Reservoir<T, R> {
+    k: usize,
+    sample: Vec<(String, T)>,
+    // Keys in the table, as far as the events seen say
+    population: u64,
+    random: R,
+}
+
+impl<T, R> Reservoir<T, R>
+where
+    T: Clone,
+    R: FnMut() -> u64,
+{
+    // `random` is as for Hash::sample(): anything returning uniformly
+    // distributed u64s
+    pub fn new(k: usize, random: R) -> Self {
+        Reservoir {
+            k,
+            sample: Vec::with_capacity(k),
+            population: 0,
+            random,
+        }
+    }
+
+    // Starts from a sample of what `hash` already holds. Subscribe first,
+    // so no change falls between the two.
+    pub fn from_hash(hash: &Hash<T>, k: usize, random: R) -> Self {
+        let mut reservoir = Reservoir::new(k, random);
+        let sample = hash.sample(k, &mut reservoir.random);
+        reservoir.sample = sample
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect();
+        reservoir.population = hash.len() as u64;
+        reservoir
+    }
+
+    pub fn sample(&self) -> &[(String, T)] {
+        &self.sample
+    }
+
+    pub fn population(&self) -> u64 {
+        self.population
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.sample.iter().position(|(key, _)| key == name)
+    }
+
+    pub fn observe(&mut self, event: &ChangeEvent<T>) {
+        match event.kind {
+            ChangeKind::Insert => {
+                self.population += 1;
+                let entry = (event.key.clone(), event.value.clone().unwrap());
+                if self.sample.len() < self.k {
+                    self.sample.push(entry);
+                } else {
+                    let j = ((self.random)() % self.population) as usize;
+                    if j < self.k {
+                        self.sample[j] = entry;
+                    }
+                }
+            }
+            ChangeKind::Update => {
+                if let Some(i) = self.position(&event.key) {
+                    self.sample[i].1 = event.value.clone().unwrap();
+                }
+            }
+            ChangeKind::Remove => {
+                self.population = self.population.saturating_sub(1);
+                if let Some(i) = self.position(&event.key) {
+                    self.sample.swap_remove(i);
+                }
+            }
+        }
+    }
+
+    // Takes in every event waiting on `events` without blocking, returning
+    // how many there were
+    pub fn catch_up(&mut self, events: &Receiver<ChangeEvent<T>>) -> usize {
+        let mut n = 0;
+        for event in events.try_iter() {
+            self.observe(&event);
+            n += 1;
+        }
+        n
+    }
+}
+
+#[test]
+fn test_hooks() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let mut table = ObservedHash::new();
+
+    let l = log.clone();
+    table.on_insert(move |name, value| l.borrow_mut().push(format!("+{}={}", name, value)));
+    let l = log.clone();
+    table.on_update(move |name, old, new| {
+        l.borrow_mut().push(format!("{}:{}->{}", name, old, new))
+    });
+    let l = log.clone();
+    table.on_remove(move |name, value| l.borrow_mut().push(format!("-{}={}", name, value)));
+
+    table.upsert("a", 1);
+    table.upsert("a", 2);
+    table.remove("a");
+    table.remove("a");
+
+    assert_eq!(*log.borrow(), vec!["+a=1", "a:1->2", "-a=2"]);
+    assert!(table.is_empty());
+}
+
+#[test]
+fn test_subscribe() {
+    use std::thread;
+
+    let mut table = ObservedHash::new();
+    let events = table.subscribe();
+    let dropped = table.subscribe();
+    drop(dropped);
+
+    let watcher = thread::spawn(move || events.iter().collect::<Vec<_>>());
+    table.upsert("a", 1);
+    table.upsert("a", 2);
+    table.remove("a");
+    assert_eq!(table.subscribers.len(), 1);
+    drop(table);
+
+    let event = |kind, value| ChangeEvent {
+        key: "a".to_string(),
+        kind,
+        value,
+    };
+    assert_eq!(
+        watcher.join().unwrap(),
+        vec![
+            event(ChangeKind::Insert, Some(1)),
+            event(ChangeKind::Update, Some(2)),
+            event(ChangeKind::Remove, None),
+        ]
+    );
+}
+
+#[test]
+fn test_reservoir() {
+    use std::thread;
+
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    let xorshift = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+
+    let mut table = ObservedHash::new();
+    for i in 0..50 {
+        table.upsert(&i.to_string(), i);
+    }
+    let events = table.subscribe();
+    let mut reservoir = Reservoir::from_hash(table.inner(), 10, xorshift);
+    assert_eq!(reservoir.sample().len(), 10);
+
+    // The monitor samples on its own thread while this one writes
+    let monitor = thread::spawn(move || {
+        let mut events_seen = 0;
+        while reservoir.population() < 10_000 {
+            events_seen += reservoir.catch_up(&events);
+            thread::yield_now();
+        }
+        (reservoir, events_seen)
+    });
+    for i in 50..10_000 {
+        table.upsert(&i.to_string(), i);
+    }
+    let (reservoir, events_seen) = monitor.join().unwrap();
+    assert_eq!(events_seen, 10_000 - 50);
+
+    let sample = reservoir.sample();
+    assert_eq!(sample.len(), 10);
+    for (name, value) in sample {
+        assert_eq!(table.lookup(name), Some(value));
+    }
+    // Nearly all of the keys came in after the reservoir was made
+    assert!(sample.iter().filter(|&&(_, value)| value >= 50).count() >= 8);
+}
+
+#[test]
+fn test_reservoir_removals() {
+    let mut table = ObservedHash::new();
+    let events = table.subscribe();
+    let mut reservoir = Reservoir::new(4, || 0);
+    for i in 0..4 {
+        table.upsert(&i.to_string(), i);
+    }
+    table.upsert("1", 10);
+    table.remove("2");
+    reservoir.catch_up(&events);
+
+    let mut sample = reservoir.sample().to_vec();
+    sample.sort();
+    let expected = vec![("0", 0), ("1", 10), ("3", 3)];
+    let expected: Vec<_> = expected.into_iter().map(|(k, v)| (k.to_string(), v)).collect();
+    assert_eq!(sample, expected);
+    assert_eq!(reservoir.population(), 3);
+
+    // The free place goes to the next insert
+    table.upsert("4", 4);
+    reservoir.catch_up(&events);
+    assert_eq!(reservoir.sample().len(), 4);
+}
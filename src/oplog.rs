@@ -0,0 +1,397 @@
+// Primary/replica replication by operation log. A LoggedHash numbers every
+// change made through it and keeps the records; a Follower applies them in
+// order to its own table and ends up with the same contents. Records can be
+// written to and read back from any byte stream, so the log can go over a
+// socket or into a file. The primary keeps records until told they've been
+// applied everywhere.
+
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use hash::Hash;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Op<T> {
+    Upsert(String, T),
+    Remove(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record<T> {
+    // Starts at 1 and goes up by one with every change
+    pub seq: u64,
+    pub op: Op<T>,
+}
+
+// Values that can be written into a log
+pub trait LogValue: Sized {
+    fn encode(&self) -> Vec<u8>;
+    // None if the bytes aren't a valid encoding
+    fn decode(bytes: Vec<u8>) -> Option<Self>;
+}
+
+impl LogValue for Vec<u8> {
+    fn encode(&self) -> Vec<u8> {
+        self.clone()
+    }
+
+    fn decode(bytes: Vec<u8>) -> Option<Self> {
+        Some(bytes)
+    }
+}
+
+impl LogValue for String {
+    fn encode(&self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
+
+    fn decode(bytes: Vec<u8>) -> Option<Self> {
+        String::from_utf8(bytes).ok()
+    }
+}
+
+const UPSERT: u8 = b'U';
+const REMOVE: u8 = b'R';
+
+pub(crate) fn invalid(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, what.to_string())
+}
+
+// Lengths are u32s on the wire; anything longer can't be written rather
+// than written with a truncated length
+fn length_prefix(len: usize) -> io::Result<[u8; 4]> {
+    match u32::try_from(len) {
+        Ok(len) => Ok(len.to_be_bytes()),
+        Err(_) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} bytes is too long to write", len),
+        )),
+    }
+}
+
+pub(crate) fn write_bytes(w: &mut dyn Write, bytes: &[u8]) -> io::Result<()> {
+    w.write_all(&length_prefix(bytes.len())?)?;
+    w.write_all(bytes)
+}
+
+// Grows the buffer as the bytes arrive, so a corrupt length can't make it
+// allocate gigabytes up front
+pub(crate) fn read_bytes(r: &mut dyn Read) -> io::Result<Vec<u8>> {
+    let mut len = [0; 4];
+    r.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len) as u64;
+    let mut bytes = Vec::new();
+    if r.take(len).read_to_end(&mut bytes)? as u64 != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(bytes)
+}
+
+impl<T: LogValue> Record<T> {
+    // A tag byte, the sequence number, then the key and (for an upsert) the
+    // value, each prefixed by its length. Integers are big-endian.
+    // A record too long to write is refused before any of it is written.
+    pub fn write_to(&self, w: &mut dyn Write) -> io::Result<()> {
+        let (tag, key, value) = match self.op {
+            Op::Upsert(ref key, ref value) => (UPSERT, key, Some(value.encode())),
+            Op::Remove(ref key) => (REMOVE, key, None),
+        };
+        length_prefix(key.len())?;
+        if let Some(ref value) = value {
+            length_prefix(value.len())?;
+        }
+        w.write_all(&[tag])?;
+        w.write_all(&self.seq.to_be_bytes())?;
+        write_bytes(w, key.as_bytes())?;
+        if let Some(ref value) = value {
+            write_bytes(w, value)?;
+        }
+        Ok(())
+    }
+
+    // Reads the next record, or None if the stream ends between records
+    pub fn read_from(r: &mut dyn Read) -> io::Result<Option<Self>> {
+        let mut tag = [0];
+        if r.read(&mut tag)? == 0 {
+            return Ok(None);
+        }
+        let mut seq = [0; 8];
+        r.read_exact(&mut seq)?;
+        let key = String::from_utf8(read_bytes(r)?).map_err(|_| invalid("key is not UTF-8"))?;
+
+        let op = match tag[0] {
+            UPSERT => {
+                let value = T::decode(read_bytes(r)?).ok_or_else(|| invalid("bad value"))?;
+                Op::Upsert(key, value)
+            }
+            REMOVE => Op::Remove(key),
+            _ => return Err(invalid("unknown record type")),
+        };
+        Ok(Some(Record {
+            seq: u64::from_be_bytes(seq),
+            op,
+        }))
+    }
+}
+
+pub struct LoggedHash<T: Clone> {
+    hash: Hash<T>,
+    // Every record after the last truncate(), oldest first
+    log: Vec<Record<T>>,
+    seq: u64,
+}
+
+impl<T> LoggedHash<T>
+where
+    T: Clone,
+{
+    pub fn new() -> Self {
+        LoggedHash {
+            hash: Hash::new(),
+            log: Vec::new(),
+            seq: 0,
+        }
+    }
+
+    pub fn inner(&self) -> &Hash<T> {
+        &self.hash
+    }
+
+    pub fn len(&self) -> usize {
+        self.hash.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hash.is_empty()
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<&T> {
+        self.hash.lookup(name)
+    }
+
+    // The sequence number of the latest change, 0 before any
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    fn record(&mut self, op: Op<T>) {
+        self.seq += 1;
+        self.log.push(Record { seq: self.seq, op });
+    }
+
+    pub fn upsert(&mut self, name: &str, value: T) -> bool {
+        self.record(Op::Upsert(name.to_string(), value.clone()));
+        self.hash.upsert(name, value)
+    }
+
+    // Removing a missing key changes nothing, so isn't logged
+    pub fn remove(&mut self, name: &str) -> Option<T> {
+        let value = self.hash.remove(name)?;
+        self.record(Op::Remove(name.to_string()));
+        Some(value)
+    }
+
+    // The records a follower that has applied everything up to `seq` still
+    // needs, or None if some of them have been truncated away, in which
+    // case the follower has to start over from a copy of the table
+    pub fn records_since(&self, seq: u64) -> Option<&[Record<T>]> {
+        let first = self.log.first().map_or(self.seq + 1, |record| record.seq);
+        if seq + 1 < first {
+            return None;
+        }
+        let start = (seq + 1 - first) as usize;
+        Some(&self.log[start.min(self.log.len())..])
+    }
+
+    // Forgets the records up to and including `seq`, once every follower
+    // has applied them
+    pub fn truncate(&mut self, seq: u64) {
+        let keep = self.log.iter().position(|record| record.seq > seq);
+        self.log.drain(..keep.unwrap_or(self.log.len()));
+    }
+}
+
+impl<T> Default for LoggedHash<T>
+where
+    T: Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// A record arrived out of order: `expected` is the sequence number that
+// should have come next
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Gap {
+    pub expected: u64,
+    pub found: u64,
+}
+
+impl fmt::Display for Gap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "expected record {}, got {}", self.expected, self.found)
+    }
+}
+
+impl Error for Gap {}
+
+pub struct Follower<T: Clone> {
+    hash: Hash<T>,
+    applied: u64,
+}
+
+impl<T> Follower<T>
+where
+    T: Clone,
+{
+    pub fn new() -> Self {
+        Follower {
+            hash: Hash::new(),
+            applied: 0,
+        }
+    }
+
+    // Follows on from a copy of the primary's table as of `seq`
+    pub fn from_hash(hash: Hash<T>, seq: u64) -> Self {
+        Follower { hash, applied: seq }
+    }
+
+    pub fn inner(&self) -> &Hash<T> {
+        &self.hash
+    }
+
+    pub fn into_inner(self) -> Hash<T> {
+        self.hash
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<&T> {
+        self.hash.lookup(name)
+    }
+
+    // The sequence number of the last record applied
+    pub fn applied(&self) -> u64 {
+        self.applied
+    }
+
+    // Applies records in order. Ones already applied are skipped, so a
+    // batch can safely be replayed; a missing record stops the replay with
+    // everything before it applied. Returns how many were applied.
+    pub fn apply_op_log<'a, I>(&mut self, records: I) -> Result<usize, Gap>
+    where
+        I: IntoIterator<Item = &'a Record<T>>,
+        T: 'a,
+    {
+        let mut applied = 0;
+        for record in records {
+            if record.seq <= self.applied {
+                continue;
+            }
+            if record.seq != self.applied + 1 {
+                return Err(Gap {
+                    expected: self.applied + 1,
+                    found: record.seq,
+                });
+            }
+            match record.op {
+                Op::Upsert(ref name, ref value) => {
+                    self.hash.upsert(name, value.clone());
+                }
+                Op::Remove(ref name) => {
+                    self.hash.remove(name);
+                }
+            }
+            self.applied = record.seq;
+            applied += 1;
+        }
+        Ok(applied)
+    }
+}
+
+impl<T> Default for Follower<T>
+where
+    T: Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[test]
+fn test_replication() {
+    let mut primary = LoggedHash::new();
+    let mut follower = Follower::new();
+    primary.upsert("a", 1);
+    primary.upsert("b", 2);
+    primary.remove("zzz");
+    assert_eq!(primary.seq(), 2);
+
+    let records = primary.records_since(follower.applied()).unwrap();
+    assert_eq!(follower.apply_op_log(records), Ok(2));
+    primary.upsert("a", 10);
+    primary.remove("b");
+    // Replaying the whole log only applies what's new
+    let records = primary.records_since(0).unwrap();
+    assert_eq!(follower.apply_op_log(records), Ok(2));
+    let copy: Vec<_> = follower.inner().iter_stable().collect();
+    assert_eq!(copy, primary.inner().iter_stable().collect::<Vec<_>>());
+    assert_eq!(follower.applied(), 4);
+
+    primary.truncate(3);
+    assert_eq!(primary.records_since(2), None);
+    assert_eq!(primary.records_since(3).unwrap().len(), 1);
+    assert!(primary.records_since(4).unwrap().is_empty());
+
+    let mut behind = Follower::new();
+    let gap = Gap {
+        expected: 1,
+        found: 4,
+    };
+    assert_eq!(behind.apply_op_log(primary.records_since(3).unwrap()), Err(gap));
+    assert!(behind.inner().is_empty());
+}
+
+#[test]
+fn test_record_encoding() {
+    let records = vec![
+        Record {
+            seq: 1,
+            op: Op::Upsert("héllo".to_string(), "wörld".to_string()),
+        },
+        Record {
+            seq: 2,
+            op: Op::Upsert(String::new(), String::new()),
+        },
+        Record {
+            seq: 3,
+            op: Op::Remove("héllo".to_string()),
+        },
+    ];
+    let mut bytes = Vec::new();
+    for record in &records {
+        record.write_to(&mut bytes).unwrap();
+    }
+
+    if let Ok(len) = usize::try_from(1u64 << 32) {
+        let e = length_prefix(len).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+    }
+    assert_eq!(length_prefix(u32::MAX as usize).unwrap(), [0xff; 4]);
+
+    let mut reader = &bytes[..];
+    let mut decoded = Vec::new();
+    while let Some(record) = Record::<String>::read_from(&mut reader).unwrap() {
+        decoded.push(record);
+    }
+    assert_eq!(decoded, records);
+
+    // A record cut short is an error, not the end of the stream
+    let mut short = &bytes[..bytes.len() - 1];
+    for _ in 0..2 {
+        assert!(Record::<String>::read_from(&mut short).unwrap().is_some());
+    }
+    assert!(Record::<String>::read_from(&mut short).is_err());
+    assert!(Record::<String>::read_from(&mut &b"X\0\0\0\0\0\0\0\x01\0\0\0\0"[..]).is_err());
+}
@@ -0,0 +1,156 @@
+// A table that also remembers the order keys were first inserted in, so the
+// oldest and newest entries are cheap to get at: a bounded journal can look
+// at first() to decide what expires next. Every key gets a sequence number
+// when it is inserted; an index from sequence numbers back to keys gives
+// the order. Overwriting a key keeps its place.
+
+use std::collections::BTreeMap;
+
+use error;
+use hash::Hash;
+
+pub struct OrderedHash<T: Clone> {
+    // Each value with the sequence number of its key
+    hash: Hash<(u64, T)>,
+    order: BTreeMap<u64, String>,
+    next: u64,
+}
+
+impl<T> OrderedHash<T>
+where
+    T: Clone,
+{
+    pub fn new() -> Self {
+        OrderedHash::from_table(Hash::new())
+    }
+
+    // Keeps its entries in `hash`, which must be empty
+    pub(crate) fn from_table(hash: Hash<(u64, T)>) -> Self {
+        OrderedHash {
+            hash,
+            order: BTreeMap::new(),
+            next: 0,
+        }
+    }
+
+    pub(crate) fn check_key(&self, name: &str) -> Result<(), error::Error> {
+        self.hash.check_key(name)
+    }
+
+    pub fn len(&self) -> usize {
+        self.hash.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hash.is_empty()
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<&T> {
+        self.hash.lookup(name).map(|entry| &entry.1)
+    }
+
+    pub fn lookup_mut(&mut self, name: &str) -> Option<&mut T> {
+        self.hash.lookup_mut(name).map(|entry| &mut entry.1)
+    }
+
+    // Returns true if the insert was a new key, which then becomes the
+    // newest. Overwriting a key doesn't move it.
+    pub fn upsert(&mut self, name: &str, value: T) -> bool {
+        if let Some(old) = self.lookup_mut(name) {
+            *old = value;
+            return false;
+        }
+
+        self.hash.upsert(name, (self.next, value));
+        self.order.insert(self.next, name.to_string());
+        self.next += 1;
+        true
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<T> {
+        let (seq, value) = self.hash.remove(name)?;
+        self.order.remove(&seq);
+        Some(value)
+    }
+
+    fn entry<'a>(&'a self, name: &'a str) -> (&'a str, &'a T) {
+        (name, self.lookup(name).unwrap())
+    }
+
+    // The entry inserted longest ago
+    pub fn first(&self) -> Option<(&str, &T)> {
+        self.order.values().next().map(|name| self.entry(name))
+    }
+
+    // The entry inserted most recently
+    pub fn last(&self) -> Option<(&str, &T)> {
+        self.order.values().next_back().map(|name| self.entry(name))
+    }
+
+    // Removes and returns the oldest entry
+    pub fn pop_first(&mut self) -> Option<(String, T)> {
+        let (_, name) = self.order.pop_first()?;
+        let (_, value) = self.hash.remove(&name).unwrap();
+        Some((name, value))
+    }
+
+    // Removes and returns the newest entry
+    pub fn pop_last(&mut self) -> Option<(String, T)> {
+        let (_, name) = self.order.pop_last()?;
+        let (_, value) = self.hash.remove(&name).unwrap();
+        Some((name, value))
+    }
+
+    // Every entry, oldest first
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &T)> {
+        self.order.values().map(move |name| self.entry(name))
+    }
+}
+
+impl<T> Default for OrderedHash<T>
+where
+    T: Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[test]
+fn test_insertion_order() {
+    let mut journal = OrderedHash::new();
+    assert_eq!(journal.first(), None);
+    for (i, name) in ["c", "a", "d", "b"].iter().enumerate() {
+        assert!(journal.upsert(name, i));
+    }
+    assert_eq!(journal.first(), Some(("c", &0)));
+    assert_eq!(journal.last(), Some(("b", &3)));
+
+    // Overwriting keeps the position; removing and reinserting doesn't
+    assert!(!journal.upsert("c", 10));
+    assert_eq!(journal.first(), Some(("c", &10)));
+    assert_eq!(journal.remove("a"), Some(1));
+    assert!(journal.upsert("a", 11));
+    let order: Vec<&str> = journal.iter().map(|(name, _)| name).collect();
+    assert_eq!(order, vec!["c", "d", "b", "a"]);
+
+    assert_eq!(journal.pop_first(), Some(("c".to_string(), 10)));
+    assert_eq!(journal.pop_last(), Some(("a".to_string(), 11)));
+    assert_eq!(journal.len(), 2);
+    assert_eq!(journal.lookup("c"), None);
+    assert_eq!(journal.first(), Some(("d", &2)));
+}
+
+#[test]
+fn test_bounded_journal() {
+    let mut journal = OrderedHash::new();
+    for i in 0..10_000 {
+        journal.upsert(&format!("record{}", i), i);
+        if journal.len() > 100 {
+            journal.pop_first();
+        }
+    }
+    assert_eq!(journal.len(), 100);
+    assert_eq!(journal.first(), Some(("record9900", &9900)));
+    assert_eq!(journal.last(), Some(("record9999", &9999)));
+}
@@ -0,0 +1,242 @@
+// Linear hashing the way the textbooks lay it out: every bucket is a primary
+// page holding a fixed number of entries, with overflow pages chained off it
+// once it fills up. Allocating an overflow page is what triggers a split
+// (Litwin's uncontrolled splitting), and the bucket split is always the one
+// under the split pointer, not the one that overflowed. Pages are the unit a
+// disk-backed table would read and write.
+
+use std::mem;
+
+use hash::{bucket_index, hash_str, next_split};
+
+const INITIAL_BUCKETS: usize = 4;
+const DEFAULT_PAGE_SIZE: usize = 8;
+
+struct Page<T> {
+    entries: Vec<(String, T)>,
+    overflow: Option<Box<Page<T>>>,
+}
+
+impl<T> Page<T> {
+    fn new(size: usize) -> Self {
+        Page {
+            entries: Vec::with_capacity(size),
+            overflow: None,
+        }
+    }
+}
+
+// Takes the last entry off the end of a chain, dropping the page it came
+// from if that leaves it empty. Only the last page of a chain is ever
+// partly full, so this keeps every other page packed.
+fn take_last<T>(chain: &mut Option<Box<Page<T>>>) -> Option<(String, T)> {
+    let entry = {
+        let page = chain.as_mut()?;
+        if page.overflow.is_some() {
+            take_last(&mut page.overflow)
+        } else {
+            page.entries.pop()
+        }
+    };
+
+    if chain.as_ref().is_some_and(|page| page.entries.is_empty()) {
+        *chain = None;
+    }
+    entry
+}
+
+pub struct PagedHash<T> {
+    buckets: Vec<Page<T>>,
+    page_size: usize,
+    bits: usize,
+    split_bucket: usize,
+    count: usize,
+}
+
+impl<T> PagedHash<T> {
+    pub fn new() -> Self {
+        PagedHash::with_page_size(DEFAULT_PAGE_SIZE)
+    }
+
+    pub fn with_page_size(page_size: usize) -> Self {
+        assert!(page_size > 0, "pages must hold at least one entry");
+
+        PagedHash {
+            buckets: (0..INITIAL_BUCKETS).map(|_| Page::new(page_size)).collect(),
+            page_size,
+            bits: 2, // log_2(INITIAL_BUCKETS)
+            split_bucket: 0,
+            count: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn bucket_count(&self) -> usize {
+        self.buckets.len()
+    }
+
+    // Number of overflow pages across all buckets
+    pub fn overflow_pages(&self) -> usize {
+        let mut pages = 0;
+        for bucket in &self.buckets {
+            let mut page = bucket;
+            while let Some(ref next) = page.overflow {
+                pages += 1;
+                page = next;
+            }
+        }
+        pages
+    }
+
+    fn hash(&self, name: &str) -> usize {
+        bucket_index(hash_str(name), self.bits, self.buckets.len())
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<&T> {
+        let mut page = &self.buckets[self.hash(name)];
+
+        loop {
+            if let Some(entry) = page.entries.iter().find(|e| e.0 == name) {
+                return Some(&entry.1);
+            }
+            page = page.overflow.as_ref()?;
+        }
+    }
+
+    pub fn lookup_mut(&mut self, name: &str) -> Option<&mut T> {
+        let h = self.hash(name);
+        let mut page = &mut self.buckets[h];
+
+        loop {
+            if page.entries.iter().any(|e| e.0 == name) {
+                return page.entries.iter_mut().find(|e| e.0 == name).map(|e| &mut e.1);
+            }
+            page = page.overflow.as_mut()?;
+        }
+    }
+
+    // Returns true if the insert was a new key,
+    // False if we overwrote a key
+    pub fn upsert(&mut self, name: &str, value: T) -> bool {
+        if let Some(old) = self.lookup_mut(name) {
+            *old = value;
+            return false;
+        }
+
+        self.count += 1;
+        if self.place((name.to_string(), value)) {
+            self.split();
+        }
+        true
+    }
+
+    // Appends an entry to its bucket's chain; returns true if that took a
+    // new overflow page
+    fn place(&mut self, entry: (String, T)) -> bool {
+        let h = self.hash(&entry.0);
+        let page_size = self.page_size;
+        let mut page = &mut self.buckets[h];
+
+        while page.entries.len() == page_size {
+            if page.overflow.is_none() {
+                let mut next = Page::new(page_size);
+                next.entries.push(entry);
+                page.overflow = Some(Box::new(next));
+                return true;
+            }
+            page = page.overflow.as_mut().unwrap();
+        }
+
+        page.entries.push(entry);
+        false
+    }
+
+    fn split(&mut self) {
+        let page_size = self.page_size;
+        let orig = mem::replace(&mut self.buckets[self.split_bucket], Page::new(page_size));
+        self.buckets.push(Page::new(page_size));
+
+        let (bits, split_bucket) = next_split(self.bits, self.split_bucket, self.buckets.len());
+        self.bits = bits;
+        self.split_bucket = split_bucket;
+
+        // Redistributing entries never splits again, even if one half of
+        // the old chain still needs overflow pages
+        let mut page = Some(Box::new(orig));
+        while let Some(current) = page {
+            let current = *current;
+            for entry in current.entries {
+                self.place(entry);
+            }
+            page = current.overflow;
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<T> {
+        let h = self.hash(name);
+        let mut page = &mut self.buckets[h];
+
+        loop {
+            if let Some(i) = page.entries.iter().position(|e| e.0 == name) {
+                let (_, value) = page.entries.swap_remove(i);
+                // Fill the hole from the end of the chain so pages stay full
+                if let Some(entry) = take_last(&mut page.overflow) {
+                    page.entries.push(entry);
+                }
+                self.count -= 1;
+                return Some(value);
+            }
+            page = page.overflow.as_mut()?;
+        }
+    }
+}
+
+impl<T> Default for PagedHash<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[test]
+fn test_paged_basics() {
+    let mut table = PagedHash::with_page_size(2);
+    assert!(table.upsert("a", 1));
+    assert!(table.upsert("b", 2));
+    assert!(!table.upsert("a", 10));
+    assert_eq!(table.lookup("a"), Some(&10));
+    assert_eq!(table.remove("b"), Some(2));
+    assert_eq!(table.remove("b"), None);
+    assert_eq!(table.lookup("b"), None);
+    assert_eq!(table.len(), 1);
+}
+
+#[test]
+fn test_paged_many() {
+    let mut table = PagedHash::new();
+    for i in 0..5000 {
+        table.upsert(&i.to_string(), i);
+    }
+
+    assert_eq!(table.len(), 5000);
+    assert!(table.bucket_count() > 5000 / DEFAULT_PAGE_SIZE);
+    assert!(table.overflow_pages() < table.bucket_count());
+    for i in 0..5000 {
+        assert_eq!(table.lookup(&i.to_string()), Some(&i));
+    }
+
+    for i in (0..5000).filter(|i| i % 2 == 0) {
+        assert_eq!(table.remove(&i.to_string()), Some(i));
+    }
+    assert_eq!(table.len(), 2500);
+    for i in 0..5000 {
+        let expected = if i % 2 == 0 { None } else { Some(&i) };
+        assert_eq!(table.lookup(&i.to_string()), expected);
+    }
+}
@@ -0,0 +1,271 @@
+// A read-only table for a key set known up front (keyword tables, static
+// configuration). The builder searches for per-group seeds ("hash and
+// displace") until every key lands in its own slot, so a lookup is two hashes,
+// one index and one key comparison, whatever the keys are.
+
+use std::error::Error;
+use std::fmt;
+
+use hash::{hash_str_seeded, mix64, Hash};
+
+const GROUP_SEED: usize = 0x9e37_79b9;
+// Average keys per group; larger groups make the table of seeds smaller but
+// the search slower
+const GROUP_SIZE: usize = 4;
+const MAX_SEED: usize = 1 << 16;
+
+pub struct PerfectHash<T> {
+    seeds: Vec<usize>,
+    slots: Vec<Option<(String, T)>>,
+    count: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BuildError {
+    DuplicateKey(String),
+    // The seed search gave up; only happens for pathological key sets
+    NoSolution,
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BuildError::DuplicateKey(ref name) => write!(f, "duplicate key `{}`", name),
+            BuildError::NoSolution => write!(f, "no collision-free layout found"),
+        }
+    }
+}
+
+impl Error for BuildError {}
+
+fn group(name: &str, groups: usize) -> usize {
+    hash_str_seeded(name, GROUP_SEED) % groups
+}
+
+fn slot(name: &str, seed: usize, slots: usize) -> usize {
+    (mix64(hash_str_seeded(name, seed)) % slots as u64) as usize
+}
+
+// Finds a seed per group so that all keys get distinct slots, or None
+fn search(names: &[&str], groups: usize, nslots: usize) -> Option<Vec<usize>> {
+    let mut members: Vec<Vec<&str>> = vec![Vec::new(); groups];
+    for name in names {
+        members[group(name, groups)].push(name);
+    }
+
+    // Place the biggest groups while the table is still mostly empty
+    let mut order: Vec<usize> = (0..groups).collect();
+    order.sort_by(|&a, &b| members[b].len().cmp(&members[a].len()));
+
+    let mut taken = vec![false; nslots];
+    let mut seeds = vec![0; groups];
+    let mut candidate = Vec::new();
+
+    for g in order {
+        if members[g].is_empty() {
+            break;
+        }
+
+        let found = (1..MAX_SEED).find(|&seed| {
+            candidate.clear();
+            for name in &members[g] {
+                let s = slot(name, seed, nslots);
+                if taken[s] || candidate.contains(&s) {
+                    return false;
+                }
+                candidate.push(s);
+            }
+            true
+        })?;
+
+        for &s in &candidate {
+            taken[s] = true;
+        }
+        seeds[g] = found;
+    }
+
+    Some(seeds)
+}
+
+impl<T> PerfectHash<T> {
+    pub fn build<S, I>(entries: I) -> Result<Self, BuildError>
+    where
+        S: Into<String>,
+        I: IntoIterator<Item = (S, T)>,
+    {
+        let mut entries: Vec<(String, T)> = entries.into_iter().map(|(k, v)| (k.into(), v)).collect();
+
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        for pair in entries.windows(2) {
+            if pair[0].0 == pair[1].0 {
+                return Err(BuildError::DuplicateKey(pair[0].0.clone()));
+            }
+        }
+
+        let count = entries.len();
+        let groups = (count / GROUP_SIZE).max(1);
+        let names: Vec<&str> = entries.iter().map(|e| &e.0[..]).collect();
+
+        // Start minimal (one slot per key) and loosen up if the search fails
+        let mut nslots = count.max(1);
+        let seeds = loop {
+            if let Some(seeds) = search(&names, groups, nslots) {
+                break seeds;
+            }
+            if nslots > 2 * count.max(1) {
+                return Err(BuildError::NoSolution);
+            }
+            nslots += nslots / 10 + 1;
+        };
+
+        let mut slots: Vec<Option<(String, T)>> = (0..nslots).map(|_| None).collect();
+        for (name, value) in entries {
+            let s = slot(&name, seeds[group(&name, groups)], nslots);
+            slots[s] = Some((name, value));
+        }
+
+        Ok(PerfectHash { seeds, slots, count })
+    }
+
+    // Freezes a table's current contents. Keys in a Hash are unique, so the
+    // only error is BuildError::NoSolution.
+    pub fn from_hash(hash: &Hash<T>) -> Result<Self, BuildError>
+    where
+        T: Clone,
+    {
+        PerfectHash::build(hash.iter().map(|(name, value)| (name, value.clone())))
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<&T> {
+        let seed = self.seeds[group(name, self.seeds.len())];
+
+        match self.slots[slot(name, seed, self.slots.len())] {
+            Some((ref key, ref value)) if key == name => Some(value),
+            _ => None,
+        }
+    }
+}
+
+// A table laid out at compile time by static_hash!, for a key set written
+// out in the source. Built the same way as a PerfectHash, but the hash is
+// computed in 64 bits on every target, since the macro runs on the build
+// machine; the macro crate has its own copy, which must match this one.
+pub struct StaticHash<T: 'static> {
+    seeds: &'static [u64],
+    slots: &'static [Option<(&'static str, T)>],
+    count: usize,
+}
+
+fn static_hash(name: &str, seed: u64) -> u64 {
+    let mut h = seed;
+    for p in name.bytes() {
+        h = h.wrapping_mul(31).wrapping_add(p as u64 ^ seed);
+    }
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    h ^= h >> 33;
+    h
+}
+
+impl<T> StaticHash<T> {
+    // What static_hash! expands to; not meant to be called by hand
+    #[doc(hidden)]
+    pub const fn from_parts(
+        seeds: &'static [u64],
+        slots: &'static [Option<(&'static str, T)>],
+        count: usize,
+    ) -> Self {
+        StaticHash { seeds, slots, count }
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<&T> {
+        let groups = self.seeds.len() as u64;
+        let seed = self.seeds[(static_hash(name, GROUP_SEED as u64) % groups) as usize];
+
+        match self.slots[(static_hash(name, seed) % self.slots.len() as u64) as usize] {
+            Some((key, ref value)) if key == name => Some(value),
+            _ => None,
+        }
+    }
+}
+
+#[test]
+fn test_perfect_keywords() {
+    let keywords = [
+        "as", "break", "const", "continue", "crate", "else", "enum", "extern", "false", "fn", "for",
+        "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return",
+        "self", "Self", "static", "struct", "super", "trait", "true", "type", "unsafe", "use",
+        "where", "while",
+    ];
+    let table = PerfectHash::build(keywords.iter().enumerate().map(|(i, &k)| (k, i))).unwrap();
+
+    assert_eq!(table.len(), keywords.len());
+    for (i, k) in keywords.iter().enumerate() {
+        assert_eq!(table.lookup(k), Some(&i));
+    }
+    assert_eq!(table.lookup("fun"), None);
+    assert_eq!(table.lookup(""), None);
+}
+
+#[test]
+fn test_static_hash() {
+    static KEYWORDS: StaticHash<u32> = static_hash! {
+        "as" => 0, "break" => 1, "const" => 2, "continue" => 3, "crate" => 4, "else" => 5,
+        "enum" => 6, "fn" => 7, "for" => 8, "if" => 9, "impl" => 10, "let" => 11, "loop" => 12,
+        "match" => 13, "mod" => 14, "pub" => 15, "return" => 16, "struct" => 17, "use" => 18,
+        "while" => 19, "tab\there" => 20, r#"quoted "raw""# => 21, "caf\u{e9}" => 1 + 21,
+    };
+    assert_eq!(KEYWORDS.len(), 23);
+    assert_eq!(KEYWORDS.lookup("fn"), Some(&7));
+    assert_eq!(KEYWORDS.lookup("while"), Some(&19));
+    assert_eq!(KEYWORDS.lookup("tab\there"), Some(&20));
+    assert_eq!(KEYWORDS.lookup("quoted \"raw\""), Some(&21));
+    assert_eq!(KEYWORDS.lookup("café"), Some(&22));
+    assert_eq!(KEYWORDS.lookup("fun"), None);
+    assert_eq!(KEYWORDS.lookup(""), None);
+
+    const EMPTY: StaticHash<&str> = static_hash! {};
+    assert!(EMPTY.is_empty());
+    assert_eq!(EMPTY.lookup("anything"), None);
+    let names = static_hash! { "x" => "ex", "y" => "why" };
+    assert_eq!(names.lookup("y"), Some(&"why"));
+}
+
+#[test]
+fn test_perfect_from_hash() {
+    let mut hash = Hash::new();
+    for i in 0..3000 {
+        hash.upsert(&format!("key{}", i), i);
+    }
+
+    let table = PerfectHash::from_hash(&hash).unwrap();
+    assert_eq!(table.len(), 3000);
+    for i in 0..3000 {
+        assert_eq!(table.lookup(&format!("key{}", i)), Some(&i));
+    }
+
+    let empty: PerfectHash<()> = PerfectHash::build(Vec::<(String, ())>::new()).unwrap();
+    assert_eq!(empty.lookup("anything"), None);
+    assert_eq!(
+        PerfectHash::build(vec![("a", 1), ("a", 2)]).err(),
+        Some(BuildError::DuplicateKey("a".to_string()))
+    );
+}
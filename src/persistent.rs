@@ -0,0 +1,354 @@
+// An immutable hash array mapped trie. insert() and remove() return a new
+// handle and leave the old one untouched; the two share every node off the
+// modified path, so keeping old versions around (undo stacks, snapshots of
+// state) costs O(log n) per change.
+
+use std::sync::Arc;
+
+use hash::{hash_str, mix64};
+
+const BITS: u32 = 5;
+const MASK: u64 = (1 << BITS) - 1;
+
+enum Node<T> {
+    // A 32-way node; bit i of the bitmap says whether child slot i exists,
+    // and children holds only the existing ones, in slot order.
+    Branch {
+        bitmap: u32,
+        children: Vec<Arc<Node<T>>>,
+    },
+    // Keys whose full 64-bit hashes are identical
+    Leaf {
+        hash: u64,
+        entries: Vec<(String, T)>,
+    },
+}
+
+pub struct PersistentHash<T> {
+    root: Option<Arc<Node<T>>>,
+    count: usize,
+}
+
+fn key_hash(name: &str) -> u64 {
+    mix64(hash_str(name))
+}
+
+fn slot(hash: u64, shift: u32) -> u32 {
+    ((hash >> shift) & MASK) as u32
+}
+
+// Position of slot `bit` among the children present in `bitmap`
+fn position(bitmap: u32, bit: u32) -> usize {
+    (bitmap & (bit - 1)).count_ones() as usize
+}
+
+impl<T> Node<T> {
+    fn is_leaf(&self) -> bool {
+        match *self {
+            Node::Leaf { .. } => true,
+            Node::Branch { .. } => false,
+        }
+    }
+
+    fn leaf_hash(&self) -> u64 {
+        match *self {
+            Node::Leaf { hash, .. } => hash,
+            Node::Branch { .. } => unreachable!(),
+        }
+    }
+}
+
+// Builds the smallest subtree holding two leaves with different hashes
+fn join<T>(shift: u32, a: Arc<Node<T>>, b: Arc<Node<T>>) -> Node<T> {
+    let (sa, sb) = (slot(a.leaf_hash(), shift), slot(b.leaf_hash(), shift));
+
+    if sa == sb {
+        Node::Branch {
+            bitmap: 1 << sa,
+            children: vec![Arc::new(join(shift + BITS, a, b))],
+        }
+    } else {
+        let children = if sa < sb { vec![a, b] } else { vec![b, a] };
+        Node::Branch {
+            bitmap: (1 << sa) | (1 << sb),
+            children,
+        }
+    }
+}
+
+fn lookup<'a, T>(mut node: &'a Node<T>, hash: u64, name: &str) -> Option<&'a T> {
+    let mut shift = 0;
+
+    loop {
+        match *node {
+            Node::Branch {
+                bitmap,
+                ref children,
+            } => {
+                let bit = 1 << slot(hash, shift);
+                if bitmap & bit == 0 {
+                    return None;
+                }
+                node = &children[position(bitmap, bit)];
+                shift += BITS;
+            }
+            Node::Leaf {
+                hash: leaf_hash,
+                ref entries,
+            } => {
+                if leaf_hash != hash {
+                    return None;
+                }
+                return entries.iter().find(|e| e.0 == name).map(|e| &e.1);
+            }
+        }
+    }
+}
+
+// Returns the new node and whether the key is new
+fn insert<T: Clone>(node: &Arc<Node<T>>, shift: u32, hash: u64, name: &str, value: T) -> (Node<T>, bool) {
+    match **node {
+        Node::Branch {
+            bitmap,
+            ref children,
+        } => {
+            let bit = 1 << slot(hash, shift);
+            let pos = position(bitmap, bit);
+            let mut children = children.clone();
+
+            let added = if bitmap & bit == 0 {
+                let leaf = Node::Leaf {
+                    hash,
+                    entries: vec![(name.to_string(), value)],
+                };
+                children.insert(pos, Arc::new(leaf));
+                true
+            } else {
+                let (child, added) = insert(&children[pos], shift + BITS, hash, name, value);
+                children[pos] = Arc::new(child);
+                added
+            };
+
+            (
+                Node::Branch {
+                    bitmap: bitmap | bit,
+                    children,
+                },
+                added,
+            )
+        }
+        Node::Leaf {
+            hash: leaf_hash,
+            ref entries,
+        } if leaf_hash == hash => {
+            let mut entries = entries.clone();
+            let added = match entries.iter().position(|e| e.0 == name) {
+                Some(i) => {
+                    entries[i].1 = value;
+                    false
+                }
+                None => {
+                    entries.push((name.to_string(), value));
+                    true
+                }
+            };
+
+            (Node::Leaf { hash, entries }, added)
+        }
+        Node::Leaf { .. } => {
+            let leaf = Node::Leaf {
+                hash,
+                entries: vec![(name.to_string(), value)],
+            };
+            (join(shift, node.clone(), Arc::new(leaf)), true)
+        }
+    }
+}
+
+enum Removed<T> {
+    NotFound,
+    // The subtree without the key, or None if nothing is left of it
+    Removed(Option<Arc<Node<T>>>, T),
+}
+
+fn remove<T: Clone>(node: &Arc<Node<T>>, shift: u32, hash: u64, name: &str) -> Removed<T> {
+    match **node {
+        Node::Branch {
+            bitmap,
+            ref children,
+        } => {
+            let bit = 1 << slot(hash, shift);
+            if bitmap & bit == 0 {
+                return Removed::NotFound;
+            }
+            let pos = position(bitmap, bit);
+
+            let (child, value) = match remove(&children[pos], shift + BITS, hash, name) {
+                Removed::NotFound => return Removed::NotFound,
+                Removed::Removed(child, value) => (child, value),
+            };
+
+            let mut children = children.clone();
+            let bitmap = match child {
+                Some(child) => {
+                    children[pos] = child;
+                    bitmap
+                }
+                None => {
+                    children.remove(pos);
+                    bitmap & !bit
+                }
+            };
+
+            // Collapse branches that are down to one leaf, so the trie stays
+            // as shallow as a fresh build would make it
+            let node = match children.len() {
+                0 => None,
+                1 if children[0].is_leaf() => Some(children[0].clone()),
+                _ => Some(Arc::new(Node::Branch { bitmap, children })),
+            };
+            Removed::Removed(node, value)
+        }
+        Node::Leaf {
+            hash: leaf_hash,
+            ref entries,
+        } => {
+            if leaf_hash != hash {
+                return Removed::NotFound;
+            }
+            let i = match entries.iter().position(|e| e.0 == name) {
+                Some(i) => i,
+                None => return Removed::NotFound,
+            };
+
+            let mut entries = entries.clone();
+            let (_, value) = entries.remove(i);
+            let node = if entries.is_empty() {
+                None
+            } else {
+                Some(Arc::new(Node::Leaf { hash, entries }))
+            };
+            Removed::Removed(node, value)
+        }
+    }
+}
+
+impl<T> PersistentHash<T>
+where
+    T: Clone,
+{
+    pub fn new() -> Self {
+        PersistentHash {
+            root: None,
+            count: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<&T> {
+        lookup(self.root.as_ref()?, key_hash(name), name)
+    }
+
+    // A new version of the table with `name` set to `value`
+    pub fn insert(&self, name: &str, value: T) -> Self {
+        let hash = key_hash(name);
+
+        match self.root {
+            None => PersistentHash {
+                root: Some(Arc::new(Node::Leaf {
+                    hash,
+                    entries: vec![(name.to_string(), value)],
+                })),
+                count: 1,
+            },
+            Some(ref root) => {
+                let (root, added) = insert(root, 0, hash, name, value);
+                PersistentHash {
+                    root: Some(Arc::new(root)),
+                    count: self.count + added as usize,
+                }
+            }
+        }
+    }
+
+    // A new version of the table without `name`. If the key isn't there,
+    // the new handle simply shares the old root.
+    pub fn remove(&self, name: &str) -> Self {
+        let root = match self.root {
+            Some(ref root) => root,
+            None => return self.clone(),
+        };
+
+        match remove(root, 0, key_hash(name), name) {
+            Removed::NotFound => self.clone(),
+            Removed::Removed(root, _) => PersistentHash {
+                root,
+                count: self.count - 1,
+            },
+        }
+    }
+}
+
+impl<T> Clone for PersistentHash<T> {
+    // Cheap: only the root pointer is copied
+    fn clone(&self) -> Self {
+        PersistentHash {
+            root: self.root.clone(),
+            count: self.count,
+        }
+    }
+}
+
+impl<T> Default for PersistentHash<T>
+where
+    T: Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[test]
+fn test_persistent_versions() {
+    let empty = PersistentHash::new();
+    let v1 = empty.insert("a", 1);
+    let v2 = v1.insert("b", 2);
+    let v3 = v2.insert("a", 10).remove("b");
+
+    assert_eq!(empty.lookup("a"), None);
+    assert_eq!(v1.lookup("a"), Some(&1));
+    assert_eq!(v1.lookup("b"), None);
+    assert_eq!(v2.lookup("b"), Some(&2));
+    assert_eq!(v3.lookup("a"), Some(&10));
+    assert_eq!(v3.lookup("b"), None);
+    assert_eq!((empty.len(), v1.len(), v2.len(), v3.len()), (0, 1, 2, 1));
+    assert_eq!(v3.remove("missing").len(), 1);
+}
+
+#[test]
+fn test_persistent_many() {
+    let mut table = PersistentHash::new();
+    for i in 0..5000 {
+        table = table.insert(&i.to_string(), i);
+    }
+    let full = table.clone();
+
+    for i in (0..5000).filter(|i| i % 3 == 0) {
+        table = table.remove(&i.to_string());
+    }
+
+    assert_eq!(full.len(), 5000);
+    assert_eq!(table.len(), 5000 - 1667);
+    for i in 0..5000 {
+        assert_eq!(full.lookup(&i.to_string()), Some(&i));
+        let expected = if i % 3 == 0 { None } else { Some(&i) };
+        assert_eq!(table.lookup(&i.to_string()), expected);
+    }
+}
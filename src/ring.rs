@@ -0,0 +1,184 @@
+// A consistent hashing ring mapping keys onto a set of named nodes. Each
+// node owns several virtual points on the ring, so adding or removing a node
+// only moves about 1/n of the keys. select_node() offers rendezvous
+// (highest random weight) hashing for callers who'd rather keep no state.
+
+use hash::{hash_str, hash_str_seeded, mix64};
+
+pub const DEFAULT_VNODES: usize = 100;
+
+#[derive(Clone, Debug)]
+pub struct ConsistentHash {
+    vnodes: usize,
+    nodes: Vec<String>,
+    // (position on the ring, index into nodes), sorted by position
+    points: Vec<(u64, usize)>,
+}
+
+fn point(name: &str) -> u64 {
+    mix64(hash_str(name))
+}
+
+impl ConsistentHash {
+    pub fn new() -> Self {
+        ConsistentHash::with_vnodes(DEFAULT_VNODES)
+    }
+
+    pub fn with_vnodes(vnodes: usize) -> Self {
+        ConsistentHash {
+            vnodes: vnodes.max(1),
+            nodes: Vec::new(),
+            points: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn nodes(&self) -> &[String] {
+        &self.nodes
+    }
+
+    // Returns false if the node was already on the ring
+    pub fn add_node(&mut self, node: &str) -> bool {
+        if self.nodes.iter().any(|n| n == node) {
+            return false;
+        }
+
+        let index = self.nodes.len();
+        self.nodes.push(node.to_string());
+        for i in 0..self.vnodes {
+            self.points.push((point(&format!("{}#{}", node, i)), index));
+        }
+        self.points.sort();
+
+        true
+    }
+
+    pub fn remove_node(&mut self, node: &str) -> bool {
+        let index = match self.nodes.iter().position(|n| n == node) {
+            Some(index) => index,
+            None => return false,
+        };
+
+        self.nodes.remove(index);
+        self.points.retain(|&(_, n)| n != index);
+        for p in self.points.iter_mut() {
+            if p.1 > index {
+                p.1 -= 1;
+            }
+        }
+
+        true
+    }
+
+    // The node owning `name`: the first point at or after the key's position,
+    // wrapping around the ring.
+    pub fn node_for(&self, name: &str) -> Option<&str> {
+        if self.points.is_empty() {
+            return None;
+        }
+
+        let h = point(name);
+        let i = match self.points.binary_search_by(|&(p, _)| p.cmp(&h)) {
+            Ok(i) => i,
+            Err(i) if i == self.points.len() => 0,
+            Err(i) => i,
+        };
+
+        Some(&self.nodes[self.points[i].1])
+    }
+}
+
+// Rendezvous hashing: every node gets a pseudo-random weight for the key and
+// the heaviest one wins. Removing a node only moves the keys it owned, and no
+// ring has to be kept in sync with the node list.
+pub fn select_node<'a, S: AsRef<str>>(name: &str, nodes: &'a [S]) -> Option<&'a S> {
+    nodes
+        .iter()
+        .max_by_key(|node| mix64(hash_str_seeded(name, hash_str(node.as_ref()))))
+}
+
+impl Default for ConsistentHash {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[test]
+fn test_ring_basics() {
+    let mut ring = ConsistentHash::new();
+    assert_eq!(ring.node_for("key"), None);
+
+    assert!(ring.add_node("a"));
+    assert!(!ring.add_node("a"));
+    assert_eq!(ring.node_for("key"), Some("a"));
+
+    ring.add_node("b");
+    ring.add_node("c");
+    let counts: Vec<usize> = ["a", "b", "c"]
+        .iter()
+        .map(|n| (0..3000).filter(|i| ring.node_for(&i.to_string()) == Some(n)).count())
+        .collect();
+    for &count in &counts {
+        assert!(count > 600, "unbalanced ring: {:?}", counts);
+    }
+
+    assert!(ring.remove_node("b"));
+    assert!(!ring.remove_node("b"));
+    assert_eq!(ring.len(), 2);
+    assert!((0..100).all(|i| ring.node_for(&i.to_string()) != Some("b")));
+}
+
+#[test]
+fn test_ring_minimal_movement() {
+    let mut ring = ConsistentHash::new();
+    for i in 0..10 {
+        ring.add_node(&format!("node{}", i));
+    }
+
+    let keys: Vec<String> = (0..10000).map(|i| format!("key{}", i)).collect();
+    let before: Vec<String> = keys.iter().map(|k| ring.node_for(k).unwrap().to_string()).collect();
+
+    ring.add_node("node10");
+    let moved = keys
+        .iter()
+        .zip(before.iter())
+        .filter(|&(k, b)| ring.node_for(k) != Some(b))
+        .count();
+
+    // Ideally 1/11 of the keys move, all of them to the new node
+    assert!(moved < 1500, "{} keys moved", moved);
+    assert!(keys
+        .iter()
+        .zip(before.iter())
+        .all(|(k, b)| ring.node_for(k) == Some(b) || ring.node_for(k) == Some("node10")));
+}
+
+#[test]
+fn test_select_node() {
+    let empty: [&str; 0] = [];
+    assert_eq!(select_node("key", &empty), None);
+
+    let nodes = vec!["a".to_string(), "b".to_string(), "c".to_string(), "d".to_string()];
+    let keys: Vec<String> = (0..4000).map(|i| format!("key{}", i)).collect();
+    let before: Vec<&String> = keys.iter().map(|k| select_node(k, &nodes).unwrap()).collect();
+
+    for n in &nodes {
+        let count = before.iter().filter(|&&b| b == n).count();
+        assert!(count > 700, "node {} got {} keys", n, count);
+    }
+
+    // Dropping a node only reassigns that node's keys
+    let fewer = &nodes[..3];
+    for (k, &b) in keys.iter().zip(before.iter()) {
+        if b != "d" {
+            assert_eq!(select_node(k, fewer), Some(b));
+        }
+    }
+}
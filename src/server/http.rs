@@ -0,0 +1,288 @@
+// A small HTTP/1.1 front-end: GET, PUT and DELETE on /keys/{key}, plus
+// GET /stats and a GET /health for load balancers. Only what curl and
+// ordinary clients need is implemented; chunked request bodies are
+// rejected and keys are percent-decoded from the path.
+
+use std::io::{self, BufRead, Write};
+use std::str;
+
+use super::{Item, Store};
+
+const MAX_BODY_LEN: usize = 64 * 1024 * 1024;
+const MAX_HEADERS: usize = 100;
+
+struct Request {
+    method: String,
+    path: String,
+    close: bool,
+    body: Vec<u8>,
+}
+
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn text(status: &'static str, body: &str) -> Self {
+        Response {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: format!("{}\n", body).into_bytes(),
+        }
+    }
+}
+
+fn percent_decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            out.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8(out).ok()
+}
+
+fn read_line(reader: &mut dyn BufRead) -> io::Result<Option<String>> {
+    let mut line = Vec::new();
+    if reader.read_until(b'\n', &mut line)? == 0 {
+        return Ok(None);
+    }
+    while line.last() == Some(&b'\n') || line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    String::from_utf8(line)
+        .map(Some)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "request head is not UTF-8"))
+}
+
+// Reads one request. Ok(Err(response)) means the request was malformed and
+// the response should be sent before closing the connection.
+fn read_request(reader: &mut dyn BufRead) -> io::Result<Option<Result<Request, Response>>> {
+    let request_line = match read_line(reader)? {
+        Some(line) => line,
+        None => return Ok(None),
+    };
+    let mut parts = request_line.split(' ');
+    let (method, path, version) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(path), Some(version)) => (method, path, version),
+        _ => return Ok(Some(Err(Response::text("400 Bad Request", "bad request line")))),
+    };
+
+    let mut close = version == "HTTP/1.0";
+    let mut content_length = 0;
+    let mut headers = 0;
+    loop {
+        let line = match read_line(reader)? {
+            Some(line) => line,
+            None => return Ok(None),
+        };
+        if line.is_empty() {
+            break;
+        }
+        // Past the limit there's no telling where the head ends, so the
+        // connection can't be trusted with another request
+        headers += 1;
+        if headers > MAX_HEADERS {
+            return Ok(Some(Err(Response::text(
+                "431 Request Header Fields Too Large",
+                "too many headers",
+            ))));
+        }
+
+        let (name, value) = match line.find(':') {
+            Some(i) => (line[..i].to_ascii_lowercase(), line[i + 1..].trim()),
+            None => return Ok(Some(Err(Response::text("400 Bad Request", "bad header")))),
+        };
+        match &name[..] {
+            "content-length" => match value.parse() {
+                Ok(len) if len <= MAX_BODY_LEN => content_length = len,
+                _ => return Ok(Some(Err(Response::text("413 Payload Too Large", "body too large")))),
+            },
+            "transfer-encoding" => {
+                return Ok(Some(Err(Response::text(
+                    "501 Not Implemented",
+                    "chunked bodies are not supported",
+                ))))
+            }
+            "connection" => close = value.eq_ignore_ascii_case("close"),
+            _ => {}
+        }
+    }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+
+    Ok(Some(Ok(Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        close,
+        body,
+    })))
+}
+
+fn handle(store: &Store, req: Request) -> Response {
+    if (req.path == "/health" || req.path == "/stats") && req.method != "GET" {
+        return Response::text("405 Method Not Allowed", "method not allowed");
+    }
+    if req.path == "/health" {
+        return Response::text("200 OK", "ok");
+    }
+    if req.path == "/stats" {
+        let stats = store.stats();
+        let body = format!(
+            "{{\"uptime\":{},\"items\":{},\"buckets\":{},\"gets\":{},\"hits\":{},\
+             \"misses\":{},\"hit_ratio\":{:.4},\"sets\":{},\"evictions\":{}}}\n",
+            stats.uptime,
+            stats.items,
+            stats.buckets,
+            stats.gets,
+            stats.hits,
+            stats.misses,
+            stats.hit_ratio(),
+            stats.sets,
+            stats.evictions
+        );
+        return Response {
+            status: "200 OK",
+            content_type: "application/json",
+            body: body.into_bytes(),
+        };
+    }
+
+    if !req.path.starts_with("/keys/") {
+        return Response::text("404 Not Found", "not found");
+    }
+    let name = match percent_decode(&req.path["/keys/".len()..]) {
+        Some(name) => name,
+        None => return Response::text("400 Bad Request", "bad key encoding"),
+    };
+
+    match &req.method[..] {
+        "GET" => match store.get(&name) {
+            Some(item) => Response {
+                status: "200 OK",
+                content_type: "application/octet-stream",
+                body: item.data,
+            },
+            None => Response::text("404 Not Found", "not found"),
+        },
+        "PUT" => {
+            let item = Item {
+                flags: 0,
+                data: req.body,
+            };
+            if store.set(&name, item) {
+                Response::text("201 Created", "created")
+            } else {
+                Response::text("200 OK", "updated")
+            }
+        }
+        "DELETE" => match store.delete(&name) {
+            Some(_) => Response::text("200 OK", "deleted"),
+            None => Response::text("404 Not Found", "not found"),
+        },
+        _ => Response::text("405 Method Not Allowed", "method not allowed"),
+    }
+}
+
+fn write_response(writer: &mut dyn Write, resp: &Response, close: bool) -> io::Result<()> {
+    write!(writer, "HTTP/1.1 {}\r\n", resp.status)?;
+    write!(writer, "Content-Type: {}\r\n", resp.content_type)?;
+    write!(writer, "Content-Length: {}\r\n", resp.body.len())?;
+    if close {
+        writer.write_all(b"Connection: close\r\n")?;
+    }
+    writer.write_all(b"\r\n")?;
+    writer.write_all(&resp.body)?;
+    writer.flush()
+}
+
+pub fn serve(store: &Store, reader: &mut dyn BufRead, writer: &mut dyn Write) -> io::Result<()> {
+    while let Some(req) = read_request(reader)? {
+        match req {
+            Ok(req) => {
+                let close = req.close;
+                let resp = handle(store, req);
+                write_response(writer, &resp, close)?;
+                if close {
+                    return Ok(());
+                }
+            }
+            Err(resp) => return write_response(writer, &resp, true),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+fn session(store: &Store, input: &[u8]) -> String {
+    let mut reader = io::Cursor::new(input.to_vec());
+    let mut out = Vec::new();
+    serve(store, &mut reader, &mut out).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn test_http_keys() {
+    let store = Store::new();
+
+    let out = session(
+        &store,
+        b"PUT /keys/a%20b HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello\
+          GET /keys/a%20b HTTP/1.1\r\n\r\n\
+          DELETE /keys/a%20b HTTP/1.1\r\n\r\n\
+          GET /keys/a%20b HTTP/1.1\r\nConnection: close\r\n\r\n\
+          GET /keys/never-read HTTP/1.1\r\n\r\n",
+    );
+    assert_eq!(
+        out,
+        "HTTP/1.1 201 Created\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: 8\r\n\r\ncreated\n\
+         HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\nContent-Length: 5\r\n\r\nhello\
+         HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: 8\r\n\r\ndeleted\n\
+         HTTP/1.1 404 Not Found\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: 10\r\n\
+         Connection: close\r\n\r\nnot found\n"
+    );
+}
+
+#[test]
+fn test_http_stats_and_errors() {
+    let store = Store::new();
+    store.set("k", Item { flags: 0, data: Vec::new() });
+
+    let out = session(&store, b"GET /stats HTTP/1.0\r\n\r\n");
+    assert!(out.starts_with("HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n"));
+    assert!(out.contains("\"items\":1"));
+    assert!(out.contains("\"buckets\":32,"));
+    assert!(out.contains("\"hit_ratio\":0.0000,"));
+    assert!(out.contains("\"evictions\":0}"));
+    assert!(session(&store, b"GET /health HTTP/1.0\r\n\r\n").ends_with("\r\n\r\nok\n"));
+    assert!(session(&store, b"PUT /health HTTP/1.0\r\n\r\n").starts_with("HTTP/1.1 405"));
+
+    assert!(session(&store, b"GET /nope HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 404"));
+    assert!(session(&store, b"POST /keys/k HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 405"));
+    assert!(session(&store, b"garbage\r\n\r\n").starts_with("HTTP/1.1 400"));
+
+    let mut head = b"GET /health HTTP/1.1\r\n".to_vec();
+    for _ in 0..MAX_HEADERS {
+        head.extend_from_slice(b"X-Padding: 1\r\n");
+    }
+    let ok = session(&store, &[&head[..], b"\r\n"].concat());
+    assert!(ok.ends_with("\r\n\r\nok\n"));
+    // The header past the limit must not be read as the start of a request
+    let over = [&head[..], b"GET /health HTTP/1.1\r\n\r\n"].concat();
+    let out = session(&store, &over);
+    assert!(out.starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"));
+    assert!(out.ends_with("Connection: close\r\n\r\ntoo many headers\n"));
+}
@@ -0,0 +1,172 @@
+// The memcached text protocol: get, set, delete, stats, version and quit.
+// Expiry times are accepted but ignored, since the table has no notion of
+// time. There's no cas, so gets, which only exists to hand out the cas
+// uniques it needs, is an unknown command too.
+
+use std::io::{self, BufRead, Write};
+use std::str;
+
+use super::{Item, Store};
+
+// Same default as memcached's item size limit
+const MAX_ITEM_SIZE: usize = 1024 * 1024;
+const MAX_KEY_LEN: usize = 250;
+
+pub fn serve(store: &Store, reader: &mut dyn BufRead, writer: &mut dyn Write) -> io::Result<()> {
+    let mut line = Vec::new();
+
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            return Ok(());
+        }
+
+        let text = match str::from_utf8(&line) {
+            Ok(text) => text,
+            Err(_) => {
+                writer.write_all(b"CLIENT_ERROR bad command line format\r\n")?;
+                writer.flush()?;
+                continue;
+            }
+        };
+        let args: Vec<&str> = text.split_whitespace().collect();
+
+        match args.first().cloned() {
+            None => writer.write_all(b"ERROR\r\n")?,
+            Some("get") if args.len() > 1 => {
+                for name in &args[1..] {
+                    if let Some(item) = store.get(name) {
+                        write!(writer, "VALUE {} {} {}\r\n", name, item.flags, item.data.len())?;
+                        writer.write_all(&item.data)?;
+                        writer.write_all(b"\r\n")?;
+                    }
+                }
+                writer.write_all(b"END\r\n")?;
+            }
+            Some("set") if args.len() == 5 || args.len() == 6 => {
+                let noreply = args.get(5) == Some(&"noreply");
+                let (flags, bytes) = match (args[2].parse(), args[3].parse::<i64>(), args[4].parse()) {
+                    (Ok(flags), Ok(_), Ok(bytes)) => (flags, bytes),
+                    _ => {
+                        writer.write_all(b"CLIENT_ERROR bad command line format\r\n")?;
+                        writer.flush()?;
+                        continue;
+                    }
+                };
+
+                if bytes > MAX_ITEM_SIZE {
+                    // Draining a data block of any claimed size could take
+                    // forever, so the connection ends here
+                    writer.write_all(b"SERVER_ERROR object too large for cache\r\n")?;
+                    return writer.flush();
+                }
+
+                let mut data = vec![0; bytes + 2];
+                reader.read_exact(&mut data)?;
+                if !data.ends_with(b"\r\n") {
+                    writer.write_all(b"CLIENT_ERROR bad data chunk\r\n")?;
+                    writer.flush()?;
+                    continue;
+                }
+                data.truncate(bytes);
+
+                if args[1].len() > MAX_KEY_LEN {
+                    writer.write_all(b"CLIENT_ERROR key too long\r\n")?;
+                } else {
+                    store.set(args[1], Item { flags, data });
+                    if !noreply {
+                        writer.write_all(b"STORED\r\n")?;
+                    }
+                }
+            }
+            Some("delete") if args.len() == 2 || args.len() == 3 => {
+                let noreply = args.get(2) == Some(&"noreply");
+                let reply: &[u8] = match store.delete(args[1]) {
+                    Some(_) => b"DELETED\r\n",
+                    None => b"NOT_FOUND\r\n",
+                };
+                if !noreply {
+                    writer.write_all(reply)?;
+                }
+            }
+            Some("stats") if args.len() == 1 => {
+                let stats = store.stats();
+                write!(writer, "STAT uptime {}\r\n", stats.uptime)?;
+                write!(writer, "STAT curr_items {}\r\n", stats.items)?;
+                write!(writer, "STAT buckets {}\r\n", stats.buckets)?;
+                write!(writer, "STAT cmd_get {}\r\n", stats.gets)?;
+                write!(writer, "STAT cmd_set {}\r\n", stats.sets)?;
+                write!(writer, "STAT get_hits {}\r\n", stats.hits)?;
+                write!(writer, "STAT get_misses {}\r\n", stats.misses)?;
+                write!(writer, "STAT hit_ratio {:.4}\r\n", stats.hit_ratio())?;
+                write!(writer, "STAT evictions {}\r\n", stats.evictions)?;
+                writer.write_all(b"END\r\n")?;
+            }
+            Some("version") => {
+                write!(writer, "VERSION {}\r\n", env!("CARGO_PKG_VERSION"))?;
+            }
+            Some("quit") => return writer.flush(),
+            Some(_) => writer.write_all(b"ERROR\r\n")?,
+        }
+
+        writer.flush()?;
+    }
+}
+
+#[cfg(test)]
+fn session(store: &Store, input: &[u8]) -> String {
+    let mut reader = io::Cursor::new(input.to_vec());
+    let mut out = Vec::new();
+    serve(store, &mut reader, &mut out).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn test_memcached_commands() {
+    let store = Store::new();
+
+    let out = session(
+        &store,
+        b"set foo 5 0 3\r\nbar\r\nget foo missing\r\ngets foo\r\ndelete foo\r\ndelete foo\r\n\
+          get foo\r\nbogus\r\nquit\r\nget foo\r\n",
+    );
+    assert_eq!(
+        out,
+        "STORED\r\nVALUE foo 5 3\r\nbar\r\nEND\r\nERROR\r\nDELETED\r\nNOT_FOUND\r\nEND\r\n\
+         ERROR\r\n"
+    );
+
+    let stats = store.stats();
+    assert_eq!(stats.gets, 3);
+    assert_eq!(stats.hits, 1);
+    assert_eq!(stats.sets, 1);
+}
+
+#[test]
+fn test_memcached_bad_input() {
+    let store = Store::new();
+
+    let out = session(&store, b"set foo 0 0 3 noreply\r\nbar\r\nset foo 0 0 2\r\nbar\r\nset foo x 0 1\r\n");
+    // The leftover "\n" of the bad chunk reads as an empty command
+    assert_eq!(
+        out,
+        "CLIENT_ERROR bad data chunk\r\nERROR\r\nCLIENT_ERROR bad command line format\r\n"
+    );
+    assert_eq!(store.get("foo").map(|item| item.data), Some(b"bar".to_vec()));
+
+    let huge = format!("set big 0 0 {}\r\nxx\r\nget foo\r\n", u64::MAX);
+    let out = session(&store, huge.as_bytes());
+    assert_eq!(out, "SERVER_ERROR object too large for cache\r\n");
+    assert!(store.get("big").is_none());
+}
+
+#[test]
+fn test_memcached_stats() {
+    let store = Store::new();
+    let out = session(&store, b"set a 0 0 1\r\nx\r\nget a b\r\nstats\r\n");
+    assert!(out.contains("STAT curr_items 1\r\n"));
+    assert!(out.contains("STAT buckets 32\r\n"));
+    assert!(out.contains("STAT hit_ratio 0.5000\r\n"));
+    assert!(out.contains("STAT evictions 0\r\n"));
+    assert!(out.ends_with("END\r\n"));
+}
@@ -0,0 +1,306 @@
+// Network front-ends serving a single shared table. Each protocol lives in
+// its own submodule and only knows how to talk to a `Store`; the accept loop
+// here hands every connection its own thread.
+
+use std::io::{self, BufReader, BufWriter};
+use std::net::{TcpListener, ToSocketAddrs};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Instant;
+
+use hash::{CasError, Hash};
+use oplog::LogValue;
+
+pub mod http;
+pub mod memcached;
+pub mod resp;
+
+// A stored value together with the opaque flags memcached clients attach.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Item {
+    pub flags: u32,
+    pub data: Vec<u8>,
+}
+
+// So a server's table can be replicated through an oplog::LoggedHash: the
+// flags as four big-endian bytes, then the data
+impl LogValue for Item {
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = self.flags.to_be_bytes().to_vec();
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+
+    fn decode(mut bytes: Vec<u8>) -> Option<Self> {
+        if bytes.len() < 4 {
+            return None;
+        }
+        let data = bytes.split_off(4);
+        let flags = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        Some(Item { flags, data })
+    }
+}
+
+pub struct Store {
+    hash: Mutex<Hash<Item>>,
+    started: Instant,
+    gets: AtomicUsize,
+    hits: AtomicUsize,
+    sets: AtomicUsize,
+}
+
+impl Store {
+    pub fn new() -> Self {
+        Store {
+            hash: Mutex::new(Hash::new()),
+            started: Instant::now(),
+            gets: AtomicUsize::new(0),
+            hits: AtomicUsize::new(0),
+            sets: AtomicUsize::new(0),
+        }
+    }
+
+    fn table(&self) -> MutexGuard<'_, Hash<Item>> {
+        // A panicking connection thread can't leave the table half-updated,
+        // so a poisoned lock is still safe to use.
+        self.hash.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn get(&self, name: &str) -> Option<Item> {
+        let item = self.table().lookup(name).cloned();
+
+        self.gets.fetch_add(1, Ordering::Relaxed);
+        if item.is_some() {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        }
+        item
+    }
+
+    pub fn set(&self, name: &str, item: Item) -> bool {
+        self.sets.fetch_add(1, Ordering::Relaxed);
+        self.table().upsert(name, item)
+    }
+
+    // Sets `name` to `item` only if the key is there already or, with
+    // `exists` false, only if it isn't (SET XX and SET NX). The check and
+    // the write happen under one lock. Returns whether it was set.
+    pub fn set_if(&self, name: &str, item: Item, exists: bool) -> bool {
+        let mut table = self.table();
+        if table.lookup(name).is_some() != exists {
+            return false;
+        }
+        self.sets.fetch_add(1, Ordering::Relaxed);
+        table.upsert(name, item);
+        true
+    }
+
+    // Sets `name` to `item` only if it still holds `expected`; the check
+    // and the write happen under one lock
+    pub fn cas(&self, name: &str, expected: &Item, item: Item) -> Result<(), CasError<Item>> {
+        let result = self.table().cas(name, expected, item);
+        if result.is_ok() {
+            self.sets.fetch_add(1, Ordering::Relaxed);
+        }
+        result
+    }
+
+    pub fn delete(&self, name: &str) -> Option<Item> {
+        self.table().remove(name)
+    }
+
+    // Unlike get(), doesn't count towards the hit/miss statistics
+    pub fn exists(&self, name: &str) -> bool {
+        self.table().lookup(name).is_some()
+    }
+
+    // Returns up to `count` keys starting `cursor` entries into the table,
+    // and the cursor to continue from (0 once the scan is complete). Keys may
+    // be skipped or repeated if the table changes between calls.
+    pub fn scan(&self, cursor: usize, count: usize) -> (Vec<String>, usize) {
+        let table = self.table();
+        let keys: Vec<String> = table
+            .iter()
+            .skip(cursor)
+            .take(count)
+            .map(|(name, _)| name.to_string())
+            .collect();
+
+        let next = cursor + keys.len();
+        (keys, if next < table.len() { next } else { 0 })
+    }
+
+    pub fn stats(&self) -> ServerStats {
+        // get() counts the get before the hit, so loading hits first keeps
+        // them at or under gets as long as the loads aren't reordered; the
+        // saturating subtraction covers it when they are
+        let hits = self.hits.load(Ordering::Relaxed);
+        let gets = self.gets.load(Ordering::Relaxed);
+
+        let table = self.table().stats();
+
+        ServerStats {
+            uptime: self.started.elapsed().as_secs(),
+            items: table.entries,
+            buckets: table.buckets,
+            gets,
+            hits,
+            misses: gets.saturating_sub(hits),
+            sets: self.sets.load(Ordering::Relaxed),
+            evictions: 0,
+        }
+    }
+}
+
+impl Default for Store {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ServerStats {
+    pub uptime: u64,
+    pub items: usize,
+    pub buckets: usize,
+    pub gets: usize,
+    pub hits: usize,
+    pub misses: usize,
+    pub sets: usize,
+    // The store never evicts, so this stays 0; it is reported so load
+    // tests written against memcached or Redis find the field they expect
+    pub evictions: usize,
+}
+
+impl ServerStats {
+    // The fraction of gets that found their key; 0 before any get
+    pub fn hit_ratio(&self) -> f64 {
+        if self.gets == 0 {
+            0.0
+        } else {
+            self.hits as f64 / self.gets as f64
+        }
+    }
+}
+
+// A protocol handler: serve one connection until the client goes away.
+pub type Handler = fn(&Store, &mut dyn io::BufRead, &mut dyn io::Write) -> io::Result<()>;
+
+// Accepts connections on `addr` forever, serving each one from its own
+// thread.
+pub fn listen<A: ToSocketAddrs>(addr: A, store: Arc<Store>, handler: Handler) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+        let store = store.clone();
+
+        thread::spawn(move || {
+            let writer = match stream.try_clone() {
+                Ok(writer) => writer,
+                Err(_) => return,
+            };
+            let mut reader = BufReader::new(stream);
+            let mut writer = BufWriter::new(writer);
+            let _ = handler(&store, &mut reader, &mut writer);
+        });
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_store_cas() {
+    let store = Arc::new(Store::new());
+    let counter = |n: u32| Item {
+        flags: 0,
+        data: n.to_string().into_bytes(),
+    };
+    store.set("n", counter(0));
+
+    // Optimistic increments from several threads: read, then retry the
+    // swap until nobody else got there first
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            let store = store.clone();
+            thread::spawn(move || {
+                for _ in 0..100 {
+                    loop {
+                        let current = store.get("n").unwrap();
+                        let n: u32 = String::from_utf8_lossy(&current.data).parse().unwrap();
+                        if store.cas("n", &current, counter(n + 1)).is_ok() {
+                            break;
+                        }
+                    }
+                }
+            })
+        })
+        .collect();
+    for t in threads {
+        t.join().unwrap();
+    }
+
+    assert_eq!(store.get("n"), Some(counter(400)));
+    assert!(store.cas("missing", &counter(0), counter(1)).is_err());
+}
+
+#[test]
+fn test_store_set_if() {
+    let store = Arc::new(Store::new());
+    let item = |n: u8| Item {
+        flags: 0,
+        data: vec![n],
+    };
+    assert!(!store.set_if("k", item(0), true));
+    assert_eq!(store.get("k"), None);
+
+    // Of several clients racing to create the key, only one gets to
+    let threads: Vec<_> = (0..8)
+        .map(|n| {
+            let store = store.clone();
+            thread::spawn(move || store.set_if("k", item(n), false))
+        })
+        .collect();
+    let created = threads.into_iter().map(|t| t.join().unwrap());
+    assert_eq!(created.filter(|&set| set).count(), 1);
+    assert!(store.set_if("k", item(9), true));
+    assert_eq!(store.get("k"), Some(item(9)));
+}
+
+#[test]
+fn test_item_log_value() {
+    let item = Item {
+        flags: 0xdead_beef,
+        data: b"value".to_vec(),
+    };
+    assert_eq!(Item::decode(item.encode()), Some(item));
+    assert_eq!(Item::decode(vec![0; 3]), None);
+}
+
+#[test]
+fn test_store_stats() {
+    let store = Store::new();
+    let item = Item {
+        flags: 0,
+        data: Vec::new(),
+    };
+    assert_eq!(store.stats().hit_ratio(), 0.0);
+    for i in 0..100 {
+        store.set(&i.to_string(), item.clone());
+    }
+    store.get("1");
+    store.get("missing");
+
+    let stats = store.stats();
+    assert_eq!((stats.items, stats.gets, stats.hits, stats.misses), (100, 2, 1, 1));
+    assert_eq!(stats.hit_ratio(), 0.5);
+    assert!(stats.buckets >= 32);
+    assert_eq!(stats.evictions, 0);
+
+    // A hit counted after the gets were read
+    store.hits.fetch_add(1, Ordering::Relaxed);
+    assert_eq!(store.stats().misses, 0);
+}
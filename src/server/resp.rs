@@ -0,0 +1,307 @@
+// A subset of the Redis protocol (RESP2): PING, GET, SET, DEL, EXISTS, SCAN,
+// TTL, INFO and QUIT, enough for ordinary Redis client libraries to talk to the
+// table. Keys never expire, so TTL only tells present keys (-1) from missing
+// ones (-2).
+
+use std::io::{self, BufRead, Read, Write};
+use std::str;
+
+use super::{Item, Store};
+
+const MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+const DEFAULT_SCAN_COUNT: usize = 10;
+
+enum Reply {
+    Status(&'static str),
+    Error(String),
+    Int(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Vec<Reply>),
+}
+
+fn write_reply(writer: &mut dyn Write, reply: &Reply) -> io::Result<()> {
+    match *reply {
+        Reply::Status(s) => write!(writer, "+{}\r\n", s),
+        Reply::Error(ref e) => write!(writer, "-{}\r\n", e),
+        Reply::Int(n) => write!(writer, ":{}\r\n", n),
+        Reply::Bulk(None) => writer.write_all(b"$-1\r\n"),
+        Reply::Bulk(Some(ref data)) => {
+            write!(writer, "${}\r\n", data.len())?;
+            writer.write_all(data)?;
+            writer.write_all(b"\r\n")
+        }
+        Reply::Array(ref items) => {
+            write!(writer, "*{}\r\n", items.len())?;
+            for item in items {
+                write_reply(writer, item)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+fn protocol_error(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+fn read_line(reader: &mut dyn BufRead, line: &mut Vec<u8>) -> io::Result<bool> {
+    line.clear();
+    if reader.read_until(b'\n', line)? == 0 {
+        return Ok(false);
+    }
+    while line.last() == Some(&b'\n') || line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    Ok(true)
+}
+
+fn parse_len(line: &[u8]) -> io::Result<i64> {
+    str::from_utf8(line)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| protocol_error("invalid length"))
+}
+
+// Reads one command, either a RESP array of bulk strings or an inline
+// space-separated line. Returns None at end of stream.
+fn read_command(reader: &mut dyn BufRead) -> io::Result<Option<Vec<Vec<u8>>>> {
+    let mut line = Vec::new();
+
+    if !read_line(reader, &mut line)? {
+        return Ok(None);
+    }
+    if line.first() != Some(&b'*') {
+        let args = line
+            .split(|&b| b == b' ')
+            .filter(|arg| !arg.is_empty())
+            .map(|arg| arg.to_vec())
+            .collect();
+        return Ok(Some(args));
+    }
+
+    let n = parse_len(&line[1..])?;
+    let mut args = Vec::new();
+    for _ in 0..n {
+        if !read_line(reader, &mut line)? || line.first() != Some(&b'$') {
+            return Err(protocol_error("expected bulk string"));
+        }
+        let len = parse_len(&line[1..])?;
+        if len < 0 || len as usize > MAX_BULK_LEN {
+            return Err(protocol_error("invalid bulk length"));
+        }
+
+        // Grows as the bytes arrive, like oplog::read_bytes(), so a bare
+        // header can't make the server allocate the whole length up front
+        let mut data = Vec::new();
+        let want = len as u64 + 2;
+        if reader.take(want).read_to_end(&mut data)? as u64 != want {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        if !data.ends_with(b"\r\n") {
+            return Err(protocol_error("bulk string not terminated"));
+        }
+        data.truncate(len as usize);
+        args.push(data);
+    }
+
+    Ok(Some(args))
+}
+
+fn wrong_args(cmd: &str) -> Reply {
+    Reply::Error(format!("ERR wrong number of arguments for '{}' command", cmd))
+}
+
+fn execute(store: &Store, cmd: &str, args: &[&str]) -> Reply {
+    match (cmd, args.len()) {
+        ("ping", 0) => Reply::Status("PONG"),
+        ("ping", 1) => Reply::Bulk(Some(args[0].as_bytes().to_vec())),
+        ("get", 1) => Reply::Bulk(store.get(args[0]).map(|item| item.data)),
+        ("set", n) if n >= 2 => {
+            let (mut nx, mut xx) = (false, false);
+            for opt in &args[2..] {
+                match &opt.to_ascii_lowercase()[..] {
+                    "nx" => nx = true,
+                    "xx" => xx = true,
+                    _ => return Reply::Error("ERR syntax error".to_string()),
+                }
+            }
+            if nx && xx {
+                return Reply::Error("ERR syntax error".to_string());
+            }
+            let item = Item {
+                flags: 0,
+                data: args[1].as_bytes().to_vec(),
+            };
+            if nx || xx {
+                // Checked and set under one lock, so two clients can't both
+                // pass an NX check
+                if !store.set_if(args[0], item, xx) {
+                    return Reply::Bulk(None);
+                }
+            } else {
+                store.set(args[0], item);
+            }
+            Reply::Status("OK")
+        }
+        ("del", n) if n >= 1 => {
+            Reply::Int(args.iter().filter(|name| store.delete(name).is_some()).count() as i64)
+        }
+        ("exists", n) if n >= 1 => {
+            Reply::Int(args.iter().filter(|name| store.exists(name)).count() as i64)
+        }
+        ("ttl", 1) => Reply::Int(if store.exists(args[0]) { -1 } else { -2 }),
+        ("scan", n) if n == 1 || n == 3 => {
+            let cursor = match args[0].parse() {
+                Ok(cursor) => cursor,
+                Err(_) => return Reply::Error("ERR invalid cursor".to_string()),
+            };
+            let count = match (args.get(1).map(|opt| opt.to_ascii_lowercase()), args.get(2)) {
+                (None, _) => DEFAULT_SCAN_COUNT,
+                (Some(ref opt), Some(count)) if opt == "count" => match count.parse() {
+                    Ok(count) if count > 0 => count,
+                    _ => return Reply::Error("ERR value is not an integer or out of range".to_string()),
+                },
+                _ => return Reply::Error("ERR syntax error".to_string()),
+            };
+
+            let (keys, next) = store.scan(cursor, count);
+            let keys = keys.into_iter().map(|key| Reply::Bulk(Some(key.into_bytes()))).collect();
+            Reply::Array(vec![
+                Reply::Bulk(Some(next.to_string().into_bytes())),
+                Reply::Array(keys),
+            ])
+        }
+        // Only the stats section, in Redis's field names where it has them
+        ("info", n) if n <= 1 => {
+            let stats = store.stats();
+            let info = format!(
+                "# Stats\r\nuptime_in_seconds:{}\r\nkeys:{}\r\nbuckets:{}\r\n\
+                 keyspace_hits:{}\r\nkeyspace_misses:{}\r\nhit_ratio:{:.4}\r\n\
+                 evicted_keys:{}\r\n",
+                stats.uptime,
+                stats.items,
+                stats.buckets,
+                stats.hits,
+                stats.misses,
+                stats.hit_ratio(),
+                stats.evictions
+            );
+            Reply::Bulk(Some(info.into_bytes()))
+        }
+        // Sent by redis-cli on connect; an empty reply is fine
+        ("command", _) => Reply::Array(Vec::new()),
+        ("ping", _) | ("get", _) | ("set", _) | ("del", _) | ("exists", _) | ("ttl", _) | ("scan", _)
+        | ("info", _) => wrong_args(cmd),
+        _ => Reply::Error(format!("ERR unknown command '{}'", cmd)),
+    }
+}
+
+pub fn serve(store: &Store, reader: &mut dyn BufRead, writer: &mut dyn Write) -> io::Result<()> {
+    while let Some(args) = read_command(reader)? {
+        if args.is_empty() {
+            continue;
+        }
+
+        // Keys live in the table as strings, so the arguments must be UTF-8
+        let args: Result<Vec<&str>, _> = args.iter().map(|arg| str::from_utf8(arg)).collect();
+        let reply = match args {
+            Ok(ref args) if args[0].eq_ignore_ascii_case("quit") => {
+                write_reply(writer, &Reply::Status("OK"))?;
+                return writer.flush();
+            }
+            Ok(args) => execute(store, &args[0].to_ascii_lowercase(), &args[1..]),
+            Err(_) => Reply::Error("ERR arguments must be valid UTF-8".to_string()),
+        };
+
+        write_reply(writer, &reply)?;
+        writer.flush()?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+fn session(store: &Store, input: &[u8]) -> String {
+    let mut reader = io::Cursor::new(input.to_vec());
+    let mut out = Vec::new();
+    serve(store, &mut reader, &mut out).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn test_resp_commands() {
+    let store = Store::new();
+
+    let out = session(
+        &store,
+        b"*3\r\n$3\r\nSET\r\n$3\r\nfoo\r\n$3\r\nbar\r\n\
+          *2\r\n$3\r\nGET\r\n$3\r\nfoo\r\n\
+          GET missing\r\n\
+          EXISTS foo missing foo\r\n\
+          TTL foo\r\nTTL missing\r\n\
+          SET foo baz NX\r\nSET other baz XX\r\nSET foo baz NX XX\r\n\
+          SET foo qux XX\r\nGET foo\r\n\
+          DEL foo other\r\n\
+          FLUSHALL\r\nQUIT\r\nPING\r\n",
+    );
+    assert_eq!(
+        out,
+        "+OK\r\n$3\r\nbar\r\n$-1\r\n:2\r\n:-1\r\n:-2\r\n$-1\r\n$-1\r\n-ERR syntax error\r\n\
+         +OK\r\n$3\r\nqux\r\n:1\r\n\
+         -ERR unknown command 'flushall'\r\n+OK\r\n"
+    );
+}
+
+#[test]
+fn test_resp_truncated_bulk() {
+    // A huge length with no data behind it is a short read, not an
+    // allocation of the whole length
+    let mut reader = io::Cursor::new(b"*1\r\n$536870912\r\nabc".to_vec());
+    let e = read_command(&mut reader).unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn test_resp_scan() {
+    let store = Store::new();
+    for i in 0..25 {
+        store.set(&format!("key{}", i), Item { flags: 0, data: Vec::new() });
+    }
+
+    let mut seen = Vec::new();
+    let mut cursor = 0;
+    loop {
+        let args = [cursor.to_string(), "COUNT".to_string(), "7".to_string()];
+        let args: Vec<&str> = args.iter().map(|s| &s[..]).collect();
+        let (next, keys) = match execute(&store, "scan", &args) {
+            Reply::Array(mut reply) => match (reply.remove(0), reply.remove(0)) {
+                (Reply::Bulk(Some(next)), Reply::Array(keys)) => (next, keys),
+                _ => panic!("bad SCAN reply"),
+            },
+            _ => panic!("bad SCAN reply"),
+        };
+
+        seen.extend(keys.into_iter().map(|key| match key {
+            Reply::Bulk(Some(key)) => String::from_utf8(key).unwrap(),
+            _ => panic!("bad SCAN key"),
+        }));
+        cursor = String::from_utf8(next).unwrap().parse().unwrap();
+        if cursor == 0 {
+            break;
+        }
+    }
+
+    seen.sort();
+    seen.dedup();
+    assert_eq!(seen.len(), 25);
+}
+
+#[test]
+fn test_resp_info() {
+    let store = Store::new();
+    let out = session(&store, b"SET a 1\r\nGET a\r\nGET b\r\nINFO\r\nINFO stats extra\r\n");
+    assert!(out.contains("\r\nkeys:1\r\n"));
+    assert!(out.contains("\r\nkeyspace_hits:1\r\nkeyspace_misses:1\r\nhit_ratio:0.5000\r\n"));
+    assert!(out.contains("\r\nevicted_keys:0\r\n"));
+    assert!(out.ends_with("-ERR wrong number of arguments for 'info' command\r\n"));
+}
@@ -0,0 +1,177 @@
+// One logical table split over several inner tables, each behind its own
+// lock, so threads working on keys in different shards don't wait for each
+// other. Keys are routed by their hash, or by a consistent hashing ring when
+// shards correspond to nodes that come and go. The API takes `&self`
+// throughout; shard() hands out a single shard for anything done a shard at
+// a time, like saving it or reading its statistics.
+
+use std::sync::{Mutex, MutexGuard};
+
+use hash::{hash_str, mix64, Hash, Stats};
+use ring::ConsistentHash;
+
+enum Router {
+    // Mixed first, since the inner tables pick buckets from the low bits
+    // of the same hash
+    Modulo,
+    // Shard i belongs to the ring's i-th node
+    Ring(ConsistentHash),
+}
+
+pub struct ShardedHash<T: Clone> {
+    shards: Vec<Mutex<Hash<T>>>,
+    router: Router,
+}
+
+impl<T> ShardedHash<T>
+where
+    T: Clone,
+{
+    pub fn new(shards: usize) -> Self {
+        assert!(shards > 0, "a ShardedHash needs at least one shard");
+        ShardedHash {
+            shards: (0..shards).map(|_| Mutex::new(Hash::new())).collect(),
+            router: Router::Modulo,
+        }
+    }
+
+    // One shard per node on the ring, in the order of ring.nodes()
+    pub fn with_ring(ring: ConsistentHash) -> Self {
+        assert!(!ring.is_empty(), "a ShardedHash needs at least one shard");
+        ShardedHash {
+            shards: ring.nodes().iter().map(|_| Mutex::new(Hash::new())).collect(),
+            router: Router::Ring(ring),
+        }
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    // The index of the shard holding `name`
+    pub fn shard_for(&self, name: &str) -> usize {
+        match self.router {
+            Router::Modulo => (mix64(hash_str(name)) % self.shards.len() as u64) as usize,
+            Router::Ring(ref ring) => {
+                let node = ring.node_for(name).unwrap();
+                ring.nodes().iter().position(|n| n == node).unwrap()
+            }
+        }
+    }
+
+    // Locks shard `i`. Holding one shard's lock never blocks another's.
+    pub fn shard(&self, i: usize) -> MutexGuard<'_, Hash<T>> {
+        // Every change to a shard is a single call into it, so a panic
+        // elsewhere can't have left it half-updated
+        self.shards[i].lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn shard_of(&self, name: &str) -> MutexGuard<'_, Hash<T>> {
+        self.shard(self.shard_for(name))
+    }
+
+    // Adds up the shards one at a time, so under concurrent writes this is
+    // only approximately the size at any one moment
+    pub fn len(&self) -> usize {
+        (0..self.shards.len()).map(|i| self.shard(i).len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        (0..self.shards.len()).all(|i| self.shard(i).is_empty())
+    }
+
+    // A copy, since the value can't be borrowed past the shard's lock
+    pub fn lookup(&self, name: &str) -> Option<T> {
+        self.shard_of(name).lookup(name).cloned()
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.shard_of(name).lookup(name).is_some()
+    }
+
+    // Runs `f` on the value in place, under the shard's lock
+    pub fn update<F, R>(&self, name: &str, f: F) -> Option<R>
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        self.shard_of(name).lookup_mut(name).map(f)
+    }
+
+    pub fn upsert(&self, name: &str, value: T) -> bool {
+        self.shard_of(name).upsert(name, value)
+    }
+
+    pub fn remove(&self, name: &str) -> Option<T> {
+        self.shard_of(name).remove(name)
+    }
+
+    pub fn stats(&self) -> Vec<Stats> {
+        (0..self.shards.len()).map(|i| self.shard(i).stats()).collect()
+    }
+
+    pub fn into_shards(self) -> Vec<Hash<T>> {
+        self.shards
+            .into_iter()
+            .map(|shard| shard.into_inner().unwrap_or_else(|e| e.into_inner()))
+            .collect()
+    }
+}
+
+#[test]
+fn test_sharded() {
+    use std::sync::Arc;
+    use std::thread;
+
+    let table = Arc::new(ShardedHash::new(8));
+    let threads: Vec<_> = (0..4)
+        .map(|t| {
+            let table = table.clone();
+            thread::spawn(move || {
+                for i in 0..1000 {
+                    table.upsert(&format!("{}:{}", t, i), i);
+                }
+            })
+        })
+        .collect();
+    for t in threads {
+        t.join().unwrap();
+    }
+
+    assert_eq!(table.len(), 4000);
+    assert_eq!(table.lookup("3:999"), Some(999));
+    assert_eq!(table.update("3:999", |v| *v += 1), Some(()));
+    assert_eq!(table.remove("3:999"), Some(1000));
+    assert!(!table.contains_key("3:999"));
+
+    // Every key lives in the shard it routes to, and the shards are even
+    let stats = table.stats();
+    assert_eq!(stats.iter().map(|s| s.entries).sum::<usize>(), 3999);
+    assert!(stats.iter().all(|s| s.entries > 350), "{:?}", stats);
+    let table = Arc::try_unwrap(table).ok().unwrap();
+    let routes: Vec<usize> = (0..1000).map(|i| table.shard_for(&format!("0:{}", i))).collect();
+    for (i, shard) in table.into_shards().iter().enumerate() {
+        for (name, _) in shard.iter().filter(|(name, _)| name.starts_with("0:")) {
+            assert_eq!(routes[name[2..].parse::<usize>().unwrap()], i);
+        }
+    }
+}
+
+#[test]
+fn test_sharded_ring() {
+    let mut ring = ConsistentHash::new();
+    for node in &["a", "b", "c"] {
+        ring.add_node(node);
+    }
+    let table = ShardedHash::with_ring(ring.clone());
+    for i in 0..300 {
+        table.upsert(&i.to_string(), i);
+    }
+
+    assert_eq!(table.shard_count(), 3);
+    for i in 0..300 {
+        let name = i.to_string();
+        let node = ring.node_for(&name).unwrap();
+        assert_eq!(ring.nodes()[table.shard_for(&name)], node);
+        assert_eq!(table.shard(table.shard_for(&name)).lookup(&name), Some(&i));
+    }
+}
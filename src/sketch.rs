@@ -0,0 +1,132 @@
+// A count-min sketch for approximate per-key frequencies in fixed memory.
+// Estimates never undercount; they overcount by at most epsilon * total with
+// probability 1 - delta. Useful for spotting hot keys in a stream before
+// promoting them into an exact `Hash`.
+
+use hash::hash_str_seeded;
+
+const SEED_STEP: usize = 0x9e37_79b9;
+
+#[derive(Clone, Debug)]
+pub struct CountMin {
+    width: usize,
+    depth: usize,
+    counts: Vec<u64>,
+    total: u64,
+}
+
+impl CountMin {
+    // `width` counters per row and one row per hash function
+    pub fn new(width: usize, depth: usize) -> Self {
+        let width = width.max(1);
+        let depth = depth.max(1);
+
+        CountMin {
+            width,
+            depth,
+            counts: vec![0; width * depth],
+            total: 0,
+        }
+    }
+
+    // Sizes the sketch so estimates are within epsilon * total of the true
+    // count with probability 1 - delta.
+    pub fn with_error(epsilon: f64, delta: f64) -> Self {
+        assert!(epsilon > 0.0 && delta > 0.0 && delta < 1.0, "bad error bounds");
+
+        let width = (::std::f64::consts::E / epsilon).ceil() as usize;
+        let depth = (1.0 / delta).ln().ceil() as usize;
+        CountMin::new(width, depth)
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    // Total of all counts added so far
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    fn cell(&self, row: usize, name: &str) -> usize {
+        let seed = (row + 1).wrapping_mul(SEED_STEP);
+        row * self.width + hash_str_seeded(name, seed) % self.width
+    }
+
+    pub fn add(&mut self, name: &str) {
+        self.add_n(name, 1);
+    }
+
+    pub fn add_n(&mut self, name: &str, n: u64) {
+        for row in 0..self.depth {
+            let cell = self.cell(row, name);
+            self.counts[cell] = self.counts[cell].saturating_add(n);
+        }
+        self.total = self.total.saturating_add(n);
+    }
+
+    pub fn estimate(&self, name: &str) -> u64 {
+        (0..self.depth)
+            .map(|row| self.counts[self.cell(row, name)])
+            .min()
+            .unwrap_or(0)
+    }
+
+    // Adds another sketch's counts into this one. Both must have the same
+    // dimensions.
+    pub fn merge(&mut self, other: &CountMin) {
+        assert!(
+            self.width == other.width && self.depth == other.depth,
+            "cannot merge sketches of different dimensions"
+        );
+
+        for (count, other) in self.counts.iter_mut().zip(other.counts.iter()) {
+            *count = count.saturating_add(*other);
+        }
+        self.total = self.total.saturating_add(other.total);
+    }
+
+    pub fn clear(&mut self) {
+        for count in self.counts.iter_mut() {
+            *count = 0;
+        }
+        self.total = 0;
+    }
+}
+
+#[test]
+fn test_count_min_estimates() {
+    let mut sketch = CountMin::with_error(0.001, 0.01);
+
+    for i in 0..2000 {
+        sketch.add(&format!("cold{}", i));
+    }
+    sketch.add_n("hot", 500);
+
+    let hot = sketch.estimate("hot");
+    assert!(hot >= 500);
+    assert!(hot <= 500 + (0.001 * sketch.total() as f64) as u64 + 1);
+    assert!(sketch.estimate("cold7") >= 1);
+    assert_eq!(sketch.total(), 2500);
+}
+
+#[test]
+fn test_count_min_merge() {
+    let mut a = CountMin::new(256, 4);
+    let mut b = CountMin::new(256, 4);
+    a.add_n("x", 3);
+    b.add_n("x", 4);
+    b.add("y");
+
+    a.merge(&b);
+    assert!(a.estimate("x") >= 7);
+    assert!(a.estimate("y") >= 1);
+    assert_eq!(a.total(), 8);
+
+    a.clear();
+    assert_eq!(a.estimate("x"), 0);
+}
@@ -0,0 +1,525 @@
+// Generators for tests of code that embeds `Hash`: a small seedable PRNG
+// and tables built in shapes that tend to find bugs. Everything is a pure
+// function of the seed, so a failing case can be replayed from the seed
+// alone.
+
+#[cfg(test)]
+use std::alloc::{GlobalAlloc, Layout, System};
+#[cfg(test)]
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+#[cfg(test)]
+use std::ptr;
+
+use hash::Hash;
+
+// xorshift64*: fast, tiny, and plenty random for generating test cases.
+// Not for anything that needs real unpredictability.
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // A zero state would stay zero forever
+        Rng {
+            state: seed ^ 0x9e37_79b9_7f4a_7c15,
+        }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    // Uniform in 0..n; n must be positive
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    pub fn chance(&mut self, percent: usize) -> bool {
+        self.below(100) < percent
+    }
+}
+
+// Key alphabets, from boring to nasty
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Keys {
+    // Decimal numbers, like most tests use
+    Numeric,
+    // Short keys over a tiny alphabet, so there are many near-duplicates
+    // and shared prefixes
+    Narrow,
+    // Multi-byte UTF-8, including the empty key
+    Unicode,
+}
+
+const NARROW: &[char] = &['a', 'b', '/'];
+const UNICODE: &[char] = &['a', 'é', 'ß', 'ガ', 'ラ', '私', '🦀', ' '];
+
+pub fn key(rng: &mut Rng, keys: Keys) -> String {
+    match keys {
+        Keys::Numeric => rng.below(1 << 20).to_string(),
+        Keys::Narrow => (0..rng.below(6)).map(|_| NARROW[rng.below(NARROW.len())]).collect(),
+        Keys::Unicode => (0..rng.below(8)).map(|_| UNICODE[rng.below(UNICODE.len())]).collect(),
+    }
+}
+
+// Table shapes worth testing against
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shape {
+    Empty,
+    // A few keys, no split yet
+    Small,
+    // Grown through many splits, including ones that completed a round
+    ManySplits,
+    // Grown large and then mostly emptied again, so it has merged buckets
+    Shrunk,
+    // Grown for many keys that were never inserted
+    Reserved,
+}
+
+pub const SHAPES: [Shape; 5] = [
+    Shape::Empty,
+    Shape::Small,
+    Shape::ManySplits,
+    Shape::Shrunk,
+    Shape::Reserved,
+];
+
+// A table of the given shape. Values are the order keys were first
+// inserted in.
+pub fn table(rng: &mut Rng, shape: Shape, keys: Keys) -> Hash<u64> {
+    let mut hash = Hash::new();
+    let fill = |hash: &mut Hash<u64>, rng: &mut Rng, n: usize| {
+        for i in 0..n {
+            hash.upsert(&key(rng, keys), i as u64);
+        }
+    };
+
+    match shape {
+        Shape::Empty => {}
+        Shape::Small => fill(&mut hash, rng, 10),
+        Shape::ManySplits => fill(&mut hash, rng, 3000),
+        Shape::Shrunk => {
+            fill(&mut hash, rng, 3000);
+            let names: Vec<String> = hash.iter().map(|(name, _)| name.to_string()).collect();
+            for name in names.iter().filter(|_| rng.chance(90)) {
+                hash.remove(name);
+            }
+        }
+        Shape::Reserved => {
+            hash.reserve(5000);
+            fill(&mut hash, rng, 100);
+        }
+    }
+    hash
+}
+
+// Applies `ops` random upserts, lookups and removals to `table` and to a
+// std HashMap holding the same entries, panicking at the first operation
+// whose result differs. Ends by comparing the full contents.
+pub fn differential(rng: &mut Rng, table: &mut Hash<u64>, ops: usize, keys: Keys) {
+    let mut model: HashMap<String, u64> =
+        table.iter().map(|(k, &v)| (k.to_string(), v)).collect();
+    // Reuse keys that exist, so lookups and removals mostly hit
+    let mut known: Vec<String> = model.keys().cloned().collect();
+
+    for op in 0..ops {
+        let name = if !known.is_empty() && rng.chance(60) {
+            known[rng.below(known.len())].clone()
+        } else {
+            key(rng, keys)
+        };
+
+        match rng.below(10) {
+            0..=4 => {
+                let value = rng.next_u64();
+                let new = model.insert(name.clone(), value).is_none();
+                assert_eq!(table.upsert(&name, value), new, "op {}: upsert {:?}", op, name);
+                if new {
+                    known.push(name);
+                }
+            }
+            5..=7 => {
+                let expected = model.get(&name);
+                assert_eq!(table.lookup(&name), expected, "op {}: lookup {:?}", op, name);
+            }
+            _ => {
+                let expected = model.remove(&name);
+                assert_eq!(table.remove(&name), expected, "op {}: remove {:?}", op, name);
+            }
+        }
+        assert_eq!(table.len(), model.len(), "op {}: len", op);
+    }
+
+    let mut entries: Vec<(&str, &u64)> = table.iter().collect();
+    entries.sort();
+    let mut expected: Vec<(&str, &u64)> = model.iter().map(|(k, v)| (&k[..], v)).collect();
+    expected.sort();
+    assert_eq!(entries, expected);
+}
+
+// Interprets arbitrary bytes as a table mode followed by a script of
+// operations, checking each against a HashMap. Meant for the fuzz targets,
+// so every byte string is a valid script: the first byte picks the mode,
+// then each operation is an opcode byte, a key length byte and that many
+// key bytes (lossily decoded), plus a value byte for upserts. A truncated
+// operation ends the script.
+pub fn run_script(data: &[u8]) {
+    use hash::SplitPolicy;
+
+    let (&mode, mut rest) = match data.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+    let mut hash = match mode % 4 {
+        0 => Hash::new(),
+        1 => Hash::with_split_policy(SplitPolicy::BucketOverflow),
+        2 => Hash::with_two_choices(),
+        _ => Hash::with_incremental_splits(),
+    };
+    let mut model = HashMap::new();
+
+    while rest.len() >= 2 {
+        let (op, len) = (rest[0], rest[1] as usize % 16);
+        rest = &rest[2..];
+        if rest.len() < len {
+            break;
+        }
+        let name = String::from_utf8_lossy(&rest[..len]).into_owned();
+        rest = &rest[len..];
+
+        match op % 3 {
+            0 => {
+                let value = match rest.split_first() {
+                    Some((&value, tail)) => {
+                        rest = tail;
+                        u64::from(value)
+                    }
+                    None => break,
+                };
+                let new = model.insert(name.clone(), value).is_none();
+                assert_eq!(hash.upsert(&name, value), new);
+            }
+            1 => assert_eq!(hash.lookup(&name), model.get(&name)),
+            _ => assert_eq!(hash.remove(&name), model.remove(&name)),
+        }
+        assert_eq!(hash.len(), model.len());
+    }
+}
+
+// One step of a state-machine test
+#[derive(Clone, Debug, PartialEq)]
+pub enum Op {
+    Upsert(String, u64),
+    Lookup(String),
+    Remove(String),
+    Pop,
+    Reserve(usize),
+    ShrinkToFit,
+    Maintain,
+}
+
+// A random script of `n` operations over keys from `keys`. Keys are drawn
+// from a pool that grows as the script goes on, so later operations mostly
+// touch keys that were inserted earlier.
+pub fn ops(rng: &mut Rng, n: usize, keys: Keys) -> Vec<Op> {
+    let mut pool: Vec<String> = Vec::new();
+    (0..n)
+        .map(|_| {
+            let name = if !pool.is_empty() && rng.chance(70) {
+                pool[rng.below(pool.len())].clone()
+            } else {
+                let name = key(rng, keys);
+                pool.push(name.clone());
+                name
+            };
+            match rng.below(100) {
+                0..=49 => Op::Upsert(name, rng.next_u64()),
+                50..=69 => Op::Lookup(name),
+                70..=95 => Op::Remove(name),
+                96 => Op::Pop,
+                97 => Op::Reserve(rng.below(2000)),
+                98 => Op::ShrinkToFit,
+                _ => Op::Maintain,
+            }
+        })
+        .collect()
+}
+
+// Applies `ops` to a fresh table from `make` and to a HashMap model,
+// checking results against the model and the table's internal invariants
+// after every step. A panic inside the table counts as a failure.
+pub fn run_ops(make: fn() -> Hash<u64>, ops: &[Op]) -> Result<(), String> {
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut hash = make();
+        let mut model = HashMap::new();
+        for (step, op) in ops.iter().enumerate() {
+            let fail = |what: String| Err(format!("step {} ({:?}): {}", step, op, what));
+            match *op {
+                Op::Upsert(ref name, value) => {
+                    let new = model.insert(name.clone(), value).is_none();
+                    if hash.upsert(name, value) != new {
+                        return fail(format!("upsert should return {}", new));
+                    }
+                }
+                Op::Lookup(ref name) => {
+                    if hash.lookup(name) != model.get(name) {
+                        return fail(format!("found {:?}", hash.lookup(name)));
+                    }
+                }
+                Op::Remove(ref name) => {
+                    let removed = hash.remove(name);
+                    if removed != model.remove(name) {
+                        return fail(format!("removed {:?}", removed));
+                    }
+                }
+                // Any entry may come out, but it has to be one that was there
+                Op::Pop => match hash.pop() {
+                    Some((name, value)) if model.remove(&name) != Some(value) => {
+                        return fail(format!("popped {:?} = {}", name, value));
+                    }
+                    None if !model.is_empty() => return fail("popped nothing".to_string()),
+                    _ => {}
+                },
+                Op::Reserve(n) => hash.reserve(n),
+                Op::ShrinkToFit => hash.shrink_to_fit(),
+                Op::Maintain => {
+                    hash.maintain();
+                }
+            }
+            if hash.len() != model.len() {
+                return fail(format!("len is {}, expected {}", hash.len(), model.len()));
+            }
+            if let Err(e) = hash.check_invariants() {
+                return fail(e);
+            }
+        }
+        Ok(())
+    }));
+
+    result.unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<String>()
+            .cloned()
+            .or_else(|| payload.downcast_ref::<&str>().map(|s| s.to_string()));
+        Err(format!("panicked: {}", message.unwrap_or_default()))
+    })
+}
+
+// Shrinks a failing script: repeatedly drops chunks of operations, then
+// single ones, keeping any smaller script that still fails. The result
+// fails, and removing any one operation from it makes it pass.
+pub fn shrink<F>(mut ops: Vec<Op>, fails: F) -> Vec<Op>
+where
+    F: Fn(&[Op]) -> bool,
+{
+    let mut chunk = ops.len() / 2;
+    while chunk > 0 {
+        let mut start = 0;
+        while start < ops.len() {
+            let mut candidate = ops.clone();
+            candidate.drain(start..(start + chunk).min(ops.len()));
+            if fails(&candidate) {
+                ops = candidate;
+            } else {
+                start += chunk;
+            }
+        }
+        chunk /= 2;
+    }
+    ops
+}
+
+// Runs `cases` random scripts of up to `len` operations against tables from
+// `make`. On failure, panics with the shrunk script and the seed that found
+// it.
+pub fn check_model(make: fn() -> Hash<u64>, seed: u64, cases: usize, len: usize, keys: Keys) {
+    let mut rng = Rng::new(seed);
+    for case in 0..cases {
+        let n = rng.below(len + 1);
+        let script = ops(&mut rng, n, keys);
+        if run_ops(make, &script).is_err() {
+            // Keep the shrinking runs' panic messages out of the output
+            let hook = panic::take_hook();
+            panic::set_hook(Box::new(|_| {}));
+            let script = shrink(script, |ops| run_ops(make, ops).is_err());
+            panic::set_hook(hook);
+            let error = run_ops(make, &script).unwrap_err();
+            panic!("seed {} case {}: {}\nminimal script: {:?}", seed, case, error, script);
+        }
+    }
+}
+
+// The crate's own test binary allocates through this: the system allocator,
+// except that it fails every request made from inside without_memory(), on
+// that thread only. Code that would abort on a failed allocation takes the
+// whole test run down with it.
+#[cfg(test)]
+struct TestAllocator;
+
+#[cfg(test)]
+thread_local! {
+    static OUT_OF_MEMORY: Cell<bool> = const { Cell::new(false) };
+}
+
+#[cfg(test)]
+fn out_of_memory() -> bool {
+    OUT_OF_MEMORY.try_with(Cell::get).unwrap_or(false)
+}
+
+#[cfg(test)]
+unsafe impl GlobalAlloc for TestAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if out_of_memory() {
+            return ptr::null_mut();
+        }
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if out_of_memory() {
+            return ptr::null_mut();
+        }
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[cfg(test)]
+#[global_allocator]
+static ALLOCATOR: TestAllocator = TestAllocator;
+
+// Runs `f` with every allocation on this thread failing
+#[cfg(test)]
+pub(crate) fn without_memory<R, F: FnOnce() -> R>(f: F) -> R {
+    OUT_OF_MEMORY.with(|flag| flag.set(true));
+    let result = f();
+    OUT_OF_MEMORY.with(|flag| flag.set(false));
+    result
+}
+
+#[test]
+fn test_generators() {
+    let mut a = Rng::new(7);
+    let mut b = Rng::new(7);
+    for &shape in &SHAPES {
+        let x = table(&mut a, shape, Keys::Unicode);
+        let y = table(&mut b, shape, Keys::Unicode);
+        let xs: Vec<(&str, &u64)> = x.iter_stable().collect();
+        let ys: Vec<(&str, &u64)> = y.iter_stable().collect();
+        assert_eq!(xs, ys);
+    }
+
+    let mut rng = Rng::new(1);
+    assert!(table(&mut rng, Shape::Empty, Keys::Numeric).is_empty());
+    assert!(table(&mut rng, Shape::ManySplits, Keys::Numeric).bucket_count() > 1000);
+    assert!(table(&mut rng, Shape::Reserved, Keys::Numeric).bucket_count() >= 2500);
+    // Only 1 + 3 + ... + 3^5 narrow keys exist
+    assert!(table(&mut rng, Shape::ManySplits, Keys::Narrow).len() <= 364);
+}
+
+#[test]
+fn test_differential() {
+    use hash::SplitPolicy;
+
+    let modes: [fn() -> Hash<u64>; 6] = [
+        Hash::new,
+        || Hash::with_split_policy(SplitPolicy::BucketOverflow),
+        Hash::with_two_choices,
+        Hash::with_incremental_splits,
+        Hash::with_deferred_splits,
+        || {
+            let mut hash = Hash::new();
+            hash.enable_prefix_index();
+            hash
+        },
+    ];
+
+    for (seed, make) in modes.iter().enumerate() {
+        for &keys in &[Keys::Numeric, Keys::Narrow, Keys::Unicode] {
+            let mut rng = Rng::new(seed as u64);
+            let mut hash = make();
+            differential(&mut rng, &mut hash, 5000, keys);
+        }
+    }
+
+    // Starting from tables that already have history
+    let mut rng = Rng::new(99);
+    for &shape in &SHAPES {
+        let mut hash = table(&mut rng, shape, Keys::Numeric);
+        differential(&mut rng, &mut hash, 2000, Keys::Numeric);
+    }
+}
+
+#[test]
+fn test_run_script() {
+    run_script(&[]);
+    run_script(&[0]);
+    // Truncated key, then truncated value
+    run_script(&[0, 0, 5, b'a']);
+    run_script(&[0, 0, 1, b'a']);
+    // Invalid UTF-8 keys
+    run_script(&[1, 0, 2, 0xff, 0xfe, 1, 2, 1, 0xff, 0xfe]);
+
+    // Random scripts in every mode, long enough to force splits
+    let mut rng = Rng::new(3);
+    for mode in 0..4 {
+        let mut data = vec![mode];
+        data.extend((0..20_000).map(|_| match rng.below(4) {
+            0 => 0,
+            _ => rng.next_u64() as u8 % 4,
+        }));
+        run_script(&data);
+    }
+}
+
+#[test]
+fn test_model() {
+    use hash::SplitPolicy;
+
+    check_model(Hash::new, 1, 20, 1000, Keys::Numeric);
+    check_model(Hash::new, 2, 20, 500, Keys::Narrow);
+    let overflow = || Hash::with_split_policy(SplitPolicy::BucketOverflow);
+    check_model(overflow, 3, 10, 1000, Keys::Unicode);
+    check_model(Hash::with_two_choices, 4, 10, 1000, Keys::Numeric);
+    check_model(Hash::with_incremental_splits, 5, 10, 1000, Keys::Numeric);
+    check_model(Hash::with_deferred_splits, 7, 10, 1000, Keys::Numeric);
+    check_model(
+        || {
+            let mut hash = Hash::new();
+            hash.enable_prefix_index();
+            hash
+        },
+        6,
+        10,
+        1000,
+        Keys::Narrow,
+    );
+}
+
+#[test]
+fn test_shrink() {
+    // Fails whenever "a" is removed after being inserted
+    let fails = |ops: &[Op]| {
+        let inserted = ops.iter().position(|op| *op == Op::Upsert("a".to_string(), 1));
+        inserted.is_some_and(|i| ops[i..].contains(&Op::Remove("a".to_string())))
+    };
+
+    let mut rng = Rng::new(8);
+    let mut script = ops(&mut rng, 200, Keys::Numeric);
+    script.insert(50, Op::Upsert("a".to_string(), 1));
+    script.insert(150, Op::Remove("a".to_string()));
+    assert_eq!(
+        shrink(script, fails),
+        vec![Op::Upsert("a".to_string(), 1), Op::Remove("a".to_string())]
+    );
+}
@@ -0,0 +1,99 @@
+// A byte trie over a table's keys, kept alongside `Hash` when its prefix
+// index is enabled. It only records which keys exist; values stay in the
+// table.
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Trie {
+    children: Vec<(u8, Trie)>,
+    terminal: bool,
+}
+
+impl Trie {
+    pub(crate) fn new() -> Self {
+        Trie::default()
+    }
+
+    fn child(&self, b: u8) -> Option<&Trie> {
+        self.children.iter().find(|c| c.0 == b).map(|c| &c.1)
+    }
+
+    pub(crate) fn insert(&mut self, name: &str) {
+        let mut node = self;
+        for b in name.bytes() {
+            let i = match node.children.iter().position(|c| c.0 == b) {
+                Some(i) => i,
+                None => {
+                    node.children.push((b, Trie::new()));
+                    node.children.len() - 1
+                }
+            };
+            node = &mut node.children[i].1;
+        }
+        node.terminal = true;
+    }
+
+    // Returns true if this node is left with no keys and can be pruned
+    fn remove_bytes(&mut self, bytes: &[u8]) -> bool {
+        match bytes.split_first() {
+            None => self.terminal = false,
+            Some((&b, rest)) => {
+                if let Some(i) = self.children.iter().position(|c| c.0 == b) {
+                    if self.children[i].1.remove_bytes(rest) {
+                        self.children.swap_remove(i);
+                    }
+                }
+            }
+        }
+        !self.terminal && self.children.is_empty()
+    }
+
+    pub(crate) fn remove(&mut self, name: &str) {
+        self.remove_bytes(name.as_bytes());
+    }
+
+    // Every key starting with `prefix`
+    pub(crate) fn with_prefix(&self, prefix: &str) -> Vec<String> {
+        let mut node = self;
+        for b in prefix.bytes() {
+            node = match node.child(b) {
+                Some(child) => child,
+                None => return Vec::new(),
+            };
+        }
+
+        let mut keys = Vec::new();
+        let mut path = prefix.as_bytes().to_vec();
+        node.collect(&mut path, &mut keys);
+        keys
+    }
+
+    fn collect(&self, path: &mut Vec<u8>, keys: &mut Vec<String>) {
+        if self.terminal {
+            // Only whole keys are terminal, so the path is valid UTF-8
+            keys.push(String::from_utf8(path.clone()).unwrap());
+        }
+        for &(b, ref child) in &self.children {
+            path.push(b);
+            child.collect(path, keys);
+            path.pop();
+        }
+    }
+
+    // Length of the longest key that is a prefix of `name`
+    pub(crate) fn longest_prefix(&self, name: &str) -> Option<usize> {
+        let mut node = self;
+        let mut longest = if self.terminal { Some(0) } else { None };
+
+        for (i, b) in name.bytes().enumerate() {
+            node = match node.child(b) {
+                Some(child) => child,
+                None => break,
+            };
+            if node.terminal {
+                longest = Some(i + 1);
+            }
+        }
+
+        longest
+    }
+}
@@ -0,0 +1,151 @@
+// All-or-nothing updates of several keys. A transaction sees the table as
+// it was plus its own writes, which are staged in a buffer and only applied
+// if the closure returns Ok.
+
+use error;
+use hash::Hash;
+
+pub struct Transaction<'a, T: 'a + Clone> {
+    base: &'a Hash<T>,
+    // The staged value for each written key; None for a removal
+    writes: Hash<Option<T>>,
+}
+
+impl<'a, T> Transaction<'a, T>
+where
+    T: Clone,
+{
+    pub fn lookup(&self, name: &str) -> Option<&T> {
+        match self.writes.lookup(name) {
+            Some(staged) => staged.as_ref(),
+            None => self.base.lookup(name),
+        }
+    }
+
+    // Returns true if the key is new, as far as the transaction can see
+    pub fn upsert(&mut self, name: &str, value: T) -> bool {
+        let new = self.lookup(name).is_none();
+        self.writes.upsert(name, Some(value));
+        new
+    }
+
+    // Returns true if there was a key to remove
+    pub fn remove(&mut self, name: &str) -> bool {
+        let existed = self.lookup(name).is_some();
+        self.writes.upsert(name, None);
+        existed
+    }
+}
+
+impl<T> Hash<T>
+where
+    T: Clone,
+{
+    // Runs `f` against a transaction on this table. If it returns Ok, every
+    // write it staged is applied; if it returns Err, none are. None are
+    // either if a staged key is too long for this table: that's an
+    // Error::KeyTooLong, where upsert() would panic halfway through.
+    pub fn transaction<F, R, E>(&mut self, f: F) -> Result<R, E>
+    where
+        F: FnOnce(&mut Transaction<T>) -> Result<R, E>,
+        E: From<error::Error>,
+    {
+        let (writes, result) = {
+            let mut txn = Transaction {
+                base: self,
+                writes: Hash::new(),
+            };
+            let result = f(&mut txn)?;
+            (txn.writes, result)
+        };
+
+        for (name, staged) in writes.iter() {
+            if staged.is_some() {
+                self.check_key(name)?;
+            }
+        }
+
+        for (name, staged) in writes {
+            match staged {
+                Some(value) => {
+                    self.upsert(&name, value);
+                }
+                None => {
+                    self.remove(&name);
+                }
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+#[test]
+fn test_transaction_commit() {
+    let mut accounts = Hash::new();
+    accounts.upsert("alice", 100);
+    accounts.upsert("bob", 20);
+
+    let moved: Result<i32, error::Error> = accounts.transaction(|txn| {
+        let alice = *txn.lookup("alice").unwrap();
+        txn.upsert("alice", alice - 30);
+        txn.upsert("bob", txn.lookup("bob").unwrap() + 30);
+        assert!(txn.upsert("carol", 0));
+        assert!(txn.remove("carol"));
+        assert_eq!(txn.lookup("carol"), None);
+        Ok(30)
+    });
+
+    assert_eq!(moved.unwrap(), 30);
+    assert_eq!(accounts.lookup("alice"), Some(&70));
+    assert_eq!(accounts.lookup("bob"), Some(&50));
+    assert_eq!(accounts.lookup("carol"), None);
+}
+
+#[test]
+fn test_transaction_rollback() {
+    #[derive(Debug)]
+    enum Refused {
+        InsufficientFunds,
+        Table(error::Error),
+    }
+
+    impl From<error::Error> for Refused {
+        fn from(e: error::Error) -> Self {
+            Refused::Table(e)
+        }
+    }
+
+    let mut accounts = Hash::with_config(&"max_key_len = 8".parse().unwrap()).unwrap();
+    accounts.upsert("alice", 10);
+
+    let result: Result<(), Refused> = accounts.transaction(|txn| {
+        txn.upsert("alice", 0);
+        txn.upsert("bob", 10);
+        txn.remove("alice");
+        Err(Refused::InsufficientFunds)
+    });
+
+    assert!(matches!(result, Err(Refused::InsufficientFunds)));
+    assert_eq!(accounts.lookup("alice"), Some(&10));
+    assert_eq!(accounts.lookup("bob"), None);
+    assert_eq!(accounts.len(), 1);
+
+    // A key the table won't take fails the commit before anything is applied
+    let result: Result<(), Refused> = accounts.transaction(|txn| {
+        txn.upsert("alice", 0);
+        txn.upsert("bob", 10);
+        txn.upsert("bartholomew", 10);
+        txn.remove("bartholomew-the-second");
+        Ok(())
+    });
+
+    match result {
+        Err(Refused::Table(e)) => {
+            assert_eq!(e.to_string(), "key of 11 bytes is over the limit of 8")
+        }
+        other => panic!("{:?}", other),
+    }
+    assert_eq!(accounts.lookup("alice"), Some(&10));
+    assert_eq!(accounts.len(), 1);
+}
@@ -0,0 +1,155 @@
+// Tables keyed by a type of the caller's own rather than a bare &str. A
+// domain newtype (UserId(String), say) implements KeyLike once, saying how
+// it reads as a key and how to get it back, and a TypedHash<UserId, T> then
+// takes and hands out UserIds, so an OrderId can't be looked up in it by
+// mistake and call sites don't convert by hand. Underneath it's a plain
+// Hash<T> keyed by the string form, and inner() gives that back.
+
+use std::marker::PhantomData;
+
+use hash::Hash;
+
+// The table's keys are strings, so a key type lends out its str form;
+// as_key_bytes() is the same thing as bytes. from_key_str() only ever sees
+// strings as_key_str() produced, and must give back an equal key.
+pub trait KeyLike {
+    fn as_key_str(&self) -> &str;
+
+    fn from_key_str(key: &str) -> Self;
+
+    fn as_key_bytes(&self) -> &[u8] {
+        self.as_key_str().as_bytes()
+    }
+}
+
+impl KeyLike for String {
+    fn as_key_str(&self) -> &str {
+        self
+    }
+
+    fn from_key_str(key: &str) -> Self {
+        key.to_string()
+    }
+}
+
+impl KeyLike for Box<str> {
+    fn as_key_str(&self) -> &str {
+        self
+    }
+
+    fn from_key_str(key: &str) -> Self {
+        key.into()
+    }
+}
+
+pub struct TypedHash<K, T: Clone> {
+    hash: Hash<T>,
+    // fn() -> K so the table is Send and Sync whatever K is; no K is stored
+    key: PhantomData<fn() -> K>,
+}
+
+impl<K, T> TypedHash<K, T>
+where
+    K: KeyLike,
+    T: Clone,
+{
+    pub fn new() -> Self {
+        TypedHash::from_hash(Hash::new())
+    }
+
+    // Every key already in `hash` must be one K::from_key_str() accepts
+    pub fn from_hash(hash: Hash<T>) -> Self {
+        TypedHash {
+            hash,
+            key: PhantomData,
+        }
+    }
+
+    pub fn inner(&self) -> &Hash<T> {
+        &self.hash
+    }
+
+    pub fn into_inner(self) -> Hash<T> {
+        self.hash
+    }
+
+    pub fn len(&self) -> usize {
+        self.hash.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hash.is_empty()
+    }
+
+    pub fn lookup(&self, key: &K) -> Option<&T> {
+        self.hash.lookup(key.as_key_str())
+    }
+
+    pub fn lookup_mut(&mut self, key: &K) -> Option<&mut T> {
+        self.hash.lookup_mut(key.as_key_str())
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.lookup(key).is_some()
+    }
+
+    pub fn upsert(&mut self, key: &K, value: T) -> bool {
+        self.hash.upsert(key.as_key_str(), value)
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<T> {
+        self.hash.remove(key.as_key_str())
+    }
+
+    // Builds each K afresh from its stored string
+    pub fn iter(&self) -> impl Iterator<Item = (K, &T)> {
+        self.hash.iter().map(|(name, value)| (K::from_key_str(name), value))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = K> + '_ {
+        self.hash.iter().map(|(name, _)| K::from_key_str(name))
+    }
+}
+
+impl<K, T> Default for TypedHash<K, T>
+where
+    K: KeyLike,
+    T: Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[test]
+fn test_typed_keys() {
+    #[derive(Debug, PartialEq)]
+    struct UserId(String);
+
+    impl KeyLike for UserId {
+        fn as_key_str(&self) -> &str {
+            &self.0
+        }
+
+        fn from_key_str(key: &str) -> Self {
+            UserId(key.to_string())
+        }
+    }
+
+    let alice = UserId("alice".to_string());
+    let mut ages: TypedHash<UserId, u32> = TypedHash::new();
+    assert!(ages.upsert(&alice, 30));
+    ages.upsert(&UserId("bob".to_string()), 25);
+    *ages.lookup_mut(&alice).unwrap() += 1;
+    assert_eq!(ages.lookup(&alice), Some(&31));
+    assert_eq!(alice.as_key_bytes(), b"alice");
+
+    let mut users: Vec<(UserId, u32)> = ages.iter().map(|(id, &age)| (id, age)).collect();
+    users.sort_by(|a, b| a.0 .0.cmp(&b.0 .0));
+    assert_eq!(users, vec![(UserId("alice".to_string()), 31), (UserId("bob".to_string()), 25)]);
+
+    // The same strings under another key type are the same entries
+    let names: TypedHash<String, u32> = TypedHash::from_hash(ages.into_inner());
+    assert_eq!(names.lookup(&"bob".to_string()), Some(&25));
+    assert_eq!(names.keys().count(), 2);
+}
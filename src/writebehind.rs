@@ -0,0 +1,179 @@
+// A table used as a write buffer in front of slower storage. Writes land in
+// the table straight away and the keys they touched are remembered as
+// dirty; flush() later hands the dirty keys to a sink in batches, so the
+// storage sees one write per key however many times it changed. A batch the
+// sink fails to take stays dirty and goes again at the next flush.
+
+use hash::Hash;
+
+pub struct WriteBehindHash<T: Clone> {
+    hash: Hash<T>,
+    // Keys changed since they were last flushed; the value is unused
+    dirty: Hash<()>,
+}
+
+impl<T> WriteBehindHash<T>
+where
+    T: Clone,
+{
+    pub fn new() -> Self {
+        WriteBehindHash::from_hash(Hash::new())
+    }
+
+    // Starts from a table that's already in storage, so nothing is dirty
+    pub fn from_hash(hash: Hash<T>) -> Self {
+        WriteBehindHash {
+            hash,
+            dirty: Hash::new(),
+        }
+    }
+
+    pub fn inner(&self) -> &Hash<T> {
+        &self.hash
+    }
+
+    pub fn len(&self) -> usize {
+        self.hash.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hash.is_empty()
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<&T> {
+        self.hash.lookup(name)
+    }
+
+    // Marks `name` dirty whether or not the value is then changed
+    pub fn lookup_mut(&mut self, name: &str) -> Option<&mut T> {
+        let value = self.hash.lookup_mut(name)?;
+        self.dirty.upsert(name, ());
+        Some(value)
+    }
+
+    pub fn upsert(&mut self, name: &str, value: T) -> bool {
+        // A key the table won't take panics before it is marked dirty
+        let new = self.hash.upsert(name, value);
+        self.dirty.upsert(name, ());
+        new
+    }
+
+    // The removal is flushed like any other change
+    pub fn remove(&mut self, name: &str) -> Option<T> {
+        let value = self.hash.remove(name)?;
+        self.dirty.upsert(name, ());
+        Some(value)
+    }
+
+    // Number of keys waiting to be flushed
+    pub fn dirty_len(&self) -> usize {
+        self.dirty.len()
+    }
+
+    pub fn is_dirty(&self, name: &str) -> bool {
+        self.dirty.lookup(name).is_some()
+    }
+
+    // Passes the dirty keys to `sink` at most `batch` at a time, each with
+    // its current value, or None if it has been removed. Stops at the first
+    // batch the sink returns an error for, leaving that batch and the rest
+    // dirty. Returns how many keys were flushed.
+    pub fn flush<F, E>(&mut self, batch: usize, mut sink: F) -> Result<usize, E>
+    where
+        F: FnMut(&[(&str, Option<&T>)]) -> Result<(), E>,
+    {
+        assert!(batch > 0, "flush batches must hold at least one key");
+        let names: Vec<String> = self.dirty.iter().map(|(name, _)| name.to_string()).collect();
+
+        let mut flushed = 0;
+        for chunk in names.chunks(batch) {
+            let entries: Vec<(&str, Option<&T>)> = chunk
+                .iter()
+                .map(|name| (&name[..], self.hash.lookup(name)))
+                .collect();
+            sink(&entries)?;
+            for name in chunk {
+                self.dirty.remove(name);
+            }
+            flushed += chunk.len();
+        }
+        Ok(flushed)
+    }
+
+    // Forgets which keys are dirty without flushing them
+    pub fn discard_dirty(&mut self) {
+        self.dirty = Hash::new();
+    }
+}
+
+impl<T> Default for WriteBehindHash<T>
+where
+    T: Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[test]
+fn test_write_behind() {
+    let mut storage = Hash::new();
+    let mut buffer = WriteBehindHash::new();
+    for i in 0..10 {
+        buffer.upsert(&i.to_string(), i);
+    }
+    // Many writes to one key are one write to storage
+    for _ in 0..5 {
+        *buffer.lookup_mut("3").unwrap() += 100;
+    }
+    buffer.remove("9");
+    assert_eq!(buffer.dirty_len(), 10);
+
+    let mut batches = Vec::new();
+    let flushed = buffer.flush(4, |entries| {
+        batches.push(entries.len());
+        for &(name, value) in entries {
+            match value {
+                Some(&value) => storage.upsert(name, value),
+                None => storage.remove(name).is_some(),
+            };
+        }
+        Ok::<(), ()>(())
+    });
+    assert_eq!(flushed, Ok(10));
+    assert_eq!(batches, vec![4, 4, 2]);
+    assert_eq!(buffer.dirty_len(), 0);
+    assert_eq!(storage.len(), 9);
+    assert_eq!(storage.lookup("3"), Some(&503));
+
+    assert_eq!(buffer.flush(4, |_| Err("nothing to flush")), Ok(0));
+    buffer.lookup("1");
+    assert!(!buffer.is_dirty("1"));
+}
+
+#[test]
+fn test_write_behind_failure() {
+    let mut buffer = WriteBehindHash::new();
+    for i in 0..10 {
+        buffer.upsert(&i.to_string(), i);
+    }
+
+    let mut calls = 0;
+    let result = buffer.flush(3, |_| {
+        calls += 1;
+        if calls == 2 {
+            Err("database unavailable")
+        } else {
+            Ok(())
+        }
+    });
+    assert_eq!(result, Err("database unavailable"));
+    // The failed batch and everything after it are still dirty
+    assert_eq!(buffer.dirty_len(), 7);
+    assert_eq!(buffer.flush(3, |_| Ok::<(), ()>(())), Ok(7));
+
+    buffer.upsert("a", 1);
+    buffer.discard_dirty();
+    assert_eq!(buffer.dirty_len(), 0);
+    assert_eq!(buffer.lookup("a"), Some(&1));
+}